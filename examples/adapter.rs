@@ -20,17 +20,15 @@ struct AppState {
 #[tokio::main]
 async fn main() {
     let mut app_state = AppState { devices: HashMap::new() };
-    //let mut adapter = BleDriver::new("/dev/ttyACM0").unwrap();
-    let mut adapter = BleDriver::new("/tmp/ttyV0").unwrap();
-    adapter.open().expect("Error opening port");
-    adapter.gap_set_role_count_config(&GapConfigRoleCount::default()).unwrap();
-    adapter.gatt_set_connection_config(EXAMPLE_TAG, DEFAULT_MTU).unwrap();
-    adapter.ble_enable().unwrap();
-    adapter.gap_scan_start(&GapScanParameters::default()).unwrap();
+    let (adapter, mut events) = BleDriver::open_first_nordic().expect("Error opening port");
+    adapter.gap_set_role_count_config(&GapConfigRoleCount::default()).await.unwrap();
+    adapter.gatt_set_connection_config(EXAMPLE_TAG, DEFAULT_MTU).await.unwrap();
+    adapter.ble_enable().await.unwrap();
+    adapter.gap_scan_start(&GapScanParameters::default()).await.unwrap();
 
-    while let Some(event) = adapter.receive_event().await {
+    while let Some(event) = events.recv().await {
         match event {
-            EventType::BleGap(gap_event) => handle_gap_event(&mut app_state, &gap_event),
+            EventType::BleGap(_, gap_event) => handle_gap_event(&mut app_state, &gap_event),
             _ => println!("Unhandled")
         }
         //println!("{:?}", event);
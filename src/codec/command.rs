@@ -0,0 +1,502 @@
+//! Encoding of the SoftDevice calls made by this crate.
+
+use crate::codec::{Writer, PACKET_TYPE_COMMAND};
+use crate::gap::{GapAddress, GapConfigRoleCount, GapConnectionParameters, GapScanParameters};
+use bytes::Bytes;
+
+/// SVC numbers of the serialized SoftDevice calls.
+pub mod opcode {
+    pub const SD_BLE_ENABLE: u8 = 0x60;
+    pub const SD_BLE_VERSION_GET: u8 = 0x65;
+    pub const SD_BLE_CFG_SET: u8 = 0x69;
+    pub const SD_BLE_GAP_DISCONNECT: u8 = 0x76;
+    pub const SD_BLE_GAP_SCAN_START: u8 = 0x8a;
+    pub const SD_BLE_GAP_SCAN_STOP: u8 = 0x8b;
+    pub const SD_BLE_GAP_CONNECT: u8 = 0x8c;
+    pub const SD_BLE_GAP_CONNECT_CANCEL: u8 = 0x8d;
+    pub const SD_BLE_GATTC_PRIMARY_SERVICES_DISCOVER: u8 = 0x9b;
+    pub const SD_BLE_GATTC_READ: u8 = 0xa1;
+    pub const SD_BLE_GATTC_WRITE: u8 = 0xa3;
+    pub const SD_BLE_GATTS_VALUE_SET: u8 = 0xac;
+    pub const SD_BLE_GATTS_HVX: u8 = 0xae;
+
+    /// Name of the SoftDevice function called with `opcode`.
+    pub fn name(opcode: u8) -> &'static str {
+        match opcode {
+            SD_BLE_ENABLE => "sd_ble_enable",
+            SD_BLE_VERSION_GET => "sd_ble_version_get",
+            SD_BLE_CFG_SET => "sd_ble_cfg_set",
+            SD_BLE_GAP_DISCONNECT => "sd_ble_gap_disconnect",
+            SD_BLE_GAP_SCAN_START => "sd_ble_gap_scan_start",
+            SD_BLE_GAP_SCAN_STOP => "sd_ble_gap_scan_stop",
+            SD_BLE_GAP_CONNECT => "sd_ble_gap_connect",
+            SD_BLE_GAP_CONNECT_CANCEL => "sd_ble_gap_connect_cancel",
+            SD_BLE_GATTC_PRIMARY_SERVICES_DISCOVER => "sd_ble_gattc_primary_services_discover",
+            SD_BLE_GATTC_READ => "sd_ble_gattc_read",
+            SD_BLE_GATTC_WRITE => "sd_ble_gattc_write",
+            SD_BLE_GATTS_VALUE_SET => "sd_ble_gatts_value_set",
+            SD_BLE_GATTS_HVX => "sd_ble_gatts_hvx",
+            _ => "unknown SoftDevice call",
+        }
+    }
+}
+
+/// Configuration IDs accepted by `sd_ble_cfg_set`.
+pub mod cfg_id {
+    pub const BLE_CONN_CFG_GAP: u32 = 0x20;
+    pub const BLE_CONN_CFG_GATTC: u32 = 0x21;
+    pub const BLE_CONN_CFG_GATTS: u32 = 0x22;
+    pub const BLE_CONN_CFG_GATT: u32 = 0x23;
+    pub const BLE_GAP_CFG_ROLE_COUNT: u32 = 0x40;
+}
+
+/// A 16-bit UUID, either Bluetooth SIG assigned or relative to a vendor
+/// specific base identified by `uuid_type`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BleUuid {
+    pub uuid: u16,
+    pub uuid_type: u8,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GattcWriteParameters {
+    /// `BLE_GATT_OP_*` write operation.
+    pub write_op: u8,
+    /// `BLE_GATT_EXEC_WRITE_FLAG_*` flags for prepared writes.
+    pub flags: u8,
+    pub handle: u16,
+    pub offset: u16,
+    pub value: Bytes,
+}
+
+/// The configurations set by `BleDriver::*_set_*_config`.
+#[derive(Debug, Clone)]
+pub enum Config {
+    GapConnection {
+        connection_tag: u8,
+        connection_count: u8,
+        event_length: u16,
+    },
+    GapRoleCount(GapConfigRoleCount),
+    GattConnection {
+        connection_tag: u8,
+        att_mtu: u16,
+    },
+    GattcConnection {
+        connection_tag: u8,
+        write_cmd_tx_queue_size: u8,
+    },
+    GattsConnection {
+        connection_tag: u8,
+        hvn_tx_queue_size: u8,
+    },
+}
+
+impl Config {
+    pub fn cfg_id(&self) -> u32 {
+        match self {
+            Config::GapConnection { .. } => cfg_id::BLE_CONN_CFG_GAP,
+            Config::GapRoleCount(_) => cfg_id::BLE_GAP_CFG_ROLE_COUNT,
+            Config::GattConnection { .. } => cfg_id::BLE_CONN_CFG_GATT,
+            Config::GattcConnection { .. } => cfg_id::BLE_CONN_CFG_GATTC,
+            Config::GattsConnection { .. } => cfg_id::BLE_CONN_CFG_GATTS,
+        }
+    }
+
+    fn encode(&self, writer: &mut Writer) {
+        match self {
+            Config::GapConnection {
+                connection_tag,
+                connection_count,
+                event_length,
+            } => {
+                writer.u8(*connection_tag).u8(*connection_count).u16(*event_length);
+            }
+            Config::GapRoleCount(role_count) => {
+                writer
+                    .u8(role_count.advertising_set_count)
+                    .u8(role_count.peripheral_role_count)
+                    .u8(role_count.central_role_count)
+                    .u8(role_count.central_security_count)
+                    .u8(role_count.qos_channel_survey_role_available & 0x01);
+            }
+            Config::GattConnection {
+                connection_tag,
+                att_mtu,
+            } => {
+                writer.u8(*connection_tag).u16(*att_mtu);
+            }
+            Config::GattcConnection {
+                connection_tag,
+                write_cmd_tx_queue_size,
+            } => {
+                writer.u8(*connection_tag).u8(*write_cmd_tx_queue_size);
+            }
+            Config::GattsConnection {
+                connection_tag,
+                hvn_tx_queue_size,
+            } => {
+                writer.u8(*connection_tag).u8(*hvn_tx_queue_size);
+            }
+        }
+    }
+}
+
+/// A serialized SoftDevice call.
+#[derive(Debug, Clone)]
+pub enum Command {
+    Enable {
+        app_ram_base: Option<u32>,
+    },
+    VersionGet,
+    CfgSet(Config),
+    /// Starts scanning, or resumes it with the previous parameters if
+    /// `parameters` is `None`, reporting into a buffer of `buffer_length` bytes.
+    GapScanStart {
+        parameters: Option<GapScanParameters>,
+        buffer_length: u16,
+    },
+    GapScanStop,
+    GapConnect {
+        peer_address: GapAddress,
+        scan_parameters: GapScanParameters,
+        connection_parameters: GapConnectionParameters,
+        connection_tag: u8,
+    },
+    GapConnectCancel,
+    GapDisconnect {
+        connection_handle: u16,
+        hci_status_code: u8,
+    },
+    GattcPrimaryServicesDiscover {
+        connection_handle: u16,
+        start_handle: u16,
+        uuid: Option<BleUuid>,
+    },
+    GattcRead {
+        connection_handle: u16,
+        handle: u16,
+        offset: u16,
+    },
+    GattcWrite {
+        connection_handle: u16,
+        parameters: GattcWriteParameters,
+    },
+    GattsValueSet {
+        connection_handle: u16,
+        handle: u16,
+        offset: u16,
+        value: Bytes,
+    },
+    GattsHvx {
+        connection_handle: u16,
+        handle: u16,
+        /// `BLE_GATT_HVX_NOTIFICATION` or `BLE_GATT_HVX_INDICATION`.
+        hvx_type: u8,
+        offset: u16,
+        data: Bytes,
+    },
+}
+
+impl Command {
+    pub fn opcode(&self) -> u8 {
+        match self {
+            Command::Enable { .. } => opcode::SD_BLE_ENABLE,
+            Command::VersionGet => opcode::SD_BLE_VERSION_GET,
+            Command::CfgSet(_) => opcode::SD_BLE_CFG_SET,
+            Command::GapScanStart { .. } => opcode::SD_BLE_GAP_SCAN_START,
+            Command::GapScanStop => opcode::SD_BLE_GAP_SCAN_STOP,
+            Command::GapConnect { .. } => opcode::SD_BLE_GAP_CONNECT,
+            Command::GapConnectCancel => opcode::SD_BLE_GAP_CONNECT_CANCEL,
+            Command::GapDisconnect { .. } => opcode::SD_BLE_GAP_DISCONNECT,
+            Command::GattcPrimaryServicesDiscover { .. } => {
+                opcode::SD_BLE_GATTC_PRIMARY_SERVICES_DISCOVER
+            }
+            Command::GattcRead { .. } => opcode::SD_BLE_GATTC_READ,
+            Command::GattcWrite { .. } => opcode::SD_BLE_GATTC_WRITE,
+            Command::GattsValueSet { .. } => opcode::SD_BLE_GATTS_VALUE_SET,
+            Command::GattsHvx { .. } => opcode::SD_BLE_GATTS_HVX,
+        }
+    }
+
+    /// The connection the command applies to, if any.
+    pub fn connection_handle(&self) -> Option<u16> {
+        match self {
+            Command::GapDisconnect { connection_handle, .. }
+            | Command::GattcPrimaryServicesDiscover { connection_handle, .. }
+            | Command::GattcRead { connection_handle, .. }
+            | Command::GattcWrite { connection_handle, .. }
+            | Command::GattsValueSet { connection_handle, .. }
+            | Command::GattsHvx { connection_handle, .. } => Some(*connection_handle),
+            _ => None,
+        }
+    }
+
+    /// Encodes the command, including its leading packet type byte.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut writer = Writer::new();
+        writer.u8(PACKET_TYPE_COMMAND).u8(self.opcode());
+
+        match self {
+            Command::Enable { app_ram_base } => {
+                writer.present(app_ram_base.is_some());
+            }
+            Command::VersionGet => {
+                writer.present(true);
+            }
+            Command::CfgSet(config) => {
+                writer.u32(config.cfg_id()).present(true);
+                config.encode(&mut writer);
+            }
+            Command::GapScanStart {
+                parameters,
+                buffer_length,
+            } => {
+                writer.present(parameters.is_some());
+                if let Some(parameters) = parameters {
+                    encode_scan_parameters(&mut writer, parameters);
+                }
+                // Only the size of the report buffer is sent, it is filled
+                // on the connectivity side.
+                writer.present(true).u16(*buffer_length).present(true);
+            }
+            Command::GapScanStop | Command::GapConnectCancel => {}
+            Command::GapConnect {
+                peer_address,
+                scan_parameters,
+                connection_parameters,
+                connection_tag,
+            } => {
+                writer.present(true);
+                encode_address(&mut writer, peer_address);
+                writer.present(true);
+                encode_scan_parameters(&mut writer, scan_parameters);
+                writer.present(true);
+                encode_connection_parameters(&mut writer, connection_parameters);
+                writer.u8(*connection_tag);
+            }
+            Command::GapDisconnect {
+                connection_handle,
+                hci_status_code,
+            } => {
+                writer.u16(*connection_handle).u8(*hci_status_code);
+            }
+            Command::GattcPrimaryServicesDiscover {
+                connection_handle,
+                start_handle,
+                uuid,
+            } => {
+                writer
+                    .u16(*connection_handle)
+                    .u16(*start_handle)
+                    .present(uuid.is_some());
+                if let Some(uuid) = uuid {
+                    writer.u16(uuid.uuid).u8(uuid.uuid_type);
+                }
+            }
+            Command::GattcRead {
+                connection_handle,
+                handle,
+                offset,
+            } => {
+                writer.u16(*connection_handle).u16(*handle).u16(*offset);
+            }
+            Command::GattcWrite {
+                connection_handle,
+                parameters,
+            } => {
+                writer
+                    .u16(*connection_handle)
+                    .present(true)
+                    .u8(parameters.write_op)
+                    .u8(parameters.flags)
+                    .u16(parameters.handle)
+                    .u16(parameters.offset)
+                    .len16_data(&parameters.value);
+            }
+            Command::GattsValueSet {
+                connection_handle,
+                handle,
+                offset,
+                value,
+            } => {
+                writer
+                    .u16(*connection_handle)
+                    .u16(*handle)
+                    .present(true)
+                    .u16(value.len() as u16)
+                    .u16(*offset)
+                    .present(true)
+                    .bytes(value);
+            }
+            Command::GattsHvx {
+                connection_handle,
+                handle,
+                hvx_type,
+                offset,
+                data,
+            } => {
+                writer
+                    .u16(*connection_handle)
+                    .present(true)
+                    .u16(*handle)
+                    .u8(*hvx_type)
+                    .u16(*offset)
+                    .present(true)
+                    .u16(data.len() as u16)
+                    .present(true)
+                    .bytes(data);
+            }
+        }
+
+        writer.finish()
+    }
+}
+
+fn encode_address(writer: &mut Writer, address: &GapAddress) {
+    let flags = (address.address_id_peer as u8) | ((address.address_type.as_u8() & 0x7f) << 1);
+    writer.u8(flags).bytes(&address.address);
+}
+
+fn encode_scan_parameters(writer: &mut Writer, parameters: &GapScanParameters) {
+    // report_incomplete_evts (bit 1) is not supported by this SoftDevice.
+    let flags = (parameters.extended & 0x01)
+        | ((parameters.active & 0x01) << 2)
+        | ((parameters.filter_policy & 0x03) << 3);
+    writer
+        .u8(flags)
+        .u8(parameters.scan_phys)
+        .u16(parameters.interval)
+        .u16(parameters.window)
+        .u16(parameters.timeout)
+        .bytes(&parameters.channel_mask);
+}
+
+fn encode_connection_parameters(writer: &mut Writer, parameters: &GapConnectionParameters) {
+    writer
+        .u16(parameters.min_connection_interval)
+        .u16(parameters.max_connection_interval)
+        .u16(parameters.slave_latency)
+        .u16(parameters.connection_supervision_timeout);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gap::GapAddressType;
+
+    #[test]
+    fn configuration_fixtures() {
+        let command = Command::CfgSet(Config::GapConnection {
+            connection_tag: 1,
+            connection_count: 2,
+            event_length: 6,
+        });
+        assert_eq!(
+            command.encode(),
+            [0x00, 0x69, 0x20, 0x00, 0x00, 0x00, 0x01, 0x01, 0x02, 0x06, 0x00]
+        );
+
+        let command = Command::CfgSet(Config::GapRoleCount(GapConfigRoleCount::default()));
+        assert_eq!(
+            command.encode(),
+            [0x00, 0x69, 0x40, 0x00, 0x00, 0x00, 0x01, 0x01, 0x01, 0x03, 0x01, 0x00]
+        );
+
+        let command = Command::CfgSet(Config::GattConnection {
+            connection_tag: 1,
+            att_mtu: 247,
+        });
+        assert_eq!(
+            command.encode(),
+            [0x00, 0x69, 0x23, 0x00, 0x00, 0x00, 0x01, 0x01, 0xf7, 0x00]
+        );
+
+        assert_eq!(
+            Command::Enable { app_ram_base: None }.encode(),
+            [0x00, 0x60, 0x00]
+        );
+    }
+
+    #[test]
+    fn gap_fixtures() {
+        let command = Command::GapScanStart {
+            parameters: Some(GapScanParameters::default()),
+            buffer_length: 255,
+        };
+        assert_eq!(
+            command.encode(),
+            [
+                0x00, 0x8a, 0x01, 0x05, 0x00, 0xa0, 0x00, 0x32, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+                0x00, 0x00, 0x01, 0xff, 0x00, 0x01
+            ]
+        );
+
+        let command = Command::GapScanStart {
+            parameters: None,
+            buffer_length: 255,
+        };
+        assert_eq!(command.encode(), [0x00, 0x8a, 0x00, 0x01, 0xff, 0x00, 0x01]);
+
+        let command = Command::GapConnect {
+            peer_address: GapAddress {
+                address_id_peer: false,
+                address_type: GapAddressType::RandomStatic,
+                address: [0x01, 0x02, 0x03, 0x04, 0x05, 0xc6],
+            },
+            scan_parameters: GapScanParameters::default(),
+            connection_parameters: GapConnectionParameters::default(),
+            connection_tag: 1,
+        };
+        assert_eq!(
+            command.encode(),
+            [
+                0x00, 0x8c, 0x01, 0x02, 0x01, 0x02, 0x03, 0x04, 0x05, 0xc6, 0x01, 0x05, 0x00,
+                0xa0, 0x00, 0x32, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01, 0x06,
+                0x00, 0x18, 0x00, 0x00, 0x00, 0x90, 0x01, 0x01
+            ]
+        );
+
+        let command = Command::GapDisconnect {
+            connection_handle: 0,
+            hci_status_code: 0x13,
+        };
+        assert_eq!(command.encode(), [0x00, 0x76, 0x00, 0x00, 0x13]);
+    }
+
+    #[test]
+    fn gatt_fixtures() {
+        let command = Command::GattcWrite {
+            connection_handle: 0,
+            parameters: GattcWriteParameters {
+                write_op: 1,
+                flags: 0,
+                handle: 0x000e,
+                offset: 0,
+                value: Bytes::from_static(&[0x01, 0x00]),
+            },
+        };
+        assert_eq!(
+            command.encode(),
+            [
+                0x00, 0xa3, 0x00, 0x00, 0x01, 0x01, 0x00, 0x0e, 0x00, 0x00, 0x00, 0x02, 0x00,
+                0x01, 0x01, 0x00
+            ]
+        );
+
+        let command = Command::GattsHvx {
+            connection_handle: 0,
+            handle: 0x0010,
+            hvx_type: 1,
+            offset: 0,
+            data: Bytes::from_static(&[0xaa]),
+        };
+        assert_eq!(
+            command.encode(),
+            [
+                0x00, 0xae, 0x00, 0x00, 0x01, 0x10, 0x00, 0x01, 0x00, 0x00, 0x01, 0x01, 0x00,
+                0x01, 0xaa
+            ]
+        );
+    }
+}
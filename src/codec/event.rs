@@ -0,0 +1,444 @@
+//! Decoding of the events sent by the connectivity firmware.
+
+use crate::codec::{command::BleUuid, Reader, PACKET_TYPE_EVENT};
+use crate::gap::{
+    GapAddress, GapAddressType, GapAdvertisementReport, GapAdvertisementReportType,
+    GapConnectionParameters, GapEvent, GapPhy, GapSetId, TxPowerLevel,
+};
+use crate::{Error, EventType, Result};
+use bytes::Bytes;
+use std::convert::TryFrom;
+
+/// IDs of the events decoded into their own `Event` variant.
+pub mod event_id {
+    pub const BLE_GAP_EVT_CONNECTED: u16 = 0x10;
+    pub const BLE_GAP_EVT_DISCONNECTED: u16 = 0x11;
+    pub const BLE_GAP_EVT_CONN_PARAM_UPDATE: u16 = 0x12;
+    pub const BLE_GAP_EVT_TIMEOUT: u16 = 0x1b;
+    pub const BLE_GAP_EVT_ADV_REPORT: u16 = 0x1d;
+    pub const BLE_GATTC_EVT_PRIM_SRVC_DISC_RSP: u16 = 0x30;
+    pub const BLE_GATTC_EVT_READ_RSP: u16 = 0x36;
+    pub const BLE_GATTC_EVT_WRITE_RSP: u16 = 0x38;
+    pub const BLE_GATTC_EVT_HVX: u16 = 0x39;
+    pub const BLE_GATTS_EVT_WRITE: u16 = 0x50;
+    pub const BLE_GATTS_EVT_HVN_TX_COMPLETE: u16 = 0x57;
+}
+
+const BLE_GAP_POWER_LEVEL_INVALID: i8 = 127;
+const BLE_GAP_ADV_REPORT_SET_ID_NOT_AVAILABLE: u8 = 0xff;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GattcService {
+    pub uuid: BleUuid,
+    pub start_handle: u16,
+    pub end_handle: u16,
+}
+
+/// A decoded SoftDevice event. Every event carries the connection handle
+/// it belongs to, `BLE_CONN_HANDLE_INVALID` if none.
+#[derive(Debug, Clone)]
+pub enum Event {
+    GapConnected {
+        connection_handle: u16,
+        peer_address: GapAddress,
+        role: u8,
+        connection_parameters: GapConnectionParameters,
+    },
+    GapDisconnected {
+        connection_handle: u16,
+        reason: u8,
+    },
+    GapConnectionParametersUpdate {
+        connection_handle: u16,
+        connection_parameters: GapConnectionParameters,
+    },
+    GapTimeout {
+        connection_handle: u16,
+        source: u8,
+    },
+    GapAdvertisingReport {
+        connection_handle: u16,
+        report: GapAdvertisementReport,
+    },
+    GattcPrimaryServicesDiscovered {
+        connection_handle: u16,
+        gatt_status: u16,
+        services: Vec<GattcService>,
+    },
+    GattcReadResponse {
+        connection_handle: u16,
+        gatt_status: u16,
+        handle: u16,
+        offset: u16,
+        data: Bytes,
+    },
+    GattcWriteResponse {
+        connection_handle: u16,
+        gatt_status: u16,
+        handle: u16,
+        write_op: u8,
+        offset: u16,
+        data: Bytes,
+    },
+    GattcHvx {
+        connection_handle: u16,
+        handle: u16,
+        hvx_type: u8,
+        data: Bytes,
+    },
+    GattsWrite {
+        connection_handle: u16,
+        handle: u16,
+        uuid: BleUuid,
+        op: u8,
+        offset: u16,
+        data: Bytes,
+    },
+    GattsHvnTxComplete {
+        connection_handle: u16,
+        count: u8,
+    },
+    /// An event without a dedicated decoder; `data` holds the fields
+    /// following the connection handle.
+    Other {
+        id: u16,
+        connection_handle: u16,
+        data: Bytes,
+    },
+}
+
+impl Event {
+    /// Decodes an event packet, including its leading packet type byte.
+    pub fn decode(packet: &[u8]) -> Result<Event> {
+        let mut reader = Reader::new(packet);
+        if reader.u8()? != PACKET_TYPE_EVENT {
+            return Err(Error::InvalidPacket("not a serialized event"));
+        }
+        let id = reader.u16()?;
+        let connection_handle = reader.u16()?;
+        let reader = &mut reader;
+
+        let event = match id {
+            event_id::BLE_GAP_EVT_CONNECTED => Event::GapConnected {
+                connection_handle,
+                peer_address: decode_address(reader)?,
+                role: reader.u8()?,
+                connection_parameters: decode_connection_parameters(reader)?,
+            },
+            event_id::BLE_GAP_EVT_DISCONNECTED => Event::GapDisconnected {
+                connection_handle,
+                reason: reader.u8()?,
+            },
+            event_id::BLE_GAP_EVT_CONN_PARAM_UPDATE => Event::GapConnectionParametersUpdate {
+                connection_handle,
+                connection_parameters: decode_connection_parameters(reader)?,
+            },
+            event_id::BLE_GAP_EVT_TIMEOUT => Event::GapTimeout {
+                connection_handle,
+                source: reader.u8()?,
+            },
+            event_id::BLE_GAP_EVT_ADV_REPORT => Event::GapAdvertisingReport {
+                connection_handle,
+                report: decode_advertising_report(reader)?,
+            },
+            event_id::BLE_GATTC_EVT_PRIM_SRVC_DISC_RSP => {
+                let (gatt_status, _error_handle) = (reader.u16()?, reader.u16()?);
+                let count = reader.u16()?;
+                let services = (0..count)
+                    .map(|_| {
+                        Ok(GattcService {
+                            uuid: decode_uuid(reader)?,
+                            start_handle: reader.u16()?,
+                            end_handle: reader.u16()?,
+                        })
+                    })
+                    .collect::<Result<Vec<_>>>()?;
+                Event::GattcPrimaryServicesDiscovered {
+                    connection_handle,
+                    gatt_status,
+                    services,
+                }
+            }
+            event_id::BLE_GATTC_EVT_READ_RSP => {
+                let (gatt_status, _error_handle) = (reader.u16()?, reader.u16()?);
+                let handle = reader.u16()?;
+                let offset = reader.u16()?;
+                let length = reader.u16()?;
+                Event::GattcReadResponse {
+                    connection_handle,
+                    gatt_status,
+                    handle,
+                    offset,
+                    data: reader.array(length)?,
+                }
+            }
+            event_id::BLE_GATTC_EVT_WRITE_RSP => {
+                let (gatt_status, _error_handle) = (reader.u16()?, reader.u16()?);
+                let handle = reader.u16()?;
+                let write_op = reader.u8()?;
+                let offset = reader.u16()?;
+                let length = reader.u16()?;
+                Event::GattcWriteResponse {
+                    connection_handle,
+                    gatt_status,
+                    handle,
+                    write_op,
+                    offset,
+                    data: reader.array(length)?,
+                }
+            }
+            event_id::BLE_GATTC_EVT_HVX => {
+                let (_gatt_status, _error_handle) = (reader.u16()?, reader.u16()?);
+                let handle = reader.u16()?;
+                let hvx_type = reader.u8()?;
+                let length = reader.u16()?;
+                Event::GattcHvx {
+                    connection_handle,
+                    handle,
+                    hvx_type,
+                    data: reader.array(length)?,
+                }
+            }
+            event_id::BLE_GATTS_EVT_WRITE => {
+                let handle = reader.u16()?;
+                let uuid = decode_uuid(reader)?;
+                let op = reader.u8()?;
+                let _auth_required = reader.u8()?;
+                let offset = reader.u16()?;
+                let length = reader.u16()?;
+                Event::GattsWrite {
+                    connection_handle,
+                    handle,
+                    uuid,
+                    op,
+                    offset,
+                    data: reader.array(length)?,
+                }
+            }
+            event_id::BLE_GATTS_EVT_HVN_TX_COMPLETE => Event::GattsHvnTxComplete {
+                connection_handle,
+                count: reader.u8()?,
+            },
+            id => Event::Other {
+                id,
+                connection_handle,
+                data: Bytes::copy_from_slice(reader.remaining()),
+            },
+        };
+
+        Ok(event)
+    }
+
+    pub fn id(&self) -> u16 {
+        match self {
+            Event::GapConnected { .. } => event_id::BLE_GAP_EVT_CONNECTED,
+            Event::GapDisconnected { .. } => event_id::BLE_GAP_EVT_DISCONNECTED,
+            Event::GapConnectionParametersUpdate { .. } => event_id::BLE_GAP_EVT_CONN_PARAM_UPDATE,
+            Event::GapTimeout { .. } => event_id::BLE_GAP_EVT_TIMEOUT,
+            Event::GapAdvertisingReport { .. } => event_id::BLE_GAP_EVT_ADV_REPORT,
+            Event::GattcPrimaryServicesDiscovered { .. } => {
+                event_id::BLE_GATTC_EVT_PRIM_SRVC_DISC_RSP
+            }
+            Event::GattcReadResponse { .. } => event_id::BLE_GATTC_EVT_READ_RSP,
+            Event::GattcWriteResponse { .. } => event_id::BLE_GATTC_EVT_WRITE_RSP,
+            Event::GattcHvx { .. } => event_id::BLE_GATTC_EVT_HVX,
+            Event::GattsWrite { .. } => event_id::BLE_GATTS_EVT_WRITE,
+            Event::GattsHvnTxComplete { .. } => event_id::BLE_GATTS_EVT_HVN_TX_COMPLETE,
+            Event::Other { id, .. } => *id,
+        }
+    }
+
+    pub fn connection_handle(&self) -> u16 {
+        match self {
+            Event::GapConnected { connection_handle, .. }
+            | Event::GapDisconnected { connection_handle, .. }
+            | Event::GapConnectionParametersUpdate { connection_handle, .. }
+            | Event::GapTimeout { connection_handle, .. }
+            | Event::GapAdvertisingReport { connection_handle, .. }
+            | Event::GattcPrimaryServicesDiscovered { connection_handle, .. }
+            | Event::GattcReadResponse { connection_handle, .. }
+            | Event::GattcWriteResponse { connection_handle, .. }
+            | Event::GattcHvx { connection_handle, .. }
+            | Event::GattsWrite { connection_handle, .. }
+            | Event::GattsHvnTxComplete { connection_handle, .. }
+            | Event::Other { connection_handle, .. } => *connection_handle,
+        }
+    }
+}
+
+impl From<Event> for EventType {
+    /// Maps a decoded event onto the events delivered by `BleDriver`.
+    fn from(event: Event) -> EventType {
+        let id = event.id() as u32;
+        let handle = event.connection_handle();
+
+        match event {
+            Event::GapConnected { .. } => EventType::BleGap(handle, GapEvent::Connect),
+            Event::GapDisconnected { .. } => EventType::BleGap(handle, GapEvent::Disconnect),
+            Event::GapConnectionParametersUpdate { .. } => {
+                EventType::BleGap(handle, GapEvent::ConnectionParametersUpdate)
+            }
+            Event::GapTimeout { source, .. } => EventType::BleGap(handle, GapEvent::Timeout(source)),
+            Event::GapAdvertisingReport { report, .. } => {
+                EventType::BleGap(handle, GapEvent::AdvertisingReport(report))
+            }
+            Event::GattcPrimaryServicesDiscovered { .. }
+            | Event::GattcReadResponse { .. }
+            | Event::GattcWriteResponse { .. }
+            | Event::GattcHvx { .. } => EventType::BleGattClient(handle, id),
+            Event::GattsWrite { .. } | Event::GattsHvnTxComplete { .. } => {
+                EventType::BleGattServer(handle, id)
+            }
+            Event::Other { .. } => match id {
+                0x01..=0x0f => EventType::BleCommon(handle, id),
+                0x10..=0x2f => EventType::BleGap(handle, GapEvent::Unknown(id)),
+                0x30..=0x4f => EventType::BleGattClient(handle, id),
+                0x50..=0x6f => EventType::BleGattServer(handle, id),
+                0x70..=0x8f => EventType::BleL2cap(handle, id),
+                id => EventType::Unknown(id),
+            },
+        }
+    }
+}
+
+fn decode_address(reader: &mut Reader) -> Result<GapAddress> {
+    let flags = reader.u8()?;
+    let mut address = [0; 6];
+    address.copy_from_slice(reader.bytes(6)?);
+
+    Ok(GapAddress {
+        address_id_peer: flags & 0x01 != 0,
+        address_type: GapAddressType::from_u8(flags >> 1),
+        address,
+    })
+}
+
+fn decode_connection_parameters(reader: &mut Reader) -> Result<GapConnectionParameters> {
+    Ok(GapConnectionParameters {
+        min_connection_interval: reader.u16()?,
+        max_connection_interval: reader.u16()?,
+        slave_latency: reader.u16()?,
+        connection_supervision_timeout: reader.u16()?,
+    })
+}
+
+fn decode_uuid(reader: &mut Reader) -> Result<BleUuid> {
+    Ok(BleUuid {
+        uuid: reader.u16()?,
+        uuid_type: reader.u8()?,
+    })
+}
+
+fn decode_advertising_report(reader: &mut Reader) -> Result<GapAdvertisementReport> {
+    let report_type = reader.u16()?;
+    let report_type = GapAdvertisementReportType {
+        connectable: report_type & 0x01 != 0,
+        scannable: report_type & 0x02 != 0,
+        directed: report_type & 0x04 != 0,
+        scan_response: report_type & 0x08 != 0,
+        extended_pdu: report_type & 0x10 != 0,
+        status: ((report_type >> 5) & 0x03) as u8,
+    };
+    let peer_address = decode_address(reader)?;
+    let direct_address = decode_address(reader)?;
+    let primary_phy = GapPhy::try_from(reader.u8()? as u32).unwrap();
+    let secondary_phy = GapPhy::try_from(reader.u8()? as u32).unwrap();
+    let tx_power = match reader.i8()? {
+        BLE_GAP_POWER_LEVEL_INVALID => TxPowerLevel::Invalid,
+        tx_power => TxPowerLevel::Value(tx_power),
+    };
+    let rssi = reader.i8()?;
+    let channel_index = reader.u8()?;
+    let set_id = match reader.u8()? {
+        BLE_GAP_ADV_REPORT_SET_ID_NOT_AVAILABLE => GapSetId::NotAvailable,
+        set_id => GapSetId::Value(set_id),
+    };
+    let _data_id = reader.u16()?;
+    let data = reader.len16_data()?;
+
+    Ok(GapAdvertisementReport {
+        report_type,
+        peer_address,
+        direct_address,
+        primary_phy,
+        secondary_phy,
+        tx_power,
+        rssi,
+        channel_index,
+        set_id,
+        data,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn advertising_report_fixture() {
+        let packet = [
+            0x02, 0x1d, 0x00, 0xff, 0xff, 0x13, 0x00, 0x03, 0x11, 0x22, 0x33, 0x44, 0x55, 0xe6,
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01, 0x00, 0x7f, 0xc4, 0x25, 0xff, 0x00,
+            0x00, 0x05, 0x00, 0x01, 0x04, 0x09, 0x6e, 0x52, 0x46, 0x00, 0x00, 0x00,
+        ];
+        let event = Event::decode(&packet).unwrap();
+        assert_eq!(event.connection_handle(), 0xffff);
+
+        let report = match event {
+            Event::GapAdvertisingReport { report, .. } => report,
+            other => panic!("unexpected event {:?}", other),
+        };
+        assert!(report.peer_address.address_id_peer);
+        assert!(matches!(report.peer_address.address_type, GapAddressType::RandomStatic));
+        assert_eq!(report.peer_address.address, [0x11, 0x22, 0x33, 0x44, 0x55, 0xe6]);
+        assert!(matches!(report.primary_phy, GapPhy::OneMbps));
+        assert!(matches!(report.tx_power, TxPowerLevel::Invalid));
+        assert_eq!(report.rssi, -60);
+        assert_eq!(report.channel_index, 37);
+        assert!(matches!(report.set_id, GapSetId::NotAvailable));
+        assert_eq!(&report.data[..], [0x04, 0x09, 0x6e, 0x52, 0x46]);
+    }
+
+    #[test]
+    fn connection_and_gatt_fixtures() {
+        let packet = [
+            0x02, 0x10, 0x00, 0x00, 0x00, 0x02, 0x01, 0x02, 0x03, 0x04, 0x05, 0xc6, 0x00, 0x06,
+            0x00, 0x18, 0x00, 0x00, 0x00, 0x90, 0x01,
+        ];
+        match Event::decode(&packet).unwrap() {
+            Event::GapConnected {
+                connection_handle: 0,
+                peer_address,
+                role: 0,
+                connection_parameters,
+            } => {
+                assert_eq!(peer_address.address, [0x01, 0x02, 0x03, 0x04, 0x05, 0xc6]);
+                assert_eq!(connection_parameters.connection_supervision_timeout, 400);
+            }
+            other => panic!("unexpected event {:?}", other),
+        }
+
+        let packet = [0x02, 0x11, 0x00, 0x00, 0x00, 0x13];
+        assert!(matches!(
+            EventType::from(Event::decode(&packet).unwrap()),
+            EventType::BleGap(0, GapEvent::Disconnect)
+        ));
+
+        let packet = [
+            0x02, 0x36, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x03, 0x00, 0x00, 0x00, 0x02,
+            0x00, 0x2a, 0x00,
+        ];
+        match Event::decode(&packet).unwrap() {
+            Event::GattcReadResponse {
+                connection_handle: 1,
+                gatt_status: 0,
+                handle: 3,
+                offset: 0,
+                data,
+            } => assert_eq!(&data[..], [0x2a, 0x00]),
+            other => panic!("unexpected event {:?}", other),
+        }
+
+        assert!(Event::decode(&packet[..packet.len() - 1]).is_err());
+    }
+}
@@ -0,0 +1,155 @@
+//! Native Rust implementation of the SoftDevice serialization format used
+//! between pc-ble-driver and the connectivity firmware.
+//!
+//! Every serialized packet starts with a packet type byte. Commands follow
+//! it with the SVC opcode and the call's arguments, responses with the
+//! opcode and the 32-bit result code, and events with the 16-bit event ID.
+//! Multi-byte integers are little-endian and optional pointers are encoded
+//! as a presence byte followed by the value when present.
+
+pub mod command;
+pub mod event;
+pub mod response;
+
+pub use command::{BleUuid, Command, Config, GattcWriteParameters};
+pub use event::{Event, GattcService};
+pub use response::Response;
+
+use crate::{Error, Result};
+use bytes::Bytes;
+
+pub const PACKET_TYPE_COMMAND: u8 = 0;
+pub const PACKET_TYPE_RESPONSE: u8 = 1;
+pub const PACKET_TYPE_EVENT: u8 = 2;
+/// Resets the connectivity chip. Followed by a single `sd_rpc_reset_t` byte.
+pub const PACKET_TYPE_RESET_COMMAND: u8 = 5;
+
+const FIELD_NOT_PRESENT: u8 = 0;
+const FIELD_PRESENT: u8 = 1;
+
+#[derive(Debug, Default)]
+pub(crate) struct Writer {
+    buffer: Vec<u8>,
+}
+
+impl Writer {
+    pub(crate) fn new() -> Writer {
+        Writer { buffer: Vec::new() }
+    }
+
+    pub(crate) fn u8(&mut self, value: u8) -> &mut Self {
+        self.buffer.push(value);
+        self
+    }
+
+    pub(crate) fn u16(&mut self, value: u16) -> &mut Self {
+        self.buffer.extend_from_slice(&value.to_le_bytes());
+        self
+    }
+
+    pub(crate) fn u32(&mut self, value: u32) -> &mut Self {
+        self.buffer.extend_from_slice(&value.to_le_bytes());
+        self
+    }
+
+    // Only capture files have 64-bit fields.
+    #[cfg_attr(not(feature = "pc-ble-driver"), allow(dead_code))]
+    pub(crate) fn u64(&mut self, value: u64) -> &mut Self {
+        self.buffer.extend_from_slice(&value.to_le_bytes());
+        self
+    }
+
+    pub(crate) fn bytes(&mut self, value: &[u8]) -> &mut Self {
+        self.buffer.extend_from_slice(value);
+        self
+    }
+
+    /// Presence byte of an optional pointer argument.
+    pub(crate) fn present(&mut self, present: bool) -> &mut Self {
+        self.u8(if present { FIELD_PRESENT } else { FIELD_NOT_PRESENT })
+    }
+
+    /// A `ble_data_t`-style buffer: its length, a presence byte and the data.
+    pub(crate) fn len16_data(&mut self, value: &[u8]) -> &mut Self {
+        self.u16(value.len() as u16).present(true).bytes(value)
+    }
+
+    pub(crate) fn finish(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.buffer)
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct Reader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    pub(crate) fn new(data: &'a [u8]) -> Reader<'a> {
+        Reader { data, position: 0 }
+    }
+
+    pub(crate) fn bytes(&mut self, length: usize) -> Result<&'a [u8]> {
+        let end = self.position + length;
+        if end > self.data.len() {
+            return Err(Error::InvalidPacket("serialized packet is truncated"));
+        }
+        let bytes = &self.data[self.position..end];
+        self.position = end;
+        Ok(bytes)
+    }
+
+    pub(crate) fn u8(&mut self) -> Result<u8> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    pub(crate) fn i8(&mut self) -> Result<i8> {
+        Ok(self.u8()? as i8)
+    }
+
+    pub(crate) fn u16(&mut self) -> Result<u16> {
+        let bytes = self.bytes(2)?;
+        Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+    }
+
+    pub(crate) fn u32(&mut self) -> Result<u32> {
+        let bytes = self.bytes(4)?;
+        Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    // Only capture files have 64-bit fields.
+    #[cfg_attr(not(feature = "pc-ble-driver"), allow(dead_code))]
+    pub(crate) fn u64(&mut self) -> Result<u64> {
+        let mut bytes = [0; 8];
+        bytes.copy_from_slice(self.bytes(8)?);
+        Ok(u64::from_le_bytes(bytes))
+    }
+
+    pub(crate) fn present(&mut self) -> Result<bool> {
+        match self.u8()? {
+            FIELD_NOT_PRESENT => Ok(false),
+            FIELD_PRESENT => Ok(true),
+            _ => Err(Error::InvalidPacket("invalid presence byte")),
+        }
+    }
+
+    /// A `ble_data_t`-style buffer, see `Writer::len16_data`.
+    pub(crate) fn len16_data(&mut self) -> Result<Bytes> {
+        let length = self.u16()? as usize;
+        if self.present()? {
+            Ok(Bytes::copy_from_slice(self.bytes(length)?))
+        } else {
+            Ok(Bytes::new())
+        }
+    }
+
+    /// An array whose 16-bit length was encoded separately.
+    pub(crate) fn array(&mut self, length: u16) -> Result<Bytes> {
+        Ok(Bytes::copy_from_slice(self.bytes(length as usize)?))
+    }
+
+    pub(crate) fn remaining(&self) -> &'a [u8] {
+        &self.data[self.position..]
+    }
+}
@@ -0,0 +1,79 @@
+//! Decoding of the connectivity firmware's command responses.
+
+use crate::codec::{command::opcode, Reader, PACKET_TYPE_RESPONSE};
+use crate::{Error, Result};
+use bytes::Bytes;
+
+pub const NRF_SUCCESS: u32 = 0;
+
+/// Response to a serialized command: the opcode it answers, the SoftDevice
+/// result code and any output parameters.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Response {
+    pub opcode: u8,
+    pub result: u32,
+    pub payload: Bytes,
+}
+
+impl Response {
+    /// Decodes a response packet, including its leading packet type byte.
+    pub fn decode(packet: &[u8]) -> Result<Response> {
+        let mut reader = Reader::new(packet);
+        if reader.u8()? != PACKET_TYPE_RESPONSE {
+            return Err(Error::InvalidPacket("not a serialized response"));
+        }
+        let opcode = reader.u8()?;
+        let result = reader.u32()?;
+
+        Ok(Response {
+            opcode,
+            result,
+            payload: Bytes::copy_from_slice(reader.remaining()),
+        })
+    }
+
+    /// Returns an error carrying the result code if the call failed.
+    pub fn check(&self) -> Result<()> {
+        if self.result == NRF_SUCCESS {
+            Ok(())
+        } else {
+            Err(Error::nrf(opcode::name(self.opcode), self.result))
+        }
+    }
+
+    /// Output of `sd_ble_version_get`: the Link Layer version number, the
+    /// company ID and the Link Layer subversion number.
+    pub fn version(&self) -> Result<(u8, u16, u16)> {
+        if self.opcode != opcode::SD_BLE_VERSION_GET {
+            return Err(Error::InvalidPacket("not a version response"));
+        }
+        self.check()?;
+
+        let mut reader = Reader::new(&self.payload);
+        Ok((reader.u8()?, reader.u16()?, reader.u16()?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::NrfError;
+
+    #[test]
+    fn version_response_fixture() {
+        let packet = [0x01, 0x65, 0x00, 0x00, 0x00, 0x00, 0x09, 0x59, 0x00, 0xb6, 0x00];
+        let response = Response::decode(&packet).unwrap();
+        assert_eq!(response.version().unwrap(), (9, 0x0059, 0x00b6));
+
+        let packet = [0x01, 0x8a, 0x08, 0x00, 0x00, 0x00];
+        let response = Response::decode(&packet).unwrap();
+        assert_eq!(response.opcode, opcode::SD_BLE_GAP_SCAN_START);
+        assert!(matches!(
+            response.check(),
+            Err(Error::Nrf {
+                operation: "sd_ble_gap_scan_start",
+                error: NrfError::InvalidState,
+            })
+        ));
+    }
+}
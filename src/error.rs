@@ -1,14 +1,261 @@
 
 
-use std::ffi;
+
+use std::{error, ffi, fmt, io};
+
+use crate::{ble::AdapterInfo, lifecycle::Lifecycle, manager::AdapterId};
 
 #[derive(Debug)]
 pub enum Error {
-    /// Error initializing the softdevice api
-    InitializationError,
+    /// Creating the pc-ble-driver layer with the named call failed
+    InitializationError(&'static str),
+
+    /// Invalid driver or transport configuration
+    InvalidConfiguration(&'static str),
+
+    /// No supported Nordic adapter was found on any serial port
+    NoAdapterFound,
+
+    /// The connectivity firmware does not implement SoftDevice API v6
+    IncompatibleFirmware(AdapterInfo),
+
+    /// The driver thread has stopped and can no longer execute commands
+    DriverClosed,
+
+    /// The call is not allowed in the driver's current lifecycle state
+    InvalidState(Lifecycle),
+
+    /// No adapter with this ID is managed by the `AdapterManager`
+    UnknownAdapter(AdapterId),
+
+    /// I/O error on the serial port of the native link layer
+    Io(io::Error),
+
+    /// A three-wire or serialization packet is malformed or failed validation
+    InvalidPacket(&'static str),
+
+    /// The peer did not complete link establishment or acknowledge a packet in time
+    LinkTimeout,
+
+    /// The native link layer's byte stream has been closed
+    LinkClosed,
+
+    /// A SoftDevice or pc-ble-driver call returned an error code
+    Nrf {
+        /// Name of the C function that failed, e.g. `sd_ble_gap_scan_start`
+        operation: &'static str,
+        error: NrfError,
+    },
 
-    FFIError(u32),
-    
     NullError(ffi::NulError),
 }
 
+impl Error {
+    pub(crate) fn nrf(operation: &'static str, code: u32) -> Error {
+        Error::Nrf {
+            operation,
+            error: NrfError::from_code(code),
+        }
+    }
+
+    /// The error code returned by the SoftDevice or pc-ble-driver, if any.
+    pub fn nrf_error(&self) -> Option<NrfError> {
+        match self {
+            Error::Nrf { error, .. } => Some(*error),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::InitializationError(operation) => write!(f, "{} failed", operation),
+            Error::InvalidConfiguration(reason) => write!(f, "invalid configuration: {}", reason),
+            Error::NoAdapterFound => f.write_str("no Nordic adapter found"),
+            Error::IncompatibleFirmware(info) => write!(
+                f,
+                "the firmware on {} does not implement SoftDevice API v6 \
+                 (company ID {:#06x}, firmware ID {:#06x})",
+                info.port_name, info.company_id, info.subversion_number
+            ),
+            Error::DriverClosed => f.write_str("the driver has been closed"),
+            Error::InvalidState(state) => write!(f, "not allowed while the driver is {}", state),
+            Error::UnknownAdapter(id) => write!(f, "{} is not managed", id),
+            Error::Io(_) => f.write_str("serial port I/O failed"),
+            Error::InvalidPacket(reason) => write!(f, "invalid packet: {}", reason),
+            Error::LinkTimeout => f.write_str("the three-wire link timed out"),
+            Error::LinkClosed => f.write_str("the three-wire link has been closed"),
+            Error::Nrf { operation, .. } => write!(f, "{} failed", operation),
+            Error::NullError(_) => f.write_str("string contains a nul byte"),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Error::Io(source) => Some(source),
+            Error::Nrf { error, .. } => Some(error),
+            Error::NullError(source) => Some(source),
+            _ => None,
+        }
+    }
+}
+
+macro_rules! nrf_errors {
+    ($($(#[$doc:meta])* $variant:ident = $code:literal => $name:literal,)*) => {
+        /// Error codes returned by the SoftDevice and by pc-ble-driver.
+        #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+        pub enum NrfError {
+            $($(#[$doc])* $variant,)*
+            /// A code outside of the known ranges
+            Unknown(u32),
+        }
+
+        impl NrfError {
+            pub fn from_code(code: u32) -> NrfError {
+                match code {
+                    $($code => NrfError::$variant,)*
+                    code => NrfError::Unknown(code),
+                }
+            }
+
+            pub fn code(&self) -> u32 {
+                match self {
+                    $(NrfError::$variant => $code,)*
+                    NrfError::Unknown(code) => *code,
+                }
+            }
+
+            /// Name of the C constant, e.g. `NRF_ERROR_INVALID_STATE`.
+            pub fn name(&self) -> &'static str {
+                match self {
+                    $(NrfError::$variant => $name,)*
+                    NrfError::Unknown(_) => "unknown error",
+                }
+            }
+        }
+    };
+}
+
+nrf_errors! {
+    /// SVC handler is missing
+    SvcHandlerMissing = 0x0001 => "NRF_ERROR_SVC_HANDLER_MISSING",
+    /// The SoftDevice has not been enabled
+    SoftDeviceNotEnabled = 0x0002 => "NRF_ERROR_SOFTDEVICE_NOT_ENABLED",
+    /// Internal error
+    Internal = 0x0003 => "NRF_ERROR_INTERNAL",
+    /// No memory for the operation
+    NoMemory = 0x0004 => "NRF_ERROR_NO_MEM",
+    NotFound = 0x0005 => "NRF_ERROR_NOT_FOUND",
+    NotSupported = 0x0006 => "NRF_ERROR_NOT_SUPPORTED",
+    InvalidParameter = 0x0007 => "NRF_ERROR_INVALID_PARAM",
+    /// The operation is not allowed in the current state
+    InvalidState = 0x0008 => "NRF_ERROR_INVALID_STATE",
+    InvalidLength = 0x0009 => "NRF_ERROR_INVALID_LENGTH",
+    InvalidFlags = 0x000a => "NRF_ERROR_INVALID_FLAGS",
+    InvalidData = 0x000b => "NRF_ERROR_INVALID_DATA",
+    DataSize = 0x000c => "NRF_ERROR_DATA_SIZE",
+    Timeout = 0x000d => "NRF_ERROR_TIMEOUT",
+    /// A required pointer was null
+    Null = 0x000e => "NRF_ERROR_NULL",
+    Forbidden = 0x000f => "NRF_ERROR_FORBIDDEN",
+    InvalidAddress = 0x0010 => "NRF_ERROR_INVALID_ADDR",
+    Busy = 0x0011 => "NRF_ERROR_BUSY",
+    /// The maximum connection count has been reached
+    ConnectionCount = 0x0012 => "NRF_ERROR_CONN_COUNT",
+    /// Not enough resources, e.g. for a queued notification
+    Resources = 0x0013 => "NRF_ERROR_RESOURCES",
+
+    /// `sd_ble_enable` has not been called
+    BleNotEnabled = 0x3001 => "BLE_ERROR_NOT_ENABLED",
+    InvalidConnectionHandle = 0x3002 => "BLE_ERROR_INVALID_CONN_HANDLE",
+    InvalidAttributeHandle = 0x3003 => "BLE_ERROR_INVALID_ATTR_HANDLE",
+    InvalidAdvertisingHandle = 0x3004 => "BLE_ERROR_INVALID_ADV_HANDLE",
+    InvalidRole = 0x3005 => "BLE_ERROR_INVALID_ROLE",
+    BlockedByOtherLinks = 0x3006 => "BLE_ERROR_BLOCKED_BY_OTHER_LINKS",
+
+    GapUuidListMismatch = 0x3200 => "BLE_ERROR_GAP_UUID_LIST_MISMATCH",
+    /// Use of the whitelist is not permitted with discoverable advertising
+    GapDiscoverableWithWhitelist = 0x3201 => "BLE_ERROR_GAP_DISCOVERABLE_WITH_WHITELIST",
+    GapInvalidBleAddress = 0x3202 => "BLE_ERROR_GAP_INVALID_BLE_ADDR",
+    GapWhitelistInUse = 0x3203 => "BLE_ERROR_GAP_WHITELIST_IN_USE",
+    GapDeviceIdentitiesInUse = 0x3204 => "BLE_ERROR_GAP_DEVICE_IDENTITIES_IN_USE",
+    GapDeviceIdentitiesDuplicate = 0x3205 => "BLE_ERROR_GAP_DEVICE_IDENTITIES_DUPLICATE",
+
+    /// A GATT client procedure is already in progress
+    GattcProcedureNotPermitted = 0x3300 => "BLE_ERROR_GATTC_PROC_NOT_PERMITTED",
+
+    GattsInvalidAttributeType = 0x3400 => "BLE_ERROR_GATTS_INVALID_ATTR_TYPE",
+    /// The system attributes of the connection have not been set
+    GattsSystemAttributesMissing = 0x3401 => "BLE_ERROR_GATTS_SYS_ATTR_MISSING",
+
+    SdRpcEncode = 0x8001 => "NRF_ERROR_SD_RPC_ENCODE",
+    SdRpcDecode = 0x8002 => "NRF_ERROR_SD_RPC_DECODE",
+    SdRpcSend = 0x8003 => "NRF_ERROR_SD_RPC_SEND",
+    SdRpcInvalidArgument = 0x8004 => "NRF_ERROR_SD_RPC_INVALID_ARGUMENT",
+    /// The connectivity firmware did not respond in time
+    SdRpcNoResponse = 0x8005 => "NRF_ERROR_SD_RPC_NO_RESPONSE",
+    SdRpcInvalidState = 0x8006 => "NRF_ERROR_SD_RPC_INVALID_STATE",
+    SdRpcSerializationTransport = 0x8014 => "NRF_ERROR_SD_RPC_SERIALIZATION_TRANSPORT",
+    SdRpcSerializationTransportInvalidState = 0x8015 => "NRF_ERROR_SD_RPC_SERIALIZATION_TRANSPORT_INVALID_STATE",
+    SdRpcSerializationTransportNoResponse = 0x8016 => "NRF_ERROR_SD_RPC_SERIALIZATION_TRANSPORT_NO_RESPONSE",
+    SdRpcSerializationTransportAlreadyOpen = 0x8017 => "NRF_ERROR_SD_RPC_SERIALIZATION_TRANSPORT_ALREADY_OPEN",
+    SdRpcSerializationTransportAlreadyClosed = 0x8018 => "NRF_ERROR_SD_RPC_SERIALIZATION_TRANSPORT_ALREADY_CLOSED",
+    SdRpcH5Transport = 0x8028 => "NRF_ERROR_SD_RPC_H5_TRANSPORT",
+    SdRpcH5TransportState = 0x8029 => "NRF_ERROR_SD_RPC_H5_TRANSPORT_STATE",
+    /// The three-wire link was not established in time
+    SdRpcH5TransportNoResponse = 0x802a => "NRF_ERROR_SD_RPC_H5_TRANSPORT_NO_RESPONSE",
+    SdRpcH5TransportSlipPayloadSize = 0x802b => "NRF_ERROR_SD_RPC_H5_TRANSPORT_SLIP_PAYLOAD_SIZE",
+    SdRpcH5TransportSlipCalculatedPayloadSize = 0x802c => "NRF_ERROR_SD_RPC_H5_TRANSPORT_SLIP_CALCULATED_PAYLOAD_SIZE",
+    SdRpcH5TransportSlipDecoding = 0x802d => "NRF_ERROR_SD_RPC_H5_TRANSPORT_SLIP_DECODING",
+    SdRpcH5TransportHeaderChecksum = 0x802e => "NRF_ERROR_SD_RPC_H5_TRANSPORT_HEADER_CHECKSUM",
+    SdRpcH5TransportPacketChecksum = 0x802f => "NRF_ERROR_SD_RPC_H5_TRANSPORT_PACKET_CHECKSUM",
+    SdRpcH5TransportAlreadyOpen = 0x8030 => "NRF_ERROR_SD_RPC_H5_TRANSPORT_ALREADY_OPEN",
+    SdRpcH5TransportAlreadyClosed = 0x8031 => "NRF_ERROR_SD_RPC_H5_TRANSPORT_ALREADY_CLOSED",
+    SdRpcH5TransportInternalError = 0x8032 => "NRF_ERROR_SD_RPC_H5_TRANSPORT_INTERNAL_ERROR",
+    /// The serial port could not be opened or used
+    SdRpcSerialPort = 0x803c => "NRF_ERROR_SD_RPC_SERIAL_PORT",
+    SdRpcSerialPortState = 0x803d => "NRF_ERROR_SD_RPC_SERIAL_PORT_STATE",
+    SdRpcSerialPortAlreadyOpen = 0x803e => "NRF_ERROR_SD_RPC_SERIAL_PORT_ALREADY_OPEN",
+    SdRpcSerialPortAlreadyClosed = 0x803f => "NRF_ERROR_SD_RPC_SERIAL_PORT_ALREADY_CLOSED",
+    SdRpcSerialPortInternalError = 0x8040 => "NRF_ERROR_SD_RPC_SERIAL_PORT_INTERNAL_ERROR",
+}
+
+impl NrfError {
+    /// Returns true for the errors raised by pc-ble-driver's transport
+    /// rather than by the SoftDevice.
+    pub fn is_transport(&self) -> bool {
+        (0x8000..0x9000).contains(&self.code())
+    }
+}
+
+impl fmt::Display for NrfError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ({:#06x})", self.name(), self.code())
+    }
+}
+
+impl error::Error for NrfError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::error::Error as _;
+
+    #[test]
+    fn nrf_error_codes_round_trip() {
+        let error = Error::nrf("sd_ble_gap_adv_start", 0x3201);
+        assert_eq!(error.nrf_error(), Some(NrfError::GapDiscoverableWithWhitelist));
+        assert_eq!(error.to_string(), "sd_ble_gap_adv_start failed");
+        assert_eq!(
+            error.source().unwrap().to_string(),
+            "BLE_ERROR_GAP_DISCOVERABLE_WITH_WHITELIST (0x3201)"
+        );
+
+        assert_eq!(NrfError::from_code(0x1234), NrfError::Unknown(0x1234));
+        assert!(NrfError::from_code(0x8005).is_transport());
+        assert!(!NrfError::InvalidState.is_transport());
+    }
+}
@@ -0,0 +1,380 @@
+//! Reliable three-wire link over a byte stream.
+
+use crate::h5::packet::{LinkControl, Packet, PacketType, SEQUENCE_MODULO};
+use crate::h5::slip;
+use crate::{Error, Result};
+use std::io::{ErrorKind, Read, Write};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Configuration field sent in SYNC_CONFIG: a sliding window of one packet
+/// with data integrity checks enabled, as used by the connectivity firmware.
+const SYNC_CONFIG_FIELD: u8 = 0x11;
+
+/// Link establishment state, following the three-wire UART specification.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LinkState {
+    /// Sending SYNC, waiting for the peer's SYNC_RESP.
+    Uninitialized,
+    /// Sending SYNC_CONFIG, waiting for the peer's CONFIG_RESP.
+    Initialized,
+    /// Link established; reliable packets can be exchanged.
+    Active,
+    /// The byte stream has been closed or failed.
+    Closed,
+}
+
+#[derive(Debug, Clone)]
+pub struct H5Config {
+    /// Interval between retransmissions of SYNC, SYNC_CONFIG and unacknowledged packets.
+    pub retransmission_interval: Duration,
+    /// Number of retransmissions of a reliable packet before giving up.
+    pub max_retransmissions: u32,
+    /// Time allowed for the SYNC/SYNC_CONFIG handshake.
+    pub link_establishment_timeout: Duration,
+}
+
+impl Default for H5Config {
+    fn default() -> Self {
+        H5Config {
+            retransmission_interval: Duration::from_millis(250),
+            max_retransmissions: 6,
+            link_establishment_timeout: Duration::from_millis(1500),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Shared {
+    state: LinkState,
+    /// Sequence number of the next reliable packet we send.
+    sequence_number: u8,
+    /// Sequence number we expect in the next reliable packet from the peer.
+    acknowledge_number: u8,
+    /// Last acknowledgement number received from the peer.
+    peer_acknowledge_number: u8,
+    /// Packets dropped because of framing, checksum or CRC errors.
+    invalid_packets: u64,
+}
+
+#[derive(Debug)]
+struct Link<W> {
+    shared: Mutex<Shared>,
+    changed: Condvar,
+    writer: Mutex<W>,
+}
+
+/// A three-wire (H5) data link over any byte stream, such as a serial port
+/// or one end of a pseudo-terminal.
+///
+/// A reader thread decodes incoming frames, answers link control messages
+/// and acknowledges reliable packets. Payloads of reliable packets are
+/// delivered in order through `recv_timeout`.
+#[derive(Debug)]
+pub struct H5Link<W: Write + Send + 'static> {
+    link: Arc<Link<W>>,
+    config: H5Config,
+    send_lock: Mutex<()>,
+    payloads: Mutex<Receiver<Vec<u8>>>,
+}
+
+impl<W: Write + Send + 'static> Link<W> {
+    fn write_packet(&self, packet: &Packet) -> Result<()> {
+        let frame = slip::encode(&packet.encode());
+        let mut writer = self.writer.lock().unwrap();
+        writer.write_all(&frame).map_err(Error::Io)?;
+        writer.flush().map_err(Error::Io)
+    }
+
+    fn set_state(&self, state: LinkState) {
+        self.shared.lock().unwrap().state = state;
+        self.changed.notify_all();
+    }
+
+    fn handle_packet(&self, packet: Packet, payloads: &Sender<Vec<u8>>) {
+        if packet.packet_type == PacketType::LinkControl {
+            self.handle_link_control(&packet);
+            return;
+        }
+
+        let mut shared = self.shared.lock().unwrap();
+        if shared.state != LinkState::Active {
+            return;
+        }
+
+        shared.peer_acknowledge_number = packet.acknowledge_number;
+        self.changed.notify_all();
+
+        if packet.reliable {
+            if packet.sequence_number == shared.acknowledge_number {
+                shared.acknowledge_number = (shared.acknowledge_number + 1) % SEQUENCE_MODULO;
+                let _result = payloads.send(packet.payload);
+            }
+            // Acknowledge duplicates as well, our previous ack may have been lost.
+            let acknowledge_number = shared.acknowledge_number;
+            drop(shared);
+            let _result = self.write_packet(&Packet::ack(acknowledge_number));
+        }
+    }
+
+    fn handle_link_control(&self, packet: &Packet) {
+        let response = match LinkControl::decode(&packet.payload) {
+            Some(LinkControl::Sync) => Some(LinkControl::SyncResponse),
+            Some(LinkControl::SyncConfig(_)) => {
+                Some(LinkControl::SyncConfigResponse(SYNC_CONFIG_FIELD))
+            }
+            Some(LinkControl::SyncResponse) => {
+                let mut shared = self.shared.lock().unwrap();
+                if shared.state == LinkState::Uninitialized {
+                    shared.state = LinkState::Initialized;
+                    self.changed.notify_all();
+                }
+                None
+            }
+            Some(LinkControl::SyncConfigResponse(_)) => {
+                let mut shared = self.shared.lock().unwrap();
+                if shared.state == LinkState::Initialized {
+                    shared.state = LinkState::Active;
+                    self.changed.notify_all();
+                }
+                None
+            }
+            _ => None,
+        };
+
+        if let Some(response) = response {
+            let _result = self.write_packet(&Packet::link_control(response));
+        }
+    }
+}
+
+impl<W: Write + Send + 'static> H5Link<W> {
+    /// Starts the link on `reader`/`writer` and performs the SYNC and
+    /// SYNC_CONFIG handshake with the peer.
+    pub fn open<R>(reader: R, writer: W, config: H5Config) -> Result<H5Link<W>>
+    where
+        R: Read + Send + 'static,
+    {
+        let link = Arc::new(Link {
+            shared: Mutex::new(Shared {
+                state: LinkState::Uninitialized,
+                sequence_number: 0,
+                acknowledge_number: 0,
+                peer_acknowledge_number: 0,
+                invalid_packets: 0,
+            }),
+            changed: Condvar::new(),
+            writer: Mutex::new(writer),
+        });
+        let (sender, payloads) = mpsc::channel();
+
+        let reader_link = Arc::clone(&link);
+        thread::spawn(move || read_loop(reader, reader_link, sender));
+
+        let h5 = H5Link {
+            link,
+            config,
+            send_lock: Mutex::new(()),
+            payloads: Mutex::new(payloads),
+        };
+        h5.establish()?;
+        Ok(h5)
+    }
+
+    pub fn state(&self) -> LinkState {
+        self.link.shared.lock().unwrap().state
+    }
+
+    /// Number of received packets dropped because of framing, header
+    /// checksum or CRC errors.
+    pub fn invalid_packets(&self) -> u64 {
+        self.link.shared.lock().unwrap().invalid_packets
+    }
+
+    /// Starts the SYNC and SYNC_CONFIG handshake over again, as needed
+    /// after the peer has been reset. Payloads not yet received are dropped.
+    pub fn reset(&self) -> Result<()> {
+        {
+            let mut shared = self.link.shared.lock().unwrap();
+            if shared.state == LinkState::Closed {
+                return Err(Error::LinkClosed);
+            }
+            shared.state = LinkState::Uninitialized;
+            shared.sequence_number = 0;
+            shared.acknowledge_number = 0;
+            shared.peer_acknowledge_number = 0;
+        }
+        let payloads = self.payloads.lock().unwrap();
+        while payloads.try_recv().is_ok() {}
+        drop(payloads);
+        self.establish()
+    }
+
+    /// Closes the link. The reader thread stops after its next read
+    /// returns.
+    pub fn close(&self) {
+        self.link.set_state(LinkState::Closed);
+    }
+
+    fn establish(&self) -> Result<()> {
+        let deadline = Instant::now() + self.config.link_establishment_timeout;
+
+        loop {
+            let state = self.state();
+            let message = match state {
+                LinkState::Uninitialized => LinkControl::Sync,
+                LinkState::Initialized => LinkControl::SyncConfig(SYNC_CONFIG_FIELD),
+                LinkState::Active => return Ok(()),
+                LinkState::Closed => return Err(Error::LinkClosed),
+            };
+            self.link.write_packet(&Packet::link_control(message))?;
+
+            let now = Instant::now();
+            if now >= deadline {
+                return Err(Error::LinkTimeout);
+            }
+            let wait = self.config.retransmission_interval.min(deadline - now);
+            let shared = self.link.shared.lock().unwrap();
+            let _result = self
+                .link
+                .changed
+                .wait_timeout_while(shared, wait, |shared| shared.state == state)
+                .unwrap();
+        }
+    }
+
+    /// Sends `payload` as a reliable vendor specific packet and waits for
+    /// the peer to acknowledge it, retransmitting as configured.
+    pub fn send(&self, payload: &[u8]) -> Result<()> {
+        let _guard = self.send_lock.lock().unwrap();
+
+        let (sequence_number, acknowledge_number) = {
+            let shared = self.link.shared.lock().unwrap();
+            match shared.state {
+                LinkState::Active => {}
+                LinkState::Closed => return Err(Error::LinkClosed),
+                _ => return Err(Error::LinkTimeout),
+            }
+            (shared.sequence_number, shared.acknowledge_number)
+        };
+        let expected_ack = (sequence_number + 1) % SEQUENCE_MODULO;
+        let packet = Packet {
+            sequence_number,
+            acknowledge_number,
+            reliable: true,
+            packet_type: PacketType::VendorSpecific,
+            payload: payload.to_vec(),
+        };
+
+        for _attempt in 0..=self.config.max_retransmissions {
+            self.link.write_packet(&packet)?;
+
+            let shared = self.link.shared.lock().unwrap();
+            let (mut shared, _timeout) = self
+                .link
+                .changed
+                .wait_timeout_while(shared, self.config.retransmission_interval, |shared| {
+                    shared.state == LinkState::Active
+                        && shared.peer_acknowledge_number != expected_ack
+                })
+                .unwrap();
+
+            if shared.state == LinkState::Closed {
+                return Err(Error::LinkClosed);
+            }
+            if shared.peer_acknowledge_number == expected_ack {
+                shared.sequence_number = expected_ack;
+                return Ok(());
+            }
+        }
+
+        Err(Error::LinkTimeout)
+    }
+
+    /// Waits up to `timeout` for the payload of the next reliable packet.
+    /// Returns `Ok(None)` on timeout.
+    pub fn recv_timeout(&self, timeout: Duration) -> Result<Option<Vec<u8>>> {
+        match self.payloads.lock().unwrap().recv_timeout(timeout) {
+            Ok(payload) => Ok(Some(payload)),
+            Err(RecvTimeoutError::Timeout) => Ok(None),
+            Err(RecvTimeoutError::Disconnected) => Err(Error::LinkClosed),
+        }
+    }
+}
+
+impl<W: Write + Send + 'static> Drop for H5Link<W> {
+    fn drop(&mut self) {
+        self.close();
+    }
+}
+
+fn read_loop<R: Read, W: Write + Send + 'static>(
+    mut reader: R,
+    link: Arc<Link<W>>,
+    payloads: Sender<Vec<u8>>,
+) {
+    let mut decoder = slip::Decoder::new();
+    let mut buffer = [0u8; 256];
+
+    loop {
+        let count = match reader.read(&mut buffer) {
+            // Readers with a read timeout, such as serial ports, report it as
+            // `TimedOut` so that a closed link is noticed.
+            Err(error) if error.kind() == ErrorKind::TimedOut || error.kind() == ErrorKind::Interrupted => 0,
+            Ok(0) | Err(_) => break,
+            Ok(count) => count,
+        };
+        if link.shared.lock().unwrap().state == LinkState::Closed {
+            return;
+        }
+
+        for &byte in &buffer[..count] {
+            if let Some(frame) = decoder.push(byte) {
+                match Packet::decode(&frame) {
+                    Ok(packet) => link.handle_packet(packet, &payloads),
+                    Err(_) => link.shared.lock().unwrap().invalid_packets += 1,
+                }
+            }
+        }
+    }
+
+    link.set_state(LinkState::Closed);
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use super::*;
+    use crate::test_support::pty_pair;
+
+    #[test]
+    fn link_establishment_and_reliable_exchange_over_pty() {
+        let (master, slave) = pty_pair();
+        let master_writer = master.try_clone().unwrap();
+        let slave_writer = slave.try_clone().unwrap();
+
+        let peer = thread::spawn(move || {
+            H5Link::open(slave, slave_writer, H5Config::default()).unwrap()
+        });
+        let host = H5Link::open(master, master_writer, H5Config::default()).unwrap();
+        let peer = peer.join().unwrap();
+        assert_eq!(host.state(), LinkState::Active);
+        assert_eq!(peer.state(), LinkState::Active);
+
+        for index in 0..10u8 {
+            // Exercise SLIP escaping and sequence number wrap-around.
+            let payload = vec![index, slip::END, slip::ESC, 0x60];
+            host.send(&payload).unwrap();
+            let received = peer.recv_timeout(Duration::from_secs(1)).unwrap();
+            assert_eq!(received, Some(payload));
+        }
+
+        peer.send(&[0x01, 0x02]).unwrap();
+        assert_eq!(
+            host.recv_timeout(Duration::from_secs(1)).unwrap(),
+            Some(vec![0x01, 0x02])
+        );
+        assert_eq!(host.invalid_packets(), 0);
+    }
+}
@@ -0,0 +1,11 @@
+//! Native Rust implementation of the Bluetooth three-wire UART (H5) data
+//! link layer used between pc-ble-driver and the connectivity firmware.
+//!
+//! `BleDriver` uses it instead of the link layer built into pc-ble-driver
+//! when `LinkLayer::Native` is selected, see `native::NativeBackend`.
+
+pub mod link;
+pub mod packet;
+pub mod slip;
+
+pub use link::{H5Config, H5Link, LinkState};
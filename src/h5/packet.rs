@@ -0,0 +1,289 @@
+//! Three-wire (H5) packet header, checksums and link control messages.
+
+use crate::{Error, Result};
+
+pub const HEADER_LENGTH: usize = 4;
+pub const CRC_LENGTH: usize = 2;
+/// Sequence and acknowledgement numbers are three bits wide.
+pub const SEQUENCE_MODULO: u8 = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PacketType {
+    Ack,
+    HciCommand,
+    AclData,
+    SyncData,
+    HciEvent,
+    Reset,
+    VendorSpecific,
+    LinkControl,
+    Unknown(u8),
+}
+
+impl PacketType {
+    fn from_u8(value: u8) -> PacketType {
+        match value {
+            0 => PacketType::Ack,
+            1 => PacketType::HciCommand,
+            2 => PacketType::AclData,
+            3 => PacketType::SyncData,
+            4 => PacketType::HciEvent,
+            5 => PacketType::Reset,
+            14 => PacketType::VendorSpecific,
+            15 => PacketType::LinkControl,
+            other => PacketType::Unknown(other),
+        }
+    }
+
+    fn as_u8(self) -> u8 {
+        match self {
+            PacketType::Ack => 0,
+            PacketType::HciCommand => 1,
+            PacketType::AclData => 2,
+            PacketType::SyncData => 3,
+            PacketType::HciEvent => 4,
+            PacketType::Reset => 5,
+            PacketType::VendorSpecific => 14,
+            PacketType::LinkControl => 15,
+            PacketType::Unknown(other) => other & 0x0f,
+        }
+    }
+}
+
+/// Link establishment messages carried in `LinkControl` packets.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LinkControl {
+    Sync,
+    SyncResponse,
+    SyncConfig(u8),
+    SyncConfigResponse(u8),
+    Wakeup,
+    Woken,
+    Sleep,
+}
+
+impl LinkControl {
+    pub fn encode(self) -> Vec<u8> {
+        match self {
+            LinkControl::Sync => vec![0x01, 0x7e],
+            LinkControl::SyncResponse => vec![0x02, 0x7d],
+            LinkControl::SyncConfig(config) => vec![0x03, 0xfc, config],
+            LinkControl::SyncConfigResponse(config) => vec![0x04, 0x7b, config],
+            LinkControl::Wakeup => vec![0x05, 0xfa],
+            LinkControl::Woken => vec![0x06, 0xf9],
+            LinkControl::Sleep => vec![0x07, 0x78],
+        }
+    }
+
+    pub fn decode(payload: &[u8]) -> Option<LinkControl> {
+        match payload {
+            [0x01, 0x7e, ..] => Some(LinkControl::Sync),
+            [0x02, 0x7d, ..] => Some(LinkControl::SyncResponse),
+            [0x03, 0xfc, config, ..] => Some(LinkControl::SyncConfig(*config)),
+            [0x03, 0xfc] => Some(LinkControl::SyncConfig(0)),
+            [0x04, 0x7b, config, ..] => Some(LinkControl::SyncConfigResponse(*config)),
+            [0x04, 0x7b] => Some(LinkControl::SyncConfigResponse(0)),
+            [0x05, 0xfa, ..] => Some(LinkControl::Wakeup),
+            [0x06, 0xf9, ..] => Some(LinkControl::Woken),
+            [0x07, 0x78, ..] => Some(LinkControl::Sleep),
+            _ => None,
+        }
+    }
+}
+
+/// A decoded three-wire packet.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Packet {
+    pub sequence_number: u8,
+    pub acknowledge_number: u8,
+    pub reliable: bool,
+    pub packet_type: PacketType,
+    pub payload: Vec<u8>,
+}
+
+impl Packet {
+    pub fn ack(acknowledge_number: u8) -> Packet {
+        Packet {
+            sequence_number: 0,
+            acknowledge_number,
+            reliable: false,
+            packet_type: PacketType::Ack,
+            payload: Vec::new(),
+        }
+    }
+
+    pub fn link_control(message: LinkControl) -> Packet {
+        Packet {
+            sequence_number: 0,
+            acknowledge_number: 0,
+            reliable: false,
+            packet_type: PacketType::LinkControl,
+            payload: message.encode(),
+        }
+    }
+
+    /// Encodes the packet, appending a CRC to reliable packets.
+    pub fn encode(&self) -> Vec<u8> {
+        let payload_length = self.payload.len() as u16;
+        let data_integrity = self.reliable;
+        let mut packet = Vec::with_capacity(HEADER_LENGTH + self.payload.len() + CRC_LENGTH);
+
+        packet.push(
+            (self.sequence_number & 0x07)
+                | (self.acknowledge_number & 0x07) << 3
+                | (data_integrity as u8) << 6
+                | (self.reliable as u8) << 7,
+        );
+        packet.push(self.packet_type.as_u8() | ((payload_length & 0x0f) as u8) << 4);
+        packet.push((payload_length >> 4) as u8);
+        packet.push(header_checksum(&packet[..3]));
+        packet.extend_from_slice(&self.payload);
+
+        if data_integrity {
+            let crc = crc16(&packet);
+            packet.extend_from_slice(&crc.to_le_bytes());
+        }
+
+        packet
+    }
+
+    /// Decodes a SLIP-decoded packet, verifying the header checksum,
+    /// payload length and, when present, the CRC.
+    pub fn decode(packet: &[u8]) -> Result<Packet> {
+        if packet.len() < HEADER_LENGTH {
+            return Err(Error::InvalidPacket("packet shorter than the header"));
+        }
+        if header_checksum(&packet[..3]) != packet[3] {
+            return Err(Error::InvalidPacket("header checksum mismatch"));
+        }
+
+        let data_integrity = packet[0] & 0x40 != 0;
+        let payload_length = ((packet[1] >> 4) as usize) | (packet[2] as usize) << 4;
+        let expected_length =
+            HEADER_LENGTH + payload_length + if data_integrity { CRC_LENGTH } else { 0 };
+        if packet.len() != expected_length {
+            return Err(Error::InvalidPacket("payload length mismatch"));
+        }
+
+        if data_integrity {
+            let crc_offset = HEADER_LENGTH + payload_length;
+            let crc = u16::from_le_bytes([packet[crc_offset], packet[crc_offset + 1]]);
+            if crc16(&packet[..crc_offset]) != crc {
+                return Err(Error::InvalidPacket("CRC mismatch"));
+            }
+        }
+
+        Ok(Packet {
+            sequence_number: packet[0] & 0x07,
+            acknowledge_number: (packet[0] >> 3) & 0x07,
+            reliable: packet[0] & 0x80 != 0,
+            packet_type: PacketType::from_u8(packet[1] & 0x0f),
+            payload: packet[HEADER_LENGTH..HEADER_LENGTH + payload_length].to_vec(),
+        })
+    }
+}
+
+/// Two's complement of the sum of the first three header bytes, so that
+/// all four header bytes add up to zero.
+pub fn header_checksum(header: &[u8]) -> u8 {
+    let sum = header.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte));
+    (!sum).wrapping_add(1)
+}
+
+/// CRC-CCITT (polynomial 0x1021, initial value 0xffff) over the header and
+/// payload. Unlike the three-wire specification, pc-ble-driver and the
+/// connectivity firmware transmit it least significant byte first.
+pub fn crc16(data: &[u8]) -> u16 {
+    let mut crc: u16 = 0xffff;
+    for &byte in data {
+        crc = crc.rotate_left(8);
+        crc ^= byte as u16;
+        crc ^= (crc & 0xff) >> 4;
+        crc ^= crc << 12;
+        crc ^= (crc & 0xff) << 5;
+    }
+    crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::h5::slip;
+
+    #[test]
+    fn crc16_matches_ccitt_false_check_value() {
+        assert_eq!(crc16(b"123456789"), 0x29b1);
+    }
+
+    #[test]
+    fn reliable_packet_round_trip() {
+        let packet = Packet {
+            sequence_number: 5,
+            acknowledge_number: 3,
+            reliable: true,
+            packet_type: PacketType::VendorSpecific,
+            payload: vec![0x00, 0x60, 0xc0, 0xdb, 0x01],
+        };
+        let encoded = packet.encode();
+        assert_eq!(encoded[..4], [0xdd, 0x5e, 0x00, 0xc5]);
+        assert_eq!(encoded.len(), HEADER_LENGTH + 5 + CRC_LENGTH);
+
+        let frame = slip::encode(&encoded);
+        let mut decoder = slip::Decoder::new();
+        let decoded: Vec<Vec<u8>> = frame.iter().filter_map(|b| decoder.push(*b)).collect();
+        assert_eq!(decoded, vec![encoded.clone()]);
+        assert_eq!(Packet::decode(&decoded[0]).unwrap(), packet);
+    }
+
+    #[test]
+    fn frames_match_pc_ble_driver() {
+        let frame = |packet: Packet| slip::encode(&packet.encode());
+
+        // Link establishment as sent by pc-ble-driver.
+        assert_eq!(
+            frame(Packet::link_control(LinkControl::Sync)),
+            [0xc0, 0x00, 0x2f, 0x00, 0xd1, 0x01, 0x7e, 0xc0]
+        );
+        assert_eq!(
+            frame(Packet::link_control(LinkControl::SyncConfig(0x11))),
+            [0xc0, 0x00, 0x3f, 0x00, 0xc1, 0x03, 0xfc, 0x11, 0xc0]
+        );
+        assert_eq!(frame(Packet::ack(1)), [0xc0, 0x08, 0x00, 0x00, 0xf8, 0xc0]);
+
+        // The first sd_ble_version_get command after link establishment. The
+        // first header byte is SLIP-escaped and the CRC is little-endian.
+        let version_get = [
+            0xc0, 0xdb, 0xdc, 0x3e, 0x00, 0x02, 0x00, 0x65, 0x01, 0x62, 0x21, 0xc0,
+        ];
+        let packet = Packet {
+            sequence_number: 0,
+            acknowledge_number: 0,
+            reliable: true,
+            packet_type: PacketType::VendorSpecific,
+            payload: vec![0x00, 0x65, 0x01],
+        };
+        assert_eq!(frame(packet.clone()), version_get);
+
+        let mut decoder = slip::Decoder::new();
+        let decoded: Vec<Vec<u8>> = version_get.iter().filter_map(|b| decoder.push(*b)).collect();
+        assert_eq!(Packet::decode(&decoded[0]).unwrap(), packet);
+    }
+
+    #[test]
+    fn corrupted_packet_is_rejected() {
+        let mut encoded = Packet::link_control(LinkControl::Sync).encode();
+        encoded[3] ^= 0x01;
+        assert!(Packet::decode(&encoded).is_err());
+
+        let mut encoded = Packet {
+            sequence_number: 0,
+            acknowledge_number: 0,
+            reliable: true,
+            packet_type: PacketType::VendorSpecific,
+            payload: vec![1, 2, 3],
+        }
+        .encode();
+        encoded[5] ^= 0x01;
+        assert!(Packet::decode(&encoded).is_err());
+    }
+}
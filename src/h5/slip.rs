@@ -0,0 +1,70 @@
+//! SLIP framing as used by the three-wire UART transport.
+
+pub const END: u8 = 0xc0;
+pub const ESC: u8 = 0xdb;
+pub const ESC_END: u8 = 0xdc;
+pub const ESC_ESC: u8 = 0xdd;
+
+/// Encodes `packet` into a SLIP frame delimited by `END` bytes.
+pub fn encode(packet: &[u8]) -> Vec<u8> {
+    let mut frame = Vec::with_capacity(packet.len() + 2);
+    frame.push(END);
+    for &byte in packet {
+        match byte {
+            END => frame.extend_from_slice(&[ESC, ESC_END]),
+            ESC => frame.extend_from_slice(&[ESC, ESC_ESC]),
+            byte => frame.push(byte),
+        }
+    }
+    frame.push(END);
+    frame
+}
+
+/// Incremental SLIP decoder fed with bytes as they arrive from the port.
+#[derive(Debug, Default)]
+pub struct Decoder {
+    buffer: Vec<u8>,
+    escaped: bool,
+    invalid: bool,
+}
+
+impl Decoder {
+    pub fn new() -> Decoder {
+        Decoder::default()
+    }
+
+    /// Feeds one byte, returning a complete packet when a frame ends.
+    /// Frames containing invalid escape sequences are discarded.
+    pub fn push(&mut self, byte: u8) -> Option<Vec<u8>> {
+        match byte {
+            END => {
+                let packet = std::mem::take(&mut self.buffer);
+                let invalid = self.invalid || self.escaped;
+                self.escaped = false;
+                self.invalid = false;
+                if packet.is_empty() || invalid {
+                    None
+                } else {
+                    Some(packet)
+                }
+            }
+            byte if self.escaped => {
+                self.escaped = false;
+                match byte {
+                    ESC_END => self.buffer.push(END),
+                    ESC_ESC => self.buffer.push(ESC),
+                    _ => self.invalid = true,
+                }
+                None
+            }
+            ESC => {
+                self.escaped = true;
+                None
+            }
+            byte => {
+                self.buffer.push(byte);
+                None
+            }
+        }
+    }
+}
@@ -1,10 +1,12 @@
 
 mod sd_api_v6;
 mod error;
+pub mod codec;
+pub mod h5;
 
 
 pub use sd_api_v6::*;
-pub use error::Error;
+pub use error::{Error, NrfError};
 
 
 pub type Result<T> = std::result::Result<T, error::Error>;
@@ -13,15 +15,60 @@ pub type Result<T> = std::result::Result<T, error::Error>;
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::blocking::BlockingBleDriver;
+    use crate::gap::{GapEvent, GapScanParameters};
+    use crate::simulated::SimulatedBackend;
+    use crate::test_support::{advertising_report, simulated_driver};
+    use std::time::Duration;
 
     #[test]
     fn adapter_test() {
-        let adapter = api::Adapter::new("/dev/ttyACM0");
-        assert!(adapter.is_ok());
+        let (backend, driver) = simulated_driver(
+            SimulatedBackend::new()
+                .advertising_report(advertising_report(b"\x04\x09one"))
+                .advertising_report(advertising_report(b"\x04\x09two")),
+        );
 
-        let mut adapter = adapter.unwrap();
-        adapter.open();
-        adapter.close();
+        let mut adapter = BlockingBleDriver::open(driver).unwrap();
+        assert!(adapter.adapter_info().unwrap().is_compatible());
+        adapter.gap_set_connection_config(1, 1, 6).unwrap();
+        assert!(matches!(
+            adapter.gap_scan_start(&GapScanParameters::default()),
+            Err(Error::InvalidState(lifecycle::Lifecycle::Configuring))
+        ));
+        adapter.ble_enable().unwrap();
+        adapter.gap_scan_start(&GapScanParameters::default()).unwrap();
 
+        // Scanning has to be resumed after every report to see the next one.
+        let mut names = Vec::new();
+        while names.len() < 3 {
+            match adapter.recv_event_timeout(Duration::from_secs(1)).unwrap() {
+                Some(EventType::BleGap(_, GapEvent::AdvertisingReport(report))) => {
+                    names.push(report.data.slice(2..));
+                }
+                Some(_) => {}
+                None => panic!("no advertising report received"),
+            }
+        }
+        assert_eq!(names, [&b"one"[..], b"two", b"one"]);
+        assert_eq!(backend.configs().len(), 1);
+        assert!(backend.is_scanning());
+
+        adapter.close().unwrap();
+        assert!(!backend.is_open());
+    }
+
+    #[test]
+    fn older_firmware_is_incompatible() {
+        // S132 v3.1.0, whose API the driver has no bindings for.
+        let (_backend, driver) = simulated_driver(SimulatedBackend::new().version(8, ble::NORDIC_COMPANY_ID, 0x0091));
+
+        match driver.open_adapter() {
+            Err(error) => assert!(matches!(
+                error.error,
+                Error::IncompatibleFirmware(ble::AdapterInfo { subversion_number: 0x0091, .. })
+            )),
+            Ok(_) => panic!("opened S132 v3 firmware"),
+        }
     }
 }
@@ -1,7 +0,0 @@
-pub mod adapter;
-pub mod ble;
-pub mod gap;
-pub mod gatt;
-pub mod gatts;
-pub mod gattc;
-pub mod l2cap;
\ No newline at end of file
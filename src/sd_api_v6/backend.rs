@@ -0,0 +1,82 @@
+use crate::{codec::{Config, Event}, gap::GapScanParameters, rpc::{LogSeverity, ResetMode, RpcStatus}, sd_api_v6::*, Result};
+use std::fmt::Debug;
+use std::sync::Weak;
+
+/// The SoftDevice calls `BleDriver` is built on.
+///
+/// `PcBleDriverBackend` implements them with pc-ble-driver and is used by
+/// default. `NativeBackend` implements them in Rust and is selected with
+/// `LinkLayer::Native`. `BleDriverBuilder::backend` selects any other
+/// implementation, such as `SimulatedBackend` in tests.
+pub trait BleBackend: Debug + Send + Sync {
+    /// Opens the transport. Events, status changes and log messages are
+    /// reported to `events` until the backend is closed.
+    fn open(&self, events: EventSink) -> Result<()>;
+
+    fn close(&self) -> Result<()>;
+
+    fn set_log_severity_filter(&self, severity: LogSeverity) -> Result<()>;
+
+    /// Resets the connectivity chip. The backend reports
+    /// `RpcStatus::ResetPerformed` once the firmware has restarted.
+    fn conn_reset(&self, mode: ResetMode) -> Result<()>;
+
+    /// Link Layer version number, company ID and Link Layer subversion
+    /// number of the SoftDevice.
+    fn version_get(&self) -> Result<(u8, u16, u16)>;
+
+    fn cfg_set(&self, config: &Config) -> Result<()>;
+
+    fn ble_enable(&self) -> Result<()>;
+
+    /// Starts scanning with `parameters`, or resumes scanning after an
+    /// advertising report if `parameters` is `None`.
+    fn gap_scan_start(&self, parameters: Option<&GapScanParameters>) -> Result<()>;
+
+    fn gap_scan_stop(&self) -> Result<()>;
+
+    /// Answers `BLE_EVT_USER_MEM_REQUEST` on `connection_handle` with a
+    /// block of `length` bytes, or with none if `length` is `None`.
+    fn user_mem_reply(&self, connection_handle: u16, length: Option<u16>) -> Result<()>;
+}
+
+/// Where a backend delivers what it receives from the adapter.
+///
+/// The sink only holds a weak reference to the driver, events reported
+/// after the driver has been dropped are discarded.
+#[derive(Debug, Clone)]
+pub struct EventSink {
+    core: Weak<DriverCore>,
+}
+
+impl EventSink {
+    pub(crate) fn new(core: Weak<DriverCore>) -> EventSink {
+        EventSink { core }
+    }
+
+    pub fn event(&self, event: EventType) {
+        if let Some(core) = self.core.upgrade() {
+            core.handle_event(event);
+        }
+    }
+
+    /// Reports the decoded form of an event that is also delivered with
+    /// `event`, for the taps that need the GATT payloads `EventType` leaves out.
+    pub fn traffic(&self, event: &Event) {
+        if let Some(core) = self.core.upgrade() {
+            core.handle_traffic(event);
+        }
+    }
+
+    pub fn status(&self, status: RpcStatus, message: String) {
+        if let Some(core) = self.core.upgrade() {
+            core.handle_status(status, message);
+        }
+    }
+
+    pub fn log(&self, severity: LogSeverity, message: String) {
+        if let Some(core) = self.core.upgrade() {
+            core.handle_log(severity, message);
+        }
+    }
+}
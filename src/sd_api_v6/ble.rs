@@ -1,22 +1,103 @@
-use crate::{Error, Result, sd_api_v6::BleDriver};
-use nrf_ble_driver_sys::ffi;
+use crate::{codec::Command, lifecycle::{Configuring, Enabled, Open, State, TransitionError, TransitionResult}, version::SdApiVersion, Error, Result, sd_api_v6::{BleDriver, DriverCore}};
 
+/// Bluetooth SIG company identifier of Nordic Semiconductor.
+pub const NORDIC_COMPANY_ID: u16 = 0x0059;
 
+/// Version information reported by the connectivity firmware.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AdapterInfo {
+    /// Serial port the adapter is attached to.
+    pub port_name: String,
+    /// UART baud rate of the transport.
+    pub baud_rate: u32,
+    /// Link Layer version number, e.g. 9 for Bluetooth 5.0.
+    pub version_number: u8,
+    /// Company identifier of the SoftDevice vendor.
+    pub company_id: u16,
+    /// Link Layer subversion number, the firmware ID of the SoftDevice.
+    pub subversion_number: u16,
+}
 
-impl BleDriver {
+impl AdapterInfo {
+    /// API version of the SoftDevice, if it is a Nordic SoftDevice this
+    /// driver can talk to.
+    pub fn api_version(&self) -> Option<SdApiVersion> {
+        if self.company_id != NORDIC_COMPANY_ID {
+            return None;
+        }
+        SdApiVersion::from_firmware_id(self.subversion_number)
+    }
+
+    /// Returns true if the firmware runs a SoftDevice implementing API v6,
+    /// the version this driver is built for.
+    pub fn is_compatible(&self) -> bool {
+        self.api_version().is_some()
+    }
+}
+
+
+
+impl BleDriver<Configuring> {
+    /// Enables the SoftDevice with the configuration set so far. From now
+    /// on it accepts radio operations but no further configuration.
+    pub fn ble_enable(self) -> TransitionResult<BleDriver<Enabled>, BleDriver<Configuring>> {
+        if let Err(error) = self.adapter.core.ble_enable() {
+            return Err(TransitionError::new(error, self));
+        }
+        Ok(self.transition())
+    }
+}
+
+impl BleDriver<Enabled> {
+    /// Answers a `BLE_EVT_USER_MEM_REQUEST` on `connection_handle`. With a
+    /// `length` the driver lends the SoftDevice a block of that many bytes
+    /// for queued writes, which it keeps until `BLE_EVT_USER_MEM_RELEASE`
+    /// or the disconnection. Without one the SoftDevice handles the request
+    /// itself.
+    pub fn user_mem_reply(&mut self, connection_handle: u16, length: Option<u16>) -> Result<()> {
+        self.adapter.core.command("sd_ble_user_mem_reply", Some(connection_handle), &length, || {
+            self.adapter.core.backend.user_mem_reply(connection_handle, length)
+        })
+    }
+}
+
+impl<S: Open> BleDriver<S> {
+    /// Queries the SoftDevice version of the connectivity firmware.
+    pub fn ble_version_get(&mut self) -> Result<AdapterInfo> {
+        self.adapter.core.version_get()
+    }
+}
+
+impl<S: State> BleDriver<S> {
+    /// Version information of the attached firmware, available once the
+    /// adapter has been opened and updated after every reset.
+    pub fn adapter_info(&self) -> Option<AdapterInfo> {
+        self.adapter.core.adapter_info.lock().unwrap().clone()
+    }
+}
+
+impl DriverCore {
+    pub(crate) fn version_get(&self) -> Result<AdapterInfo> {
+        let (version_number, company_id, subversion_number) =
+            self.serialized(&Command::VersionGet, || self.backend.version_get())?;
+
+        Ok(AdapterInfo {
+            port_name: self.config.port_name.clone(),
+            baud_rate: self.config.baud_rate.as_u32(),
+            version_number,
+            company_id,
+            subversion_number,
+        })
+    }
 
-    pub fn ble_enable(
-        &mut self,
-    ) -> Result<()> {
-        
-        unsafe {
-            let mut ram_base: u32 = 0;
-            let error_code = ffi::sd_ble_enable(self.adapter, &mut ram_base);
-            if error_code == ffi::NRF_SUCCESS {
-                Ok(())
-            } else {
-                Err(Error::FFIError(error_code))
-            }
+    /// Reads the firmware version and checks that this driver can talk to
+    /// it, then records it as the attached firmware.
+    pub(crate) fn identify_firmware(&self) -> Result<()> {
+        let adapter_info = self.version_get()?;
+        if adapter_info.api_version().is_none() {
+            return Err(Error::IncompatibleFirmware(adapter_info));
         }
+        *self.adapter_info.lock().unwrap() = Some(adapter_info);
+        Ok(())
     }
-}
\ No newline at end of file
+}
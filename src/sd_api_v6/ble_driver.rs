@@ -1,184 +1,300 @@
-use crate::gap::GapEvent;
-use crate::{sd_api_v6::*, Error, Result};
-use nrf_ble_driver_sys::ffi;
-use std::ffi::{c_void, CStr, CString};
-use tokio::sync::mpsc;
-use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender};
+use crate::{backend::{BleBackend, EventSink}, builder::{LinkLayer, TransportConfig}, codec::{command::opcode, Command, Event}, event_bus::{EventBus, EventFilter}, event_queue::{EventQueueStats, OverflowPolicy}, gap::GapScanParameters, handle::{BleDriverHandle, EventStream}, lifecycle::{Closed, Configuring, Lifecycle, Open, State, TransitionError, TransitionResult}, native::NativeBackend, pcap::PcapWriter, recovery::RecoveryState, stats::{DriverStats, StatsCollector}, rpc::{LogSeverity, ResetMode, RpcStatus}, sd_api_v6::{trace, *}, Result};
+use std::fmt::Debug;
+use std::marker::PhantomData;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
 
-const DEFAULT_BAUDRATE: u32 = 1_000_000;
+#[cfg(feature = "pc-ble-driver")]
+use crate::pc_ble_driver::PcBleDriverBackend;
 
-unsafe impl Send for BleDriver {}
+/// Source of a GAP timeout that ended scanning.
+const BLE_GAP_TIMEOUT_SRC_SCAN: u8 = 1;
 
-impl BleDriver {
+impl BleDriver<Closed> {
     pub fn new(port_name: &str) -> Result<BleDriver> {
-        let raw_adapter = BleDriver::adapter_init(port_name)?;
-        // Create our single boxed advertising data buffer
-        let mut p_data = vec![0; ffi::BLE_GAP_SCAN_BUFFER_EXTENDED_MAX as usize].into_boxed_slice();
-        let adv_data = Box::new(ffi::ble_data_t {
-            p_data: p_data.as_mut_ptr(),
-            len: p_data.len() as u16,
-        });
-        std::mem::forget(p_data);
-        let (send, recv): (UnboundedSender<EventType>, UnboundedReceiver<EventType>) =
-            mpsc::unbounded_channel();
-
-        Ok(BleDriver {
-            adapter: raw_adapter,
-            adv_data,
-            is_open: false,
-            event_receiver: recv,
+        BleDriver::with_config(TransportConfig::new(port_name))
+    }
+
+    pub(crate) fn with_config(config: TransportConfig) -> Result<BleDriver> {
+        let backend: Arc<dyn BleBackend> = match config.link_layer {
+            #[cfg(feature = "pc-ble-driver")]
+            LinkLayer::PcBleDriver => Arc::new(PcBleDriverBackend::new(&config)?),
+            LinkLayer::Native => Arc::new(NativeBackend::new(&config)),
+        };
+        Ok(BleDriver::with_backend(config, backend))
+    }
+
+    pub(crate) fn with_backend(config: TransportConfig, backend: Arc<dyn BleBackend>) -> BleDriver {
+        let (send, _) = EventBus::new();
+
+        let core = Arc::new_cyclic(|this| DriverCore {
+            this: this.clone(),
+            backend,
             callback_event: send,
-            is_scanning: false,
-        })
+            is_scanning: AtomicBool::new(false),
+            log_events: AtomicBool::new(false),
+            auto_recover: AtomicBool::new(false),
+            recovering: AtomicBool::new(false),
+            shutdown: AtomicBool::new(false),
+            recovery: Mutex::new(RecoveryState::default()),
+            resetting: AtomicBool::new(false),
+            resets_performed: Mutex::new(0),
+            reset_performed: Condvar::new(),
+            pcap: Mutex::new(None),
+            stats: Arc::new(StatsCollector::new()),
+            config,
+            adapter_info: Arc::new(Mutex::new(None)),
+        });
+
+        BleDriver {
+            adapter: Box::new(Adapter {
+                core,
+                is_open: false,
+            }),
+            state: PhantomData,
+        }
     }
 
-    pub fn open(&mut self) -> Result<()> {
-        if !self.is_open {
-            unsafe {
-                let error_code = ffi::sd_rpc_open(
-                    self.adapter,
-                    Some(sd_rpc_status_handler),
-                    Some(sd_rpc_event_handler),
-                    Some(sd_rpc_log_handler),
-                    self as *mut _ as *mut c_void,
-                );
-                if error_code == ffi::NRF_SUCCESS {
-                    return Ok(());
-                } else {
-                    return Err(Error::FFIError(error_code));
-                }
-            }
+    /// Opens the adapter and hands it over to a dedicated driver thread.
+    ///
+    /// Returns a cloneable handle for issuing commands and the stream of
+    /// events received from the adapter.
+    pub fn open(self) -> Result<(BleDriverHandle, EventStream)> {
+        // Subscribe before opening so no event received while opening is lost.
+        let events = self.subscribe(EventFilter::All);
+        let driver = self.open_adapter()?;
+        let bus = Arc::clone(driver.adapter.core.callback_event.bus());
+        Ok((BleDriverHandle::spawn(driver, bus), events))
+    }
+
+    /// Opens the adapter and checks that its firmware is compatible, leaving
+    /// the SoftDevice ready for configuration.
+    pub fn open_adapter(mut self) -> TransitionResult<BleDriver<Configuring>, BleDriver> {
+        if let Err(error) = self.adapter.open() {
+            return Err(TransitionError::new(error, self));
         }
+        Ok(self.transition())
+    }
+}
 
-        Ok(())
+impl<S: Open> BleDriver<S> {
+    /// Closes the adapter. Dropping an open driver closes it as well.
+    pub fn close(mut self) -> TransitionResult<BleDriver, BleDriver<S>> {
+        if let Err(error) = self.adapter.close() {
+            return Err(TransitionError::new(error, self));
+        }
+        Ok(self.transition())
     }
 
-    pub fn close(&mut self) -> Result<()> {
-        if self.is_open {
-            unsafe {
-                let error_code = ffi::sd_rpc_close(self.adapter);
-                if error_code == ffi::NRF_SUCCESS {
-                    return Ok(());
-                } else {
-                    return Err(Error::FFIError(error_code));
-                }
-            }
+    /// Resets the connectivity chip and waits until the firmware has
+    /// restarted.
+    ///
+    /// The transport stays open, but the SoftDevice comes back disabled:
+    /// scanning has stopped and the configuration has to be set again
+    /// before calling `ble_enable`. Automatic recovery is not triggered by
+    /// the reset. The firmware version is read again afterwards.
+    pub fn reset(self, mode: ResetMode) -> TransitionResult<BleDriver<Configuring>, BleDriver<S>> {
+        if let Err(error) = self.adapter.core.reset(mode) {
+            return Err(TransitionError::new(error, self));
         }
+        Ok(self.transition())
+    }
+}
 
-        Ok(())
+impl<S: State> BleDriver<S> {
+    pub(crate) fn transition<T: State>(self) -> BleDriver<T> {
+        BleDriver {
+            adapter: self.adapter,
+            state: PhantomData,
+        }
     }
 
-    pub async fn receive_event(&mut self) -> Option<EventType> {
-        self.event_receiver.recv().await
+    pub fn lifecycle(&self) -> Lifecycle {
+        S::LIFECYCLE
     }
 
-    pub fn handle_ffi_event(&mut self, ble_event: *mut ffi::ble_evt_t) {
-        unsafe {
-            let event_id: u32 = (*ble_event).header.evt_id.into();
+    /// Sets the lowest severity of log messages reported by the driver.
+    pub fn set_log_severity_filter(&mut self, severity: LogSeverity) -> Result<()> {
+        self.adapter.core.command("sd_rpc_log_handler_severity_filter_set", None, &severity, || {
+            self.adapter.core.backend.set_log_severity_filter(severity)
+        })
+    }
 
-            let event = match event_id {
-                ffi::BLE_EVT_INVALID => EventType::Invalid,
-                id@ ffi::BLE_EVT_BASE..=ffi::BLE_EVT_LAST => EventType::BleCommon(id),
-                id@ffi::BLE_GAP_EVT_BASE..=ffi::BLE_GAP_EVT_LAST => EventType::BleGap(self.handle_gap_event(id, &(*ble_event).evt.gap_evt)),
-                id@ffi::BLE_GATTC_EVT_BASE..=ffi::BLE_GATTC_EVT_LAST => EventType::BleGattClient(id),
-                id@ffi::BLE_GATTS_EVT_BASE..=ffi::BLE_GATTS_EVT_LAST => EventType::BleGattServer(id),
-                id@ffi::BLE_L2CAP_EVT_BASE..=ffi::BLE_L2CAP_EVT_LAST => EventType::BleL2cap(id),
-                id => EventType::Unknown(id),
-            };
+    /// Enables or disables delivery of driver log messages as `EventType::RpcLog`.
+    pub fn set_log_events(&mut self, enabled: bool) {
+        self.adapter.core.log_events.store(enabled, Ordering::Relaxed);
+    }
 
-            let _result = self.callback_event.send(event);
-        }
+    /// Limits every event queue to `capacity` events, applying `policy` when
+    /// it is full. `None` removes the limit, which is the default. A
+    /// capacity of 0 is treated as 1.
+    pub fn set_event_queue_capacity(&mut self, capacity: Option<usize>, policy: OverflowPolicy) {
+        self.adapter.core.callback_event.bus().set_capacity(capacity, policy);
     }
 
-    fn adapter_init(port_name: &str) -> Result<*mut ffi::adapter_t> {
-        let port = CString::new(port_name).map_err(Error::NullError)?;
-        unsafe {
-            let phy = ffi::sd_rpc_physical_layer_create_uart(
-                port.as_ptr(),
-                DEFAULT_BAUDRATE,
-                ffi::sd_rpc_flow_control_t_SD_RPC_FLOW_CONTROL_NONE,
-                ffi::sd_rpc_parity_t_SD_RPC_PARITY_NONE,
-            );
+    /// Number of events dropped because an event queue was full, summed
+    /// over all subscribers.
+    pub fn event_queue_stats(&self) -> EventQueueStats {
+        self.adapter.core.callback_event.bus().stats()
+    }
 
-            if phy.is_null() {
-                return Err(Error::InitializationError);
-            }
+    /// Subscribes to the events matching `filter`, in addition to the
+    /// stream returned by `open`.
+    pub fn subscribe(&self, filter: EventFilter) -> EventStream {
+        EventStream::new(self.adapter.core.callback_event.bus().subscribe(filter))
+    }
 
-            let link_layer = ffi::sd_rpc_data_link_layer_create_bt_three_wire(phy, 250);
-            if link_layer.is_null() {
-                return Err(Error::InitializationError);
-            }
+    /// Enables or disables automatic recovery after a link loss or an
+    /// unexpected reset of the connectivity firmware.
+    ///
+    /// When enabled the transport is reopened in the background and the
+    /// firmware is reset, then every configuration call and `ble_enable` is
+    /// replayed and scanning resumes with the last parameters.
+    /// `EventType::Reconnected` is emitted once the adapter is usable again,
+    /// or `EventType::RecoveryFailed` if it could not be restored.
+    pub fn set_auto_recover(&mut self, enabled: bool) {
+        self.adapter.core.auto_recover.store(enabled, Ordering::Relaxed);
+    }
+
+    /// Health counters of the adapter since the driver was created or
+    /// `reset_stats` was last called.
+    pub fn stats(&self) -> DriverStats {
+        self.adapter.core.stats.snapshot(self.adapter.core.callback_event.bus())
+    }
 
-            let transport_layer = ffi::sd_rpc_transport_layer_create(link_layer, 1500);
-            if transport_layer.is_null() {
-                return Err(Error::InitializationError);
+    /// Resets the counters returned by `stats` and `event_queue_stats`.
+    pub fn reset_stats(&self) {
+        self.adapter.core.stats.reset(self.adapter.core.callback_event.bus())
+    }
+
+    /// Writes the advertising reports and GATT traffic seen from now on to
+    /// `pcap`, replacing any previous one. `None` stops the export.
+    pub fn set_pcap(&mut self, pcap: Option<PcapWriter>) {
+        *self.adapter.core.pcap.lock().unwrap() = pcap;
+    }
+}
+
+impl DriverCore {
+    /// Runs the SoftDevice call `name`, tracing it and counting it in the
+    /// driver statistics.
+    pub(crate) fn command<T>(
+        &self,
+        name: &'static str,
+        connection_handle: Option<u16>,
+        parameters: &dyn Debug,
+        command: impl FnOnce() -> Result<T>,
+    ) -> Result<T> {
+        let result = trace::command(name, connection_handle, parameters, command);
+        self.stats.record_command(name, &result);
+        result
+    }
+
+    /// Runs the serialized SoftDevice call `command` like `command`, writing
+    /// the ATT PDU it sends to the pcap export once it has succeeded.
+    pub(crate) fn serialized<T>(
+        &self,
+        command: &Command,
+        call: impl FnOnce() -> Result<T>,
+    ) -> Result<T> {
+        let name = opcode::name(command.opcode());
+        let result = self.command(name, command.connection_handle(), command, call);
+        if result.is_ok() {
+            if let Some(pcap) = self.pcap.lock().unwrap().as_mut() {
+                let _result = pcap.command(command);
             }
+        }
+        result
+    }
 
-            let adapter = ffi::sd_rpc_adapter_create(transport_layer);
-            if adapter.is_null() {
-                return Err(Error::InitializationError);
+    pub(crate) fn rpc_open(&self) -> Result<()> {
+        self.command("sd_rpc_open", None, &(), || {
+            self.backend.open(EventSink::new(self.this.clone()))
+        })
+    }
+
+    pub(crate) fn rpc_close(&self) -> Result<()> {
+        self.command("sd_rpc_close", None, &(), || self.backend.close())
+    }
+
+    pub(crate) fn handle_event(&self, event: EventType) {
+        trace::event(&event);
+        self.stats.record_event(&event);
+        if let EventType::BleGap(_, GapEvent::AdvertisingReport(report)) = &event {
+            if let Some(pcap) = self.pcap.lock().unwrap().as_mut() {
+                let _result = pcap.advertising_report(report);
+            }
+            // The SoftDevice pauses scanning after every report.
+            if self.is_scanning.load(Ordering::Acquire) {
+                let _result = self.gap_scan_start(&GapScanParameters::default());
+            }
+        }
+        if let EventType::BleGap(_, GapEvent::Timeout(source)) = &event {
+            // The SoftDevice has stopped scanning on its own, so it is
+            // neither resumed nor restarted after a recovery.
+            if *source == BLE_GAP_TIMEOUT_SRC_SCAN {
+                self.is_scanning.store(false, Ordering::Release);
+                self.record_scan_parameters(None);
             }
+        }
+
+        self.callback_event.send(event);
+    }
 
-            Ok(adapter)
+    pub(crate) fn handle_traffic(&self, event: &Event) {
+        if let Some(pcap) = self.pcap.lock().unwrap().as_mut() {
+            let _result = pcap.event(event);
         }
     }
-}
 
-impl Drop for BleDriver {
-    fn drop(&mut self) {
-        match self.close() {
-            Ok(()) => {}
-            Err(e) => println!("{:?}", e),
+    pub(crate) fn handle_status(self: &Arc<Self>, status: RpcStatus, message: String) {
+        trace::status(status, &message);
+        self.stats.record_status(status);
+        self.callback_event.send(EventType::RpcStatus(status, message));
+        if status == RpcStatus::ResetPerformed && self.resetting.load(Ordering::Acquire) {
+            *self.resets_performed.lock().unwrap() += 1;
+            self.reset_performed.notify_all();
+            return;
         }
-        unsafe {
-            ffi::sd_rpc_adapter_delete(self.adapter);
+        self.check_link_loss(status);
+    }
+
+    pub(crate) fn handle_log(&self, severity: LogSeverity, message: String) {
+        severity.forward(&message);
+        if self.log_events.load(Ordering::Relaxed) {
+            self.callback_event.send(EventType::RpcLog(severity, message));
         }
     }
 }
 
-extern "C" fn sd_rpc_status_handler(
-    adapter: *mut ffi::adapter_t,
-    code: ffi::sd_rpc_app_status_t,
-    message: *const ::std::os::raw::c_char,
-) {
-    /*
-    unsafe {
-        if !(*adapter).user_data.is_null() {
-            let message = CStr::from_ptr(message);
-            let user_data: &mut BleDriver = &mut *((*adapter).user_data as *mut BleDriver);
-            user_data.on_ffi_callback(EventType::RpcStatus(
-                code,
-                message.to_string_lossy().into_owned(),
-            ));
+impl Adapter {
+    fn open(&mut self) -> Result<()> {
+        if !self.is_open {
+            self.core.rpc_open()?;
+            if let Err(e) = self.core.identify_firmware() {
+                let _result = self.core.rpc_close();
+                return Err(e);
+            }
+            self.is_open = true;
         }
+
+        Ok(())
     }
-    */
-}
 
-extern "C" fn sd_rpc_event_handler(adapter: *mut ffi::adapter_t, rpc_event: *mut ffi::ble_evt_t) {
-    unsafe {
-        if !(*adapter).user_data.is_null() {
-            let user_data: &mut BleDriver = &mut *((*adapter).user_data as *mut BleDriver);
-            user_data.handle_ffi_event(rpc_event);
+    fn close(&mut self) -> Result<()> {
+        if self.is_open {
+            self.core.rpc_close()?;
+            self.is_open = false;
+            self.core.is_scanning.store(false, Ordering::Release);
         }
+
+        Ok(())
     }
 }
 
-extern "C" fn sd_rpc_log_handler(
-    adapter: *mut ffi::adapter_t,
-    severity: ffi::sd_rpc_log_severity_t,
-    message: *const ::std::os::raw::c_char,
-) {
-    /*
-    unsafe {
-        if !(*adapter).user_data.is_null() {
-            let message = CStr::from_ptr(message);
-            let user_data: &mut BleDriver = &mut *((*adapter).user_data as *mut BleDriver);
-            user_data.on_ffi_callback(EventType::RpcLog(
-                severity,
-                message.to_string_lossy().to_string(),
-            ));
-        }
+impl Drop for Adapter {
+    fn drop(&mut self) {
+        self.core.stop_recovery();
+        // A failure is reported by the close command's span.
+        let _result = self.close();
     }
-    */
 }
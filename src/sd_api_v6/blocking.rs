@@ -0,0 +1,202 @@
+//! Synchronous facade over an open adapter for programs without an async
+//! runtime.
+//!
+//! ```no_run
+//! use nrf_sd_api::{blocking::BlockingBleDriver, gap::GapScanParameters, BleDriver};
+//! use std::time::Duration;
+//!
+//! let mut driver = BlockingBleDriver::open(BleDriver::new("/dev/ttyACM0").unwrap()).unwrap();
+//! driver.ble_enable().unwrap();
+//! driver.gap_scan_start(&GapScanParameters::default()).unwrap();
+//! while let Ok(Some(event)) = driver.recv_event_timeout(Duration::from_secs(5)) {
+//!     println!("{:?}", event);
+//! }
+//! ```
+
+use crate::{
+    ble::AdapterInfo,
+    gap::{GapConfigRoleCount, GapScanParameters},
+    handle::{settle, BleDriverHandle, EventStream},
+    rpc::{LogSeverity, ResetMode},
+    sd_api_v6::{BleDriver, EventType},
+    Error, Result,
+};
+use std::sync::Arc;
+use std::task::{Context, Poll, Wake, Waker};
+use std::thread::{self, Thread};
+use std::time::{Duration, Instant};
+
+/// Blocking counterpart of `BleDriverHandle` and `EventStream`.
+///
+/// Every call blocks the calling thread. None of the methods may be called
+/// from within an async runtime.
+#[derive(Debug)]
+pub struct BlockingBleDriver {
+    handle: BleDriverHandle,
+    events: EventStream,
+}
+
+/// Wakes the thread blocked in `recv_event_timeout`.
+struct ThreadWaker(Thread);
+
+impl Wake for ThreadWaker {
+    fn wake(self: Arc<Self>) {
+        self.0.unpark();
+    }
+}
+
+impl BlockingBleDriver {
+    /// Opens `driver` and wraps the resulting handle and event stream.
+    pub fn open(driver: BleDriver) -> Result<BlockingBleDriver> {
+        let (handle, events) = driver.open()?;
+        Ok(BlockingBleDriver::new(handle, events))
+    }
+
+    pub fn new(handle: BleDriverHandle, events: EventStream) -> BlockingBleDriver {
+        BlockingBleDriver { handle, events }
+    }
+
+    /// Returns a clone of the async handle, e.g. to issue commands from
+    /// another thread.
+    pub fn handle(&self) -> BleDriverHandle {
+        self.handle.clone()
+    }
+
+    /// Blocks until the next event is received. Returns `None` once the
+    /// driver has been dropped.
+    pub fn recv_event(&mut self) -> Option<EventType> {
+        self.poll_events(None).unwrap_or(None)
+    }
+
+    /// Blocks until the next event is received or `timeout` expires.
+    ///
+    /// Returns `Ok(None)` on timeout and `Error::DriverClosed` once the
+    /// driver has been dropped.
+    pub fn recv_event_timeout(&mut self, timeout: Duration) -> Result<Option<EventType>> {
+        match self.poll_events(Some(Instant::now() + timeout)) {
+            Some(Some(event)) => Ok(Some(event)),
+            Some(None) => Err(Error::DriverClosed),
+            None => Ok(None),
+        }
+    }
+
+    /// Polls the event stream, parking the current thread between wake-ups.
+    /// Returns `None` if `deadline` passes before the stream is ready.
+    fn poll_events(&mut self, deadline: Option<Instant>) -> Option<Option<EventType>> {
+        let waker = Waker::from(Arc::new(ThreadWaker(thread::current())));
+        let mut cx = Context::from_waker(&waker);
+
+        loop {
+            if let Poll::Ready(event) = self.events.poll_recv(&mut cx) {
+                return Some(event);
+            }
+
+            match deadline {
+                Some(deadline) => {
+                    let now = Instant::now();
+                    if now >= deadline {
+                        return None;
+                    }
+                    thread::park_timeout(deadline - now);
+                }
+                None => thread::park(),
+            }
+        }
+    }
+
+    pub fn adapter_info(&self) -> Option<AdapterInfo> {
+        self.handle.adapter_info()
+    }
+
+    pub fn close(&self) -> Result<()> {
+        self.handle.transition_blocking(|driver| settle(driver.close()))
+    }
+
+    pub fn reset(&self, mode: ResetMode) -> Result<()> {
+        self.handle.transition_blocking(move |driver| settle(driver.reset(mode)))
+    }
+
+    pub fn ble_enable(&self) -> Result<()> {
+        self.handle.transition_blocking(|driver| settle(driver.ble_enable()))
+    }
+
+    pub fn ble_version_get(&self) -> Result<AdapterInfo> {
+        self.handle.execute_blocking(|driver| driver.ble_version_get())
+    }
+
+    pub fn user_mem_reply(&self, connection_handle: u16, length: Option<u16>) -> Result<()> {
+        self.handle
+            .execute_blocking(move |driver| driver.enabled()?.user_mem_reply(connection_handle, length))
+    }
+
+    pub fn set_log_severity_filter(&self, severity: LogSeverity) -> Result<()> {
+        self.handle
+            .execute_blocking(move |driver| driver.set_log_severity_filter(severity))
+    }
+
+    pub fn set_log_events(&self, enabled: bool) -> Result<()> {
+        self.handle.execute_blocking(move |driver| {
+            driver.set_log_events(enabled);
+            Ok(())
+        })
+    }
+
+    pub fn set_auto_recover(&self, enabled: bool) -> Result<()> {
+        self.handle.execute_blocking(move |driver| {
+            driver.set_auto_recover(enabled);
+            Ok(())
+        })
+    }
+
+    pub fn gap_set_connection_config(
+        &self,
+        connection_tag: u8,
+        connection_count: u8,
+        event_length: u16,
+    ) -> Result<()> {
+        self.handle.execute_blocking(move |driver| {
+            driver.configuring()?.gap_set_connection_config(connection_tag, connection_count, event_length)
+        })
+    }
+
+    pub fn gap_set_role_count_config(&self, config: &GapConfigRoleCount) -> Result<()> {
+        let config = config.clone();
+        self.handle
+            .execute_blocking(move |driver| driver.configuring()?.gap_set_role_count_config(&config))
+    }
+
+    pub fn gap_scan_start(&self, scan_parameters: &GapScanParameters) -> Result<()> {
+        let scan_parameters = scan_parameters.clone();
+        self.handle
+            .execute_blocking(move |driver| driver.enabled()?.gap_scan_start(&scan_parameters))
+    }
+
+    pub fn gap_scan_stop(&self) -> Result<()> {
+        self.handle.execute_blocking(|driver| driver.enabled()?.gap_scan_stop())
+    }
+
+    pub fn gatt_set_connection_config(&self, connection_tag: u8, att_mtu: u16) -> Result<()> {
+        self.handle
+            .execute_blocking(move |driver| driver.configuring()?.gatt_set_connection_config(connection_tag, att_mtu))
+    }
+
+    pub fn gattc_set_connection_config(
+        &self,
+        connection_tag: u8,
+        write_cmd_tx_queue_size: u8,
+    ) -> Result<()> {
+        self.handle.execute_blocking(move |driver| {
+            driver.configuring()?.gattc_set_connection_config(connection_tag, write_cmd_tx_queue_size)
+        })
+    }
+
+    pub fn gatts_set_connection_config(
+        &self,
+        connection_tag: u8,
+        hvn_tx_queue_size: u8,
+    ) -> Result<()> {
+        self.handle.execute_blocking(move |driver| {
+            driver.configuring()?.gatts_set_connection_config(connection_tag, hvn_tx_queue_size)
+        })
+    }
+}
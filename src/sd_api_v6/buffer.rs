@@ -0,0 +1,163 @@
+//! Memory the SoftDevice keeps pointers into.
+//!
+//! Some calls hand the SoftDevice a buffer it goes on using after the call
+//! has returned: the scan report buffer and the user memory of queued
+//! writes. A backend lends such buffers to its `BufferRegistry`, which owns
+//! them until the SoftDevice releases them again (scan timeout or stop,
+//! `BLE_EVT_USER_MEM_RELEASE`, disconnection) or the backend is dropped.
+
+use nrf_ble_driver_sys::ffi;
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Mutex;
+
+/// What a buffer lent to the SoftDevice is used for. There is at most one
+/// buffer per use.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub(crate) enum BufferUse {
+    /// Buffer advertising reports are written into while scanning.
+    ScanReport,
+    /// User memory of queued (long) writes on a connection.
+    UserMemory { connection_handle: u16 },
+}
+
+/// A zeroed heap buffer together with the descriptors handed to the
+/// SoftDevice. Neither moves when the buffer is moved, so the pointers stay
+/// valid for as long as the buffer is alive.
+pub(crate) struct SoftDeviceBuffer {
+    data: Box<[u8]>,
+    ble_data: Box<ffi::ble_data_t>,
+    mem_block: Box<ffi::ble_user_mem_block_t>,
+}
+
+// SAFETY: the descriptors only point into `data`, which is owned by the
+// buffer and only written by the SoftDevice through pc-ble-driver.
+unsafe impl Send for SoftDeviceBuffer {}
+
+impl SoftDeviceBuffer {
+    pub(crate) fn new(len: u16) -> SoftDeviceBuffer {
+        let mut data = vec![0; len as usize].into_boxed_slice();
+        let p_data = data.as_mut_ptr();
+        SoftDeviceBuffer {
+            data,
+            ble_data: Box::new(ffi::ble_data_t { p_data, len }),
+            mem_block: Box::new(ffi::ble_user_mem_block_t { p_mem: p_data, len }),
+        }
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.data.len()
+    }
+
+    pub(crate) fn ble_data(&self) -> *const ffi::ble_data_t {
+        &*self.ble_data
+    }
+
+    pub(crate) fn mem_block(&self) -> *const ffi::ble_user_mem_block_t {
+        &*self.mem_block
+    }
+}
+
+impl fmt::Debug for SoftDeviceBuffer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SoftDeviceBuffer")
+            .field("len", &self.len())
+            .finish()
+    }
+}
+
+/// The buffers a backend has lent to the SoftDevice.
+#[derive(Debug, Default)]
+pub(crate) struct BufferRegistry {
+    buffers: Mutex<HashMap<BufferUse, SoftDeviceBuffer>>,
+}
+
+impl BufferRegistry {
+    pub(crate) fn new() -> BufferRegistry {
+        BufferRegistry::default()
+    }
+
+    /// Keeps `buffer` alive until `usage` is released. Returns the buffer
+    /// previously lent for `usage`, which the caller must keep alive until
+    /// the SoftDevice has accepted the new one.
+    pub(crate) fn lend(&self, usage: BufferUse, buffer: SoftDeviceBuffer) -> Option<SoftDeviceBuffer> {
+        self.buffers.lock().unwrap().insert(usage, buffer)
+    }
+
+    /// Calls `f` with the buffer lent for `usage`, which is not released
+    /// while `f` runs.
+    pub(crate) fn with<T>(&self, usage: BufferUse, f: impl FnOnce(&SoftDeviceBuffer) -> T) -> Option<T> {
+        self.buffers.lock().unwrap().get(&usage).map(f)
+    }
+
+    /// Frees the buffer lent for `usage`. Returns true if there was one.
+    pub(crate) fn release(&self, usage: BufferUse) -> bool {
+        self.buffers.lock().unwrap().remove(&usage).is_some()
+    }
+
+    /// Frees the buffers belonging to the connection `connection_handle`.
+    pub(crate) fn release_connection(&self, connection_handle: u16) {
+        self.buffers.lock().unwrap().retain(|usage, _| {
+            *usage != BufferUse::UserMemory { connection_handle }
+        });
+    }
+
+    /// Frees every buffer, e.g. after the firmware has been reset.
+    pub(crate) fn release_all(&self) {
+        self.buffers.lock().unwrap().clear();
+    }
+
+    /// Frees the buffers the SoftDevice releases with `ble_event`.
+    ///
+    /// # Safety
+    ///
+    /// `ble_event` must point to a valid event buffer.
+    pub(crate) unsafe fn on_event(&self, ble_event: *const ffi::ble_evt_t) {
+        let evt = &(*ble_event).evt;
+        match (*ble_event).header.evt_id as u32 {
+            ffi::BLE_GAP_EVTS_BLE_GAP_EVT_TIMEOUT
+                if evt.gap_evt.params.timeout.src as u32 == ffi::BLE_GAP_TIMEOUT_SRC_SCAN =>
+            {
+                self.release(BufferUse::ScanReport);
+            }
+            ffi::BLE_GAP_EVTS_BLE_GAP_EVT_DISCONNECTED => {
+                self.release_connection(evt.gap_evt.conn_handle);
+            }
+            ffi::BLE_COMMON_EVTS_BLE_EVT_USER_MEM_RELEASE => {
+                let connection_handle = evt.common_evt.conn_handle;
+                self.release(BufferUse::UserMemory { connection_handle });
+            }
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn buffers_live_until_released() {
+        let registry = BufferRegistry::new();
+        let buffer = SoftDeviceBuffer::new(ffi::BLE_GAP_SCAN_BUFFER_EXTENDED_MAX as u16);
+        let ble_data = buffer.ble_data();
+        registry.lend(BufferUse::ScanReport, buffer);
+        registry.lend(BufferUse::UserMemory { connection_handle: 1 }, SoftDeviceBuffer::new(64));
+        registry.lend(BufferUse::UserMemory { connection_handle: 2 }, SoftDeviceBuffer::new(64));
+
+        // The descriptor handed out before lending stays valid.
+        assert_eq!(registry.with(BufferUse::ScanReport, SoftDeviceBuffer::ble_data), Some(ble_data));
+        assert_eq!(unsafe { (*ble_data).len } as u32, ffi::BLE_GAP_SCAN_BUFFER_EXTENDED_MAX);
+
+        registry.release_connection(1);
+        assert!(!registry.release(BufferUse::UserMemory { connection_handle: 1 }));
+        assert!(registry.release(BufferUse::ScanReport));
+        assert!(!registry.release(BufferUse::ScanReport));
+        assert!(registry.with(BufferUse::ScanReport, SoftDeviceBuffer::len).is_none());
+        // Lending again hands back the buffer lent before.
+        let previous = registry.lend(BufferUse::UserMemory { connection_handle: 2 }, SoftDeviceBuffer::new(32));
+        assert_eq!(previous.map(|buffer| buffer.len()), Some(64));
+        registry.release_all();
+        assert!(!registry.release(BufferUse::UserMemory { connection_handle: 2 }));
+    }
+}
@@ -0,0 +1,422 @@
+use crate::{backend::BleBackend, event_queue::OverflowPolicy, pcap::PcapWriter, h5::H5Config, rpc::LogSeverity, sd_api_v6::BleDriver, Error, Result};
+#[cfg(feature = "pc-ble-driver")]
+use crate::{capture::CaptureWriter, pc_ble_driver::PcBleDriverBackend};
+#[cfg(feature = "pc-ble-driver")]
+use nrf_ble_driver_sys::ffi;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Baud rates supported by the connectivity firmware UART.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum BaudRate {
+    Baud115200,
+    Baud230400,
+    Baud460800,
+    Baud921600,
+    #[default]
+    Baud1000000,
+}
+
+impl BaudRate {
+    pub fn as_u32(&self) -> u32 {
+        match self {
+            BaudRate::Baud115200 => 115_200,
+            BaudRate::Baud230400 => 230_400,
+            BaudRate::Baud460800 => 460_800,
+            BaudRate::Baud921600 => 921_600,
+            BaudRate::Baud1000000 => 1_000_000,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FlowControl {
+    None,
+    Hardware,
+}
+
+#[cfg(feature = "pc-ble-driver")]
+impl FlowControl {
+    fn to_ffi(self) -> ffi::sd_rpc_flow_control_t {
+        match self {
+            FlowControl::None => ffi::sd_rpc_flow_control_t_SD_RPC_FLOW_CONTROL_NONE,
+            FlowControl::Hardware => ffi::sd_rpc_flow_control_t_SD_RPC_FLOW_CONTROL_HARDWARE,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Parity {
+    None,
+    Even,
+}
+
+#[cfg(feature = "pc-ble-driver")]
+impl Parity {
+    fn to_ffi(self) -> ffi::sd_rpc_parity_t {
+        match self {
+            Parity::None => ffi::sd_rpc_parity_t_SD_RPC_PARITY_NONE,
+            Parity::Even => ffi::sd_rpc_parity_t_SD_RPC_PARITY_EVEN,
+        }
+    }
+}
+
+/// Implementation of the three-wire (H5) data link layer and the
+/// serialization transport on top of it. pc-ble-driver is used by
+/// default if the `pc-ble-driver` feature is enabled.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum LinkLayer {
+    /// The transport built into pc-ble-driver.
+    #[cfg(feature = "pc-ble-driver")]
+    #[default]
+    PcBleDriver,
+    /// `NativeBackend`: the link layer in `crate::h5` carrying commands and
+    /// events encoded by `crate::codec`.
+    #[cfg_attr(not(feature = "pc-ble-driver"), default)]
+    Native,
+}
+
+/// Transport parameters used to create the UART physical layer, the
+/// three-wire data link layer and the transport layer of an adapter.
+#[derive(Debug, Clone)]
+pub struct TransportConfig {
+    pub port_name: String,
+    pub baud_rate: BaudRate,
+    pub flow_control: FlowControl,
+    pub parity: Parity,
+    /// Interval between three-wire retransmissions of unacknowledged packets.
+    pub retransmission_interval: Duration,
+    /// Time to wait for a response from the connectivity firmware.
+    pub response_timeout: Duration,
+    pub link_layer: LinkLayer,
+}
+
+impl TransportConfig {
+    pub fn new(port_name: &str) -> TransportConfig {
+        TransportConfig {
+            port_name: String::from(port_name),
+            baud_rate: BaudRate::default(),
+            flow_control: FlowControl::None,
+            parity: Parity::None,
+            retransmission_interval: Duration::from_millis(250),
+            response_timeout: Duration::from_millis(1500),
+            link_layer: LinkLayer::default(),
+        }
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        if self.port_name.is_empty() {
+            return Err(Error::InvalidConfiguration("port name is empty"));
+        }
+        if self.port_name.contains('\0') {
+            return Err(Error::InvalidConfiguration("port name contains a nul byte"));
+        }
+        // pc-ble-driver takes both in whole milliseconds.
+        if self.retransmission_interval < Duration::from_millis(1) {
+            return Err(Error::InvalidConfiguration(
+                "retransmission interval must be at least 1 ms",
+            ));
+        }
+        if self.response_timeout < Duration::from_millis(1) {
+            return Err(Error::InvalidConfiguration("response timeout must be at least 1 ms"));
+        }
+        if self.retransmission_interval >= self.response_timeout {
+            return Err(Error::InvalidConfiguration(
+                "retransmission interval must be shorter than the response timeout",
+            ));
+        }
+        if self.response_timeout.as_millis() > u32::MAX as u128 {
+            return Err(Error::InvalidConfiguration("response timeout is too long"));
+        }
+
+        Ok(())
+    }
+
+    /// Link layer parameters for the native three-wire implementation.
+    pub fn h5_config(&self) -> H5Config {
+        H5Config {
+            retransmission_interval: self.retransmission_interval,
+            link_establishment_timeout: self.response_timeout,
+            ..H5Config::default()
+        }
+    }
+
+    #[cfg(feature = "pc-ble-driver")]
+    pub(crate) fn to_ffi(
+        &self,
+    ) -> (u32, ffi::sd_rpc_flow_control_t, ffi::sd_rpc_parity_t, u32, u32) {
+        (
+            self.baud_rate.as_u32(),
+            self.flow_control.to_ffi(),
+            self.parity.to_ffi(),
+            self.retransmission_interval.as_millis() as u32,
+            self.response_timeout.as_millis() as u32,
+        )
+    }
+}
+
+/// Builds a `BleDriver` with non-default transport parameters.
+///
+/// ```no_run
+/// use nrf_sd_api::builder::{BaudRate, BleDriverBuilder, FlowControl};
+/// use std::time::Duration;
+///
+/// let driver = BleDriverBuilder::new("/dev/ttyACM0")
+///     .baud_rate(BaudRate::Baud115200)
+///     .flow_control(FlowControl::Hardware)
+///     .response_timeout(Duration::from_millis(3000))
+///     .build()
+///     .unwrap();
+/// ```
+#[derive(Debug, Clone)]
+pub struct BleDriverBuilder {
+    config: TransportConfig,
+    log_severity_filter: Option<LogSeverity>,
+    log_events: bool,
+    auto_recover: bool,
+    event_queue_capacity: Option<usize>,
+    overflow_policy: OverflowPolicy,
+    backend: Option<Arc<dyn BleBackend>>,
+    #[cfg(feature = "pc-ble-driver")]
+    capture: Option<PathBuf>,
+    pcap: Option<PathBuf>,
+}
+
+impl BleDriverBuilder {
+    pub fn new(port_name: &str) -> BleDriverBuilder {
+        BleDriverBuilder {
+            config: TransportConfig::new(port_name),
+            log_severity_filter: None,
+            log_events: false,
+            auto_recover: false,
+            event_queue_capacity: None,
+            overflow_policy: OverflowPolicy::default(),
+            backend: None,
+            #[cfg(feature = "pc-ble-driver")]
+            capture: None,
+            pcap: None,
+        }
+    }
+
+    pub fn baud_rate(mut self, baud_rate: BaudRate) -> Self {
+        self.config.baud_rate = baud_rate;
+        self
+    }
+
+    pub fn flow_control(mut self, flow_control: FlowControl) -> Self {
+        self.config.flow_control = flow_control;
+        self
+    }
+
+    pub fn parity(mut self, parity: Parity) -> Self {
+        self.config.parity = parity;
+        self
+    }
+
+    pub fn retransmission_interval(mut self, interval: Duration) -> Self {
+        self.config.retransmission_interval = interval;
+        self
+    }
+
+    pub fn response_timeout(mut self, timeout: Duration) -> Self {
+        self.config.response_timeout = timeout;
+        self
+    }
+
+    /// Talks to the firmware through `link_layer` instead of pc-ble-driver.
+    pub fn link_layer(mut self, link_layer: LinkLayer) -> Self {
+        self.config.link_layer = link_layer;
+        self
+    }
+
+    /// Lowest severity of driver log messages to report.
+    pub fn log_severity_filter(mut self, severity: LogSeverity) -> Self {
+        self.log_severity_filter = Some(severity);
+        self
+    }
+
+    /// Deliver driver log messages as `EventType::RpcLog` in addition to
+    /// forwarding them to `log`/`tracing`.
+    pub fn log_events(mut self, enabled: bool) -> Self {
+        self.log_events = enabled;
+        self
+    }
+
+    /// Reopen the transport and restore the SoftDevice configuration
+    /// automatically after a link loss. See `BleDriver::set_auto_recover`.
+    pub fn auto_recover(mut self, enabled: bool) -> Self {
+        self.auto_recover = enabled;
+        self
+    }
+
+    /// Limits the event queue to `capacity` events and applies `policy`
+    /// when it is full. The queue is unbounded by default. A capacity of 0
+    /// is treated as 1, as in `BleDriver::set_event_queue_capacity`.
+    pub fn event_queue_capacity(mut self, capacity: usize, policy: OverflowPolicy) -> Self {
+        self.event_queue_capacity = Some(capacity);
+        self.overflow_policy = policy;
+        self
+    }
+
+    /// Drives `backend` instead of opening the serial port with the selected
+    /// link layer, e.g. a `SimulatedBackend` in tests. The transport
+    /// parameters are then only reported in `AdapterInfo`.
+    pub fn backend(mut self, backend: Arc<dyn BleBackend>) -> Self {
+        self.backend = Some(backend);
+        self
+    }
+
+    /// Records everything received from the adapter to a capture file at
+    /// `path`, to be replayed later with `capture::ReplayBackend`. Only
+    /// available with the pc-ble-driver backend.
+    #[cfg(feature = "pc-ble-driver")]
+    pub fn capture<P: AsRef<Path>>(mut self, path: P) -> Self {
+        self.capture = Some(path.as_ref().to_path_buf());
+        self
+    }
+
+    /// Writes the advertising reports and GATT traffic seen by the driver
+    /// to a pcap file at `path`, for inspection in Wireshark.
+    pub fn pcap<P: AsRef<Path>>(mut self, path: P) -> Self {
+        self.pcap = Some(path.as_ref().to_path_buf());
+        self
+    }
+
+    pub fn build(self) -> Result<BleDriver> {
+        self.config.validate()?;
+        #[cfg(feature = "pc-ble-driver")]
+        let mut driver = match (self.backend, self.capture) {
+            (Some(backend), None) => BleDriver::with_backend(self.config, backend),
+            (None, None) => BleDriver::with_config(self.config)?,
+            (None, Some(path)) if self.config.link_layer == LinkLayer::PcBleDriver => {
+                let mut backend = PcBleDriverBackend::new(&self.config)?;
+                backend.set_capture(CaptureWriter::create(path)?);
+                BleDriver::with_backend(self.config, Arc::new(backend))
+            }
+            (_, Some(_)) => {
+                return Err(Error::InvalidConfiguration(
+                    "capture requires the pc-ble-driver backend",
+                ))
+            }
+        };
+        #[cfg(not(feature = "pc-ble-driver"))]
+        let mut driver = match self.backend {
+            Some(backend) => BleDriver::with_backend(self.config, backend),
+            None => BleDriver::with_config(self.config)?,
+        };
+        driver.set_log_events(self.log_events);
+        driver.set_auto_recover(self.auto_recover);
+        driver.set_event_queue_capacity(self.event_queue_capacity, self.overflow_policy);
+        if let Some(path) = self.pcap {
+            driver.set_pcap(Some(PcapWriter::create(path)?));
+        }
+        if let Some(severity) = self.log_severity_filter {
+            driver.set_log_severity_filter(severity)?;
+        }
+        Ok(driver)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codec::Event;
+    use crate::event_bus::EventFilter;
+    use crate::simulated::SimulatedBackend;
+    use crate::test_support::{simulated_builder, simulated_driver};
+    use crate::EventType;
+
+    fn config() -> TransportConfig {
+        TransportConfig::new("/dev/ttyACM0")
+    }
+
+    fn rejects(config: TransportConfig, reason: &str) {
+        match config.validate() {
+            Err(Error::InvalidConfiguration(message)) => assert_eq!(message, reason),
+            result => panic!("expected {:?} to be rejected, got {:?}", reason, result),
+        }
+    }
+
+    #[test]
+    fn validate_transport_config() {
+        assert!(config().validate().is_ok());
+
+        rejects(TransportConfig::new(""), "port name is empty");
+        rejects(TransportConfig::new("COM\u{0}3"), "port name contains a nul byte");
+        rejects(
+            TransportConfig {
+                retransmission_interval: Duration::ZERO,
+                ..config()
+            },
+            "retransmission interval must be at least 1 ms",
+        );
+        rejects(
+            TransportConfig {
+                retransmission_interval: Duration::from_micros(900),
+                ..config()
+            },
+            "retransmission interval must be at least 1 ms",
+        );
+        rejects(
+            TransportConfig {
+                retransmission_interval: Duration::from_millis(1),
+                response_timeout: Duration::from_micros(999),
+                ..config()
+            },
+            "response timeout must be at least 1 ms",
+        );
+        rejects(
+            TransportConfig {
+                retransmission_interval: Duration::from_millis(1500),
+                ..config()
+            },
+            "retransmission interval must be shorter than the response timeout",
+        );
+        rejects(
+            TransportConfig {
+                response_timeout: Duration::from_millis(u32::MAX as u64 + 1),
+                ..config()
+            },
+            "response timeout is too long",
+        );
+
+        // The shortest accepted values reach pc-ble-driver as 1 and 2 ms.
+        let config = TransportConfig {
+            retransmission_interval: Duration::from_millis(1),
+            response_timeout: Duration::from_millis(2),
+            ..config()
+        };
+        assert!(config.validate().is_ok());
+        #[cfg(feature = "pc-ble-driver")]
+        {
+            let (_, _, _, retransmission_interval, response_timeout) = config.to_ffi();
+            assert_eq!((retransmission_interval, response_timeout), (1, 2));
+        }
+    }
+
+    /// Opens `driver` and checks that its event queues hold a single event.
+    async fn holds_one_event(driver: BleDriver, backend: &SimulatedBackend) {
+        let driver = driver.open_adapter().unwrap();
+        let mut events = driver.subscribe(EventFilter::All);
+        backend.emit(Event::GapDisconnected { connection_handle: 1, reason: 0x13 });
+        backend.emit(Event::GapDisconnected { connection_handle: 2, reason: 0x13 });
+        while driver.event_queue_stats().dropped_events == 0 {
+            std::thread::sleep(Duration::from_millis(1));
+        }
+
+        assert!(matches!(events.recv_timeout(Duration::from_secs(1)).await, Ok(Some(EventType::BleGap(2, _)))));
+        assert_eq!(driver.event_queue_stats().dropped_events, 1);
+    }
+
+    #[tokio::test]
+    async fn zero_event_queue_capacity_holds_one_event() {
+        let (backend, builder) = simulated_builder(SimulatedBackend::new());
+        let driver = builder.event_queue_capacity(0, OverflowPolicy::DropOldest).build().unwrap();
+        holds_one_event(driver, &backend).await;
+
+        let (backend, mut driver) = simulated_driver(SimulatedBackend::new());
+        driver.set_event_queue_capacity(Some(0), OverflowPolicy::DropOldest);
+        holds_one_event(driver, &backend).await;
+    }
+}
@@ -0,0 +1,328 @@
+//! Recording of the raw events received from pc-ble-driver and their
+//! deterministic replay.
+//!
+//! A capture file starts with `CAPTURE_MAGIC` followed by records of
+//! `kind: u8`, `timestamp: u64` (microseconds since the capture started)
+//! and a kind specific body, all little-endian:
+//!
+//! - event: `length: u16`, the raw `ble_evt_t`, `data_length: u16` and the
+//!   advertising data an advertising report points to;
+//! - status: `code: u32`, `length: u16` and the UTF-8 message;
+//! - version: `version_number: u8`, `company_id: u16`, `subversion_number: u16`.
+//!
+//! Raw events use the memory layout of the `ble_evt_t` this crate was built
+//! against, so captures are only portable between builds using the same
+//! nrf-ble-driver-sys.
+
+use crate::{backend::{BleBackend, EventSink}, ble::NORDIC_COMPANY_ID, codec::{Config, Reader, Writer}, gap::GapScanParameters, pc_ble_driver::forward_event, rpc::{LogSeverity, ResetMode, RpcStatus}, Error, Result};
+use nrf_ble_driver_sys::ffi;
+use std::fs::File;
+use std::io::{Read, Write};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+use std::{mem, ptr, slice};
+
+pub const CAPTURE_MAGIC: [u8; 8] = *b"NRFSDCP1";
+
+const RECORD_EVENT: u8 = 0;
+const RECORD_STATUS: u8 = 1;
+const RECORD_VERSION: u8 = 2;
+
+/// Appends timestamped records to a capture file.
+#[derive(Debug)]
+pub struct CaptureWriter {
+    file: File,
+    start: Instant,
+}
+
+impl CaptureWriter {
+    pub fn create<P: AsRef<Path>>(path: P) -> Result<CaptureWriter> {
+        let mut file = File::create(path).map_err(Error::Io)?;
+        file.write_all(&CAPTURE_MAGIC).map_err(Error::Io)?;
+        Ok(CaptureWriter {
+            file,
+            start: Instant::now(),
+        })
+    }
+
+    fn record(&mut self, kind: u8, body: impl FnOnce(&mut Writer)) -> Result<()> {
+        let timestamp = self.start.elapsed().as_micros() as u64;
+        let mut writer = Writer::new();
+        writer.u8(kind).u64(timestamp);
+        body(&mut writer);
+        // One write per record, so a crash loses at most the last one.
+        self.file.write_all(&writer.finish()).map_err(Error::Io)
+    }
+
+    /// Records the event pc-ble-driver passed to the event handler.
+    ///
+    /// # Safety
+    ///
+    /// `ble_event` must point to a valid event buffer of at least
+    /// `size_of::<ble_evt_t>()` and `header.evt_len` bytes.
+    pub(crate) unsafe fn event(&mut self, ble_event: *const ffi::ble_evt_t) -> Result<()> {
+        let length = ((*ble_event).header.evt_len as usize).max(mem::size_of::<ffi::ble_evt_t>());
+        let raw = slice::from_raw_parts(ble_event as *const u8, length);
+        let data = match (*ble_event).header.evt_id as u32 {
+            ffi::BLE_GAP_EVTS_BLE_GAP_EVT_ADV_REPORT => {
+                let data = &(*ble_event).evt.gap_evt.params.adv_report.data;
+                if data.p_data.is_null() {
+                    &[][..]
+                } else {
+                    slice::from_raw_parts(data.p_data, data.len as usize)
+                }
+            }
+            _ => &[][..],
+        };
+
+        self.record(RECORD_EVENT, |writer| {
+            writer.u16(length as u16).bytes(raw).u16(data.len() as u16).bytes(data);
+        })
+    }
+
+    pub(crate) fn status(&mut self, code: u32, message: &str) -> Result<()> {
+        self.record(RECORD_STATUS, |writer| {
+            writer.u32(code).u16(message.len() as u16).bytes(message.as_bytes());
+        })
+    }
+
+    pub(crate) fn version(&mut self, version: (u8, u16, u16)) -> Result<()> {
+        self.record(RECORD_VERSION, |writer| {
+            writer.u8(version.0).u16(version.1).u16(version.2);
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Record {
+    Event { raw: Vec<u8>, data: Vec<u8> },
+    Status { code: u32, message: String },
+    Version((u8, u16, u16)),
+}
+
+/// How fast `ReplayBackend` replays a capture.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ReplaySpeed {
+    /// With the original delays between records.
+    Original,
+    /// With the original delays divided by the given factor.
+    Accelerated(f64),
+    /// Without any delay.
+    Unthrottled,
+}
+
+/// A `BleBackend` that feeds a capture file back through the same event
+/// decoding as `PcBleDriverBackend`.
+///
+/// Playback starts when the driver is opened and runs once. Commands
+/// succeed without effect; `sd_ble_version_get` reports the version
+/// recorded in the capture.
+#[derive(Debug)]
+pub struct ReplayBackend {
+    records: Vec<(Duration, Record)>,
+    speed: ReplaySpeed,
+    started: AtomicBool,
+    playback: Mutex<Option<thread::JoinHandle<()>>>,
+    events: Mutex<Option<EventSink>>,
+}
+
+impl ReplayBackend {
+    pub fn open<P: AsRef<Path>>(path: P, speed: ReplaySpeed) -> Result<ReplayBackend> {
+        let mut contents = Vec::new();
+        File::open(path)
+            .and_then(|mut file| file.read_to_end(&mut contents))
+            .map_err(Error::Io)?;
+        ReplayBackend::from_bytes(&contents, speed)
+    }
+
+    pub fn from_bytes(contents: &[u8], speed: ReplaySpeed) -> Result<ReplayBackend> {
+        let mut reader = Reader::new(contents);
+        if reader.bytes(CAPTURE_MAGIC.len())? != CAPTURE_MAGIC {
+            return Err(Error::InvalidPacket("not a capture file"));
+        }
+
+        let mut records = Vec::new();
+        while !reader.remaining().is_empty() {
+            let kind = reader.u8()?;
+            let timestamp = reader.u64()?;
+            let record = match kind {
+                RECORD_EVENT => {
+                    let length = reader.u16()? as usize;
+                    if length < mem::size_of::<ffi::ble_evt_t>() {
+                        return Err(Error::InvalidPacket("captured event is truncated"));
+                    }
+                    let raw = reader.bytes(length)?.to_vec();
+                    let data_length = reader.u16()? as usize;
+                    let data = reader.bytes(data_length)?.to_vec();
+                    Record::Event { raw, data }
+                }
+                RECORD_STATUS => {
+                    let code = reader.u32()?;
+                    let length = reader.u16()? as usize;
+                    let message = String::from_utf8_lossy(reader.bytes(length)?).into_owned();
+                    Record::Status { code, message }
+                }
+                RECORD_VERSION => Record::Version((reader.u8()?, reader.u16()?, reader.u16()?)),
+                _ => return Err(Error::InvalidPacket("unknown capture record")),
+            };
+            records.push((Duration::from_micros(timestamp), record));
+        }
+
+        Ok(ReplayBackend {
+            records,
+            speed,
+            started: AtomicBool::new(false),
+            playback: Mutex::new(None),
+            events: Mutex::new(None),
+        })
+    }
+
+    /// Waits until every record has been replayed.
+    pub fn wait(&self) {
+        let playback = self.playback.lock().unwrap().take();
+        if let Some(playback) = playback {
+            let _result = playback.join();
+        }
+    }
+}
+
+impl BleBackend for ReplayBackend {
+    fn open(&self, events: EventSink) -> Result<()> {
+        *self.events.lock().unwrap() = Some(events.clone());
+        // Reopening, e.g. during recovery, does not restart playback.
+        if self.started.swap(true, Ordering::AcqRel) {
+            return Ok(());
+        }
+
+        let records = self.records.clone();
+        let speed = self.speed;
+        let playback = thread::spawn(move || replay(records, speed, events));
+        *self.playback.lock().unwrap() = Some(playback);
+        Ok(())
+    }
+
+    fn close(&self) -> Result<()> {
+        Ok(())
+    }
+
+    fn set_log_severity_filter(&self, _severity: LogSeverity) -> Result<()> {
+        Ok(())
+    }
+
+    fn conn_reset(&self, _mode: ResetMode) -> Result<()> {
+        // Playback goes on, only the completion of the reset is reported.
+        if let Some(events) = &*self.events.lock().unwrap() {
+            events.status(RpcStatus::ResetPerformed, String::from("Reset performed"));
+        }
+        Ok(())
+    }
+
+    fn version_get(&self) -> Result<(u8, u16, u16)> {
+        let version = self.records.iter().find_map(|(_, record)| match record {
+            Record::Version(version) => Some(*version),
+            _ => None,
+        });
+        Ok(version.unwrap_or((9, NORDIC_COMPANY_ID, 0x00b6)))
+    }
+
+    fn cfg_set(&self, _config: &Config) -> Result<()> {
+        Ok(())
+    }
+
+    fn ble_enable(&self) -> Result<()> {
+        Ok(())
+    }
+
+    fn gap_scan_start(&self, _parameters: Option<&GapScanParameters>) -> Result<()> {
+        Ok(())
+    }
+
+    fn gap_scan_stop(&self) -> Result<()> {
+        Ok(())
+    }
+
+    fn user_mem_reply(&self, _connection_handle: u16, _length: Option<u16>) -> Result<()> {
+        Ok(())
+    }
+}
+
+fn replay(records: Vec<(Duration, Record)>, speed: ReplaySpeed, events: EventSink) {
+    let start = Instant::now();
+
+    for (timestamp, record) in records {
+        let due = match speed {
+            ReplaySpeed::Original => Some(timestamp),
+            ReplaySpeed::Accelerated(factor) => Some(timestamp.div_f64(factor)),
+            ReplaySpeed::Unthrottled => None,
+        };
+        if let Some(due) = due {
+            if let Some(delay) = due.checked_sub(start.elapsed()) {
+                thread::sleep(delay);
+            }
+        }
+
+        match record {
+            Record::Event { raw, mut data } => unsafe {
+                // Copy into a buffer aligned for ble_evt_t and point the
+                // advertising report at the captured data.
+                let mut buffer = vec![0u64; raw.len().div_ceil(mem::size_of::<u64>())];
+                let ble_event = buffer.as_mut_ptr() as *mut ffi::ble_evt_t;
+                ptr::copy_nonoverlapping(raw.as_ptr(), ble_event as *mut u8, raw.len());
+                if (*ble_event).header.evt_id as u32 == ffi::BLE_GAP_EVTS_BLE_GAP_EVT_ADV_REPORT {
+                    let report_data = &mut (*ble_event).evt.gap_evt.params.adv_report.data;
+                    report_data.p_data = data.as_mut_ptr();
+                    report_data.len = data.len() as u16;
+                }
+                forward_event(&events, ble_event);
+            },
+            Record::Status { code, message } => {
+                events.status(RpcStatus::from(code), message);
+            }
+            Record::Version(_) => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{blocking::BlockingBleDriver, builder::BleDriverBuilder, gap::GapEvent, EventType};
+    use std::sync::Arc;
+
+    #[test]
+    fn captured_advertising_report_is_replayed() {
+        let path = std::env::temp_dir().join(format!("nrf-sd-api-{}.cap", std::process::id()));
+        let mut capture = CaptureWriter::create(&path).unwrap();
+        let mut advertising_data = [0x02, 0x01, 0x06];
+        unsafe {
+            let mut ble_event: ffi::ble_evt_t = mem::zeroed();
+            ble_event.header.evt_id = ffi::BLE_GAP_EVTS_BLE_GAP_EVT_ADV_REPORT as u16;
+            ble_event.evt.gap_evt.conn_handle = ffi::BLE_CONN_HANDLE_INVALID as u16;
+            ble_event.evt.gap_evt.params.adv_report.rssi = -42;
+            ble_event.evt.gap_evt.params.adv_report.data = ffi::ble_data_t {
+                p_data: advertising_data.as_mut_ptr(),
+                len: advertising_data.len() as u16,
+            };
+            capture.event(&ble_event).unwrap();
+        }
+        capture.version((9, NORDIC_COMPANY_ID, 0x00a8)).unwrap();
+        drop(capture);
+
+        let backend = Arc::new(ReplayBackend::open(&path, ReplaySpeed::Unthrottled).unwrap());
+        std::fs::remove_file(&path).unwrap();
+        let driver = BleDriverBuilder::new("replay").backend(backend).build().unwrap();
+        let mut driver = BlockingBleDriver::open(driver).unwrap();
+        assert_eq!(driver.adapter_info().unwrap().subversion_number, 0x00a8);
+
+        match driver.recv_event_timeout(Duration::from_secs(1)).unwrap() {
+            Some(EventType::BleGap(_, GapEvent::AdvertisingReport(report))) => {
+                assert_eq!(report.rssi, -42);
+                assert_eq!(&report.data[..], advertising_data);
+            }
+            other => panic!("unexpected event {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,294 @@
+use crate::{
+    event_queue::{DropCounters, EventQueue, EventQueueStats, EventReceiver, OverflowPolicy},
+    sd_api_v6::EventType,
+};
+use std::fmt;
+use std::sync::{Arc, Mutex};
+
+/// Broad category of an `EventType`, used to filter subscriptions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum EventCategory {
+    Common,
+    Gap,
+    GattClient,
+    GattServer,
+    L2cap,
+    /// Status and log messages from the pc-ble-driver transport.
+    Rpc,
+    /// Events generated by this crate, such as `EventType::Reconnected`.
+    Driver,
+}
+
+/// Selects the events delivered to a subscriber.
+#[derive(Clone)]
+pub enum EventFilter {
+    All,
+    Category(EventCategory),
+    Categories(Vec<EventCategory>),
+    /// Events belonging to the connection with this handle.
+    ConnectionHandle(u16),
+    Predicate(Arc<dyn Fn(&EventType) -> bool + Send + Sync>),
+}
+
+impl EventFilter {
+    pub fn predicate<F>(predicate: F) -> EventFilter
+    where
+        F: Fn(&EventType) -> bool + Send + Sync + 'static,
+    {
+        EventFilter::Predicate(Arc::new(predicate))
+    }
+
+    pub fn matches(&self, event: &EventType) -> bool {
+        match self {
+            EventFilter::All => true,
+            EventFilter::Category(category) => event.category() == *category,
+            EventFilter::Categories(categories) => categories.contains(&event.category()),
+            EventFilter::ConnectionHandle(handle) => event.connection_handle() == Some(*handle),
+            EventFilter::Predicate(predicate) => predicate(event),
+        }
+    }
+}
+
+impl fmt::Debug for EventFilter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EventFilter::All => write!(f, "All"),
+            EventFilter::Category(category) => f.debug_tuple("Category").field(category).finish(),
+            EventFilter::Categories(categories) => {
+                f.debug_tuple("Categories").field(categories).finish()
+            }
+            EventFilter::ConnectionHandle(handle) => {
+                f.debug_tuple("ConnectionHandle").field(handle).finish()
+            }
+            EventFilter::Predicate(_) => write!(f, "Predicate(..)"),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Subscriber {
+    filter: EventFilter,
+    queue: Arc<EventQueue>,
+}
+
+#[derive(Debug)]
+struct BusState {
+    subscribers: Vec<Subscriber>,
+    capacity: Option<usize>,
+    policy: OverflowPolicy,
+    closed: bool,
+}
+
+/// Fans events out to every subscribed `EventStream` whose filter matches.
+#[derive(Debug)]
+pub(crate) struct EventBus {
+    state: Mutex<BusState>,
+    totals: Arc<DropCounters>,
+}
+
+/// Producing side of the event bus, owned by the `DriverCore`. Dropping it
+/// ends every subscribed stream.
+#[derive(Debug)]
+pub(crate) struct EventSender {
+    bus: Arc<EventBus>,
+}
+
+impl EventBus {
+    pub(crate) fn new() -> (EventSender, Arc<EventBus>) {
+        let bus = Arc::new(EventBus {
+            state: Mutex::new(BusState {
+                subscribers: Vec::new(),
+                capacity: None,
+                policy: OverflowPolicy::default(),
+                closed: false,
+            }),
+            totals: Arc::new(DropCounters::default()),
+        });
+
+        (
+            EventSender {
+                bus: Arc::clone(&bus),
+            },
+            bus,
+        )
+    }
+
+    /// Adds a subscriber receiving every event matching `filter` from now on.
+    pub(crate) fn subscribe(&self, filter: EventFilter) -> EventReceiver {
+        let mut state = self.state.lock().unwrap();
+        let queue = EventQueue::new(state.capacity, state.policy, Arc::clone(&self.totals));
+        if state.closed {
+            queue.close();
+        } else {
+            state.subscribers.push(Subscriber {
+                filter,
+                queue: Arc::clone(&queue),
+            });
+        }
+        EventReceiver::new(queue)
+    }
+
+    /// Limits every subscriber queue, present and future, to `capacity`
+    /// events. A queue holds at least one event.
+    pub(crate) fn set_capacity(&self, capacity: Option<usize>, policy: OverflowPolicy) {
+        let capacity = capacity.map(|capacity| capacity.max(1));
+        let mut state = self.state.lock().unwrap();
+        state.capacity = capacity;
+        state.policy = policy;
+        for subscriber in &state.subscribers {
+            subscriber.queue.set_capacity(capacity, policy);
+        }
+    }
+
+    /// Drop counters summed over all subscribers, including those that
+    /// have since been dropped.
+    pub(crate) fn stats(&self) -> EventQueueStats {
+        self.totals.stats()
+    }
+
+    pub(crate) fn reset_stats(&self) {
+        self.totals.reset();
+        let state = self.state.lock().unwrap();
+        for subscriber in &state.subscribers {
+            subscriber.queue.reset_stats();
+        }
+    }
+}
+
+impl EventSender {
+    pub(crate) fn bus(&self) -> &Arc<EventBus> {
+        &self.bus
+    }
+
+    /// Delivers `event` to every matching subscriber. The event is cloned
+    /// only for matching subscribers beyond the first, and advertising
+    /// payloads are reference counted, so clones stay cheap.
+    pub(crate) fn send(&self, event: EventType) {
+        // Collect matching queues first so the bus lock is not held while a
+        // queue with the Block policy waits for its consumer.
+        let queues: Vec<Arc<EventQueue>> = {
+            let mut state = self.bus.state.lock().unwrap();
+            state
+                .subscribers
+                .retain(|subscriber| !subscriber.queue.is_closed());
+            state
+                .subscribers
+                .iter()
+                .filter(|subscriber| subscriber.filter.matches(&event))
+                .map(|subscriber| Arc::clone(&subscriber.queue))
+                .collect()
+        };
+
+        if let Some((last, rest)) = queues.split_last() {
+            for queue in rest {
+                let _result = queue.push(event.clone());
+            }
+            let _result = last.push(event);
+        }
+    }
+}
+
+impl Drop for EventSender {
+    fn drop(&mut self) {
+        let mut state = self.bus.state.lock().unwrap();
+        state.closed = true;
+        for subscriber in state.subscribers.drain(..) {
+            subscriber.queue.close();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codec::Event;
+    use crate::gap::GapEvent;
+    use crate::rpc::RpcStatus;
+    use crate::simulated::SimulatedBackend;
+    use crate::test_support::simulated_builder;
+    use std::future;
+    use std::time::Duration;
+
+    /// Receives everything queued for `receiver` once the sender is gone.
+    async fn received(mut receiver: EventReceiver) -> Vec<String> {
+        let mut events = Vec::new();
+        while let Some(event) = future::poll_fn(|cx| receiver.poll_recv(cx)).await {
+            events.push(format!("{:?}", event));
+        }
+        events
+    }
+
+    #[tokio::test]
+    async fn subscribers_receive_matching_events() {
+        let (sender, bus) = EventBus::new();
+        let all = bus.subscribe(EventFilter::All);
+        let gap = bus.subscribe(EventFilter::Category(EventCategory::Gap));
+        let gatt = bus.subscribe(EventFilter::Categories(vec![
+            EventCategory::GattClient,
+            EventCategory::GattServer,
+        ]));
+        let connection = bus.subscribe(EventFilter::ConnectionHandle(3));
+        let driver = bus.subscribe(EventFilter::predicate(|event| {
+            matches!(event, EventType::Reconnected)
+        }));
+        // A dropped subscriber does not hold up the others.
+        drop(bus.subscribe(EventFilter::All));
+
+        sender.send(EventType::BleGap(3, GapEvent::Connect));
+        sender.send(EventType::BleGattClient(3, 0x30));
+        sender.send(EventType::BleGattServer(4, 0x50));
+        sender.send(EventType::RpcStatus(RpcStatus::ConnectionActive, String::new()));
+        sender.send(EventType::Reconnected);
+        drop(sender);
+
+        assert_eq!(received(all).await.len(), 5);
+        assert_eq!(received(gap).await, ["BleGap(3, Connect)"]);
+        assert_eq!(received(gatt).await, ["BleGattClient(3, 48)", "BleGattServer(4, 80)"]);
+        assert_eq!(received(connection).await, ["BleGap(3, Connect)", "BleGattClient(3, 48)"]);
+        assert_eq!(received(driver).await, ["Reconnected"]);
+
+        // Subscribing to a closed bus yields an ended stream.
+        assert!(received(bus.subscribe(EventFilter::All)).await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn zero_capacity_holds_one_event() {
+        let (sender, bus) = EventBus::new();
+        bus.set_capacity(Some(0), OverflowPolicy::DropOldest);
+        let receiver = bus.subscribe(EventFilter::All);
+        sender.send(EventType::Unknown(1));
+        sender.send(EventType::Unknown(2));
+        drop(sender);
+
+        assert_eq!(received(receiver).await, ["Unknown(2)"]);
+        assert_eq!(bus.stats().dropped_events, 1);
+
+        // Under Block the first event no longer waits for room.
+        let (sender, bus) = EventBus::new();
+        bus.set_capacity(Some(0), OverflowPolicy::Block);
+        let receiver = bus.subscribe(EventFilter::All);
+        sender.send(EventType::Unknown(1));
+        drop(sender);
+        assert_eq!(received(receiver).await, ["Unknown(1)"]);
+    }
+
+    #[tokio::test]
+    async fn open_adapter_leaves_no_stream_undrained() {
+        // Nothing but the stream below is subscribed, so the backend's
+        // delivery thread never waits for a queue nobody reads.
+        let (backend, builder) = simulated_builder(SimulatedBackend::new());
+        let driver = builder.event_queue_capacity(2, OverflowPolicy::Block).build().unwrap();
+        let driver = driver.open_adapter().unwrap();
+        let mut events = driver.subscribe(EventFilter::All);
+
+        for connection_handle in 0..5 {
+            backend.emit(Event::GapDisconnected { connection_handle, reason: 0x13 });
+        }
+        for connection_handle in 0..5 {
+            match events.recv_timeout(Duration::from_secs(1)).await.unwrap() {
+                Some(EventType::BleGap(handle, GapEvent::Disconnect)) => assert_eq!(handle, connection_handle),
+                event => panic!("expected a disconnection, got {:?}", event),
+            }
+        }
+    }
+}
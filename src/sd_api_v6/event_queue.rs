@@ -0,0 +1,355 @@
+use crate::{gap::GapEvent, sd_api_v6::EventType};
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::task::{Context, Poll, Waker};
+
+/// What to do with a new event when the event queue is full.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum OverflowPolicy {
+    /// Discard the oldest queued event to make room for the new one.
+    #[default]
+    DropOldest,
+    /// Discard new advertising reports. All other events are still queued,
+    /// temporarily exceeding the capacity, so no connection or GATT event is lost.
+    DropNewAdvertisingReports,
+    /// Block the driver's callback thread until the consumer makes room.
+    /// This stalls the transport while the queue is full.
+    Block,
+}
+
+/// Counters of events discarded because an event queue was full.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct EventQueueStats {
+    /// Total number of events dropped.
+    pub dropped_events: u64,
+    /// Number of dropped events that were advertising reports.
+    pub dropped_advertising_reports: u64,
+    /// Largest number of events waiting in a queue at once.
+    pub high_water_mark: usize,
+}
+
+impl std::ops::Add for EventQueueStats {
+    type Output = EventQueueStats;
+
+    fn add(self, other: EventQueueStats) -> EventQueueStats {
+        EventQueueStats {
+            dropped_events: self.dropped_events + other.dropped_events,
+            dropped_advertising_reports: self.dropped_advertising_reports
+                + other.dropped_advertising_reports,
+            high_water_mark: self.high_water_mark.max(other.high_water_mark),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct QueueState {
+    events: VecDeque<EventType>,
+    capacity: Option<usize>,
+    policy: OverflowPolicy,
+    waker: Option<Waker>,
+    sender_closed: bool,
+    receiver_closed: bool,
+}
+
+/// Drop counters of a single queue, or of every queue an `EventBus` ever
+/// had, which keep counting after their streams have been dropped.
+#[derive(Debug, Default)]
+pub(crate) struct DropCounters {
+    dropped_events: AtomicU64,
+    dropped_advertising_reports: AtomicU64,
+    high_water_mark: AtomicUsize,
+}
+
+impl DropCounters {
+    pub(crate) fn stats(&self) -> EventQueueStats {
+        EventQueueStats {
+            dropped_events: self.dropped_events.load(Ordering::Relaxed),
+            dropped_advertising_reports: self.dropped_advertising_reports.load(Ordering::Relaxed),
+            high_water_mark: self.high_water_mark.load(Ordering::Relaxed),
+        }
+    }
+
+    pub(crate) fn reset(&self) {
+        self.dropped_events.store(0, Ordering::Relaxed);
+        self.dropped_advertising_reports.store(0, Ordering::Relaxed);
+        self.high_water_mark.store(0, Ordering::Relaxed);
+    }
+
+    fn record_drop(&self, event: &EventType) {
+        self.dropped_events.fetch_add(1, Ordering::Relaxed);
+        if is_advertising_report(event) {
+            self.dropped_advertising_reports.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    fn record_len(&self, len: usize) {
+        self.high_water_mark.fetch_max(len, Ordering::Relaxed);
+    }
+}
+
+/// Queue of events waiting to be consumed by one `EventStream`.
+#[derive(Debug)]
+pub(crate) struct EventQueue {
+    state: Mutex<QueueState>,
+    not_full: Condvar,
+    counters: DropCounters,
+    /// Counters of the bus the queue belongs to.
+    totals: Arc<DropCounters>,
+}
+
+/// Consuming side of an event queue, owned by an `EventStream`.
+#[derive(Debug)]
+pub(crate) struct EventReceiver {
+    queue: Arc<EventQueue>,
+}
+
+fn is_advertising_report(event: &EventType) -> bool {
+    matches!(event, EventType::BleGap(_, GapEvent::AdvertisingReport(_)))
+}
+
+impl EventQueue {
+    pub(crate) fn new(
+        capacity: Option<usize>,
+        policy: OverflowPolicy,
+        totals: Arc<DropCounters>,
+    ) -> Arc<EventQueue> {
+        Arc::new(EventQueue {
+            state: Mutex::new(QueueState {
+                events: VecDeque::new(),
+                capacity: capacity.map(|capacity| capacity.max(1)),
+                policy,
+                waker: None,
+                sender_closed: false,
+                receiver_closed: false,
+            }),
+            not_full: Condvar::new(),
+            counters: DropCounters::default(),
+            totals,
+        })
+    }
+
+    /// Limits the queue to `capacity` events, or removes the limit if `None`.
+    pub(crate) fn set_capacity(&self, capacity: Option<usize>, policy: OverflowPolicy) {
+        let mut state = self.state.lock().unwrap();
+        state.capacity = capacity.map(|capacity| capacity.max(1));
+        state.policy = policy;
+        self.not_full.notify_all();
+    }
+
+    pub(crate) fn stats(&self) -> EventQueueStats {
+        self.counters.stats()
+    }
+
+    pub(crate) fn reset_stats(&self) {
+        self.counters.reset();
+    }
+
+    /// Returns true once the consuming `EventStream` has been dropped.
+    pub(crate) fn is_closed(&self) -> bool {
+        self.state.lock().unwrap().receiver_closed
+    }
+
+    /// Queues `event`, applying the overflow policy if the queue is full.
+    /// Returns the event if the `EventStream` has been dropped.
+    pub(crate) fn push(&self, event: EventType) -> Result<(), EventType> {
+        let mut state = self.state.lock().unwrap();
+        if state.receiver_closed {
+            return Err(event);
+        }
+
+        if let Some(capacity) = state.capacity {
+            if state.events.len() >= capacity {
+                match state.policy {
+                    OverflowPolicy::DropOldest => {
+                        if let Some(oldest) = state.events.pop_front() {
+                            self.record_drop(&oldest);
+                        }
+                    }
+                    OverflowPolicy::DropNewAdvertisingReports => {
+                        if is_advertising_report(&event) {
+                            self.record_drop(&event);
+                            return Ok(());
+                        }
+                    }
+                    OverflowPolicy::Block => {
+                        while !state.receiver_closed
+                            && state.policy == OverflowPolicy::Block
+                            && state.capacity.is_some_and(|capacity| state.events.len() >= capacity)
+                        {
+                            state = self.not_full.wait(state).unwrap();
+                        }
+                        if state.receiver_closed {
+                            return Err(event);
+                        }
+                    }
+                }
+            }
+        }
+
+        state.events.push_back(event);
+        self.counters.record_len(state.events.len());
+        self.totals.record_len(state.events.len());
+        if let Some(waker) = state.waker.take() {
+            waker.wake();
+        }
+        Ok(())
+    }
+
+    /// Marks the producing side as gone so the stream ends once drained.
+    pub(crate) fn close(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.sender_closed = true;
+        if let Some(waker) = state.waker.take() {
+            waker.wake();
+        }
+    }
+
+    fn record_drop(&self, event: &EventType) {
+        self.counters.record_drop(event);
+        self.totals.record_drop(event);
+    }
+}
+
+impl EventReceiver {
+    pub(crate) fn new(queue: Arc<EventQueue>) -> EventReceiver {
+        EventReceiver { queue }
+    }
+
+    pub(crate) fn queue(&self) -> &Arc<EventQueue> {
+        &self.queue
+    }
+
+    pub(crate) fn poll_recv(&mut self, cx: &mut Context<'_>) -> Poll<Option<EventType>> {
+        let mut state = self.queue.state.lock().unwrap();
+        if let Some(event) = state.events.pop_front() {
+            self.queue.not_full.notify_one();
+            return Poll::Ready(Some(event));
+        }
+        if state.sender_closed {
+            return Poll::Ready(None);
+        }
+
+        state.waker = Some(cx.waker().clone());
+        Poll::Pending
+    }
+}
+
+impl Drop for EventReceiver {
+    fn drop(&mut self) {
+        let mut state = self.queue.state.lock().unwrap();
+        state.receiver_closed = true;
+        state.events.clear();
+        self.queue.not_full.notify_all();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support;
+    use std::future;
+    use std::thread;
+    use std::time::Duration;
+
+    fn advertising_report() -> EventType {
+        EventType::BleGap(0xffff, GapEvent::AdvertisingReport(test_support::advertising_report(b"")))
+    }
+
+    fn queue(capacity: usize, policy: OverflowPolicy) -> (Arc<EventQueue>, EventReceiver, Arc<DropCounters>) {
+        let totals = Arc::new(DropCounters::default());
+        let queue = EventQueue::new(Some(capacity), policy, Arc::clone(&totals));
+        (Arc::clone(&queue), EventReceiver::new(queue), totals)
+    }
+
+    async fn recv(receiver: &mut EventReceiver) -> Option<EventType> {
+        future::poll_fn(|cx| receiver.poll_recv(cx)).await
+    }
+
+    #[tokio::test]
+    async fn drop_oldest() {
+        let (queue, mut receiver, _totals) = queue(2, OverflowPolicy::DropOldest);
+        queue.push(EventType::Unknown(1)).unwrap();
+        queue.push(EventType::Unknown(2)).unwrap();
+        queue.push(EventType::Unknown(3)).unwrap();
+
+        assert!(matches!(recv(&mut receiver).await, Some(EventType::Unknown(2))));
+        assert!(matches!(recv(&mut receiver).await, Some(EventType::Unknown(3))));
+        assert_eq!(
+            queue.stats(),
+            EventQueueStats {
+                dropped_events: 1,
+                dropped_advertising_reports: 0,
+                high_water_mark: 2,
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn drop_new_advertising_reports() {
+        let (queue, mut receiver, _totals) = queue(1, OverflowPolicy::DropNewAdvertisingReports);
+        queue.push(advertising_report()).unwrap();
+        queue.push(advertising_report()).unwrap();
+        // Other events exceed the capacity rather than being lost.
+        queue.push(EventType::Reconnected).unwrap();
+
+        assert!(matches!(recv(&mut receiver).await, Some(EventType::BleGap(..))));
+        assert!(matches!(recv(&mut receiver).await, Some(EventType::Reconnected)));
+        assert_eq!(
+            queue.stats(),
+            EventQueueStats {
+                dropped_events: 1,
+                dropped_advertising_reports: 1,
+                high_water_mark: 2,
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn block_waits_for_the_consumer() {
+        let (queue, mut receiver, _totals) = queue(1, OverflowPolicy::Block);
+        queue.push(EventType::Unknown(1)).unwrap();
+        let producer = {
+            let queue = Arc::clone(&queue);
+            thread::spawn(move || queue.push(EventType::Unknown(2)))
+        };
+        thread::sleep(Duration::from_millis(50));
+        assert!(!producer.is_finished());
+
+        assert!(matches!(recv(&mut receiver).await, Some(EventType::Unknown(1))));
+        producer.join().unwrap().unwrap();
+        assert!(matches!(recv(&mut receiver).await, Some(EventType::Unknown(2))));
+        assert_eq!(queue.stats().dropped_events, 0);
+
+        // A blocked producer gives up once the stream is dropped.
+        queue.push(EventType::Unknown(3)).unwrap();
+        let producer = {
+            let queue = Arc::clone(&queue);
+            thread::spawn(move || queue.push(EventType::Unknown(4)))
+        };
+        drop(receiver);
+        assert!(producer.join().unwrap().is_err());
+    }
+
+    #[test]
+    fn totals_outlive_the_queue() {
+        let totals = Arc::new(DropCounters::default());
+        let queue = EventQueue::new(Some(1), OverflowPolicy::DropOldest, Arc::clone(&totals));
+        let receiver = EventReceiver::new(Arc::clone(&queue));
+        queue.push(advertising_report()).unwrap();
+        queue.push(advertising_report()).unwrap();
+        drop(receiver);
+        drop(queue);
+
+        assert_eq!(
+            totals.stats(),
+            EventQueueStats {
+                dropped_events: 1,
+                dropped_advertising_reports: 1,
+                high_water_mark: 1,
+            }
+        );
+        totals.reset();
+        assert_eq!(totals.stats(), EventQueueStats::default());
+    }
+}
@@ -1,11 +1,22 @@
-use crate::{sd_api_v6::BleDriver, Error, Result, BluetoothAddress};
+use crate::{codec::{Command, Config}, lifecycle::{Configuring, Enabled}, sd_api_v6::{BleDriver, DriverCore}, Result, BluetoothAddress};
+use bytes::Bytes;
+#[cfg(feature = "pc-ble-driver")]
 use nrf_ble_driver_sys::ffi;
-use std::{ptr, slice, result, str, clone};
+use std::str;
 use num_enum::TryFromPrimitive;
-use std::convert::TryFrom;
+#[cfg(feature = "pc-ble-driver")]
+use std::{convert::TryFrom, slice};
+use std::sync::atomic::Ordering;
 
+const BLE_GAP_SCAN_FP_ACCEPT_ALL: u8 = 0x00;
+const BLE_GAP_ADDR_TYPE_PUBLIC: u8 = 0x00;
+const BLE_GAP_ADDR_TYPE_RANDOM_STATIC: u8 = 0x01;
+const BLE_GAP_ADDR_TYPE_RANDOM_PRIVATE_RESOLVABLE: u8 = 0x02;
+const BLE_GAP_ADDR_TYPE_RANDOM_PRIVATE_NON_RESOLVABLE: u8 = 0x03;
+const BLE_GAP_ADDR_TYPE_ANONYMOUS: u8 = 0x7f;
 
-#[derive(Debug)]
+
+#[derive(Debug, Clone)]
 pub enum GapEvent {
     Connect,
     Disconnect,
@@ -18,7 +29,8 @@ pub enum GapEvent {
     LESecureKeyDiffieHellmanKeyRequest,
     AuthenticationStatus,
     ConnectionSecurityUpdate,
-    Timeout,
+    /// A procedure timed out. Carries the `BLE_GAP_TIMEOUT_SRC_*` source.
+    Timeout(u8),
     RSSIChanged,
     AdvertisingReport(GapAdvertisementReport),
     SecurityRequest,
@@ -33,6 +45,7 @@ pub enum GapEvent {
     Unknown(u32),
 }
 
+#[derive(Debug, Clone)]
 pub struct GapConfigRoleCount {
     /// Maximum number of advertising sets. Default value is 1.
     pub advertising_set_count: u8,
@@ -74,6 +87,7 @@ impl Default for GapConfigRoleCount {
     }
 }
 
+#[derive(Debug, Clone)]
 pub struct GapScanParameters {
     /// If 1, the scanner will accept extended advertising packets.
     /// If set to 0, the scanner will not receive advertising packets
@@ -118,8 +132,8 @@ impl Default for GapScanParameters {
         GapScanParameters {
             extended: 1,
             active: 1,
-            filter_policy: ffi::BLE_GAP_SCAN_FP_ACCEPT_ALL as u8,
-            scan_phys: ffi::BLE_GAP_PHY_AUTO as u8,
+            filter_policy: BLE_GAP_SCAN_FP_ACCEPT_ALL,
+            scan_phys: GapPhy::Auto as u8,
             interval: 0xa0,
             window: 50,
             timeout: 0,
@@ -128,6 +142,28 @@ impl Default for GapScanParameters {
     }
 }
 
+#[derive(Debug, Clone, Copy)]
+pub struct GapConnectionParameters {
+    /// Minimum connection interval in 1.25 ms units.
+    pub min_connection_interval: u16,
+    /// Maximum connection interval in 1.25 ms units.
+    pub max_connection_interval: u16,
+    pub slave_latency: u16,
+    /// Supervision timeout in 10 ms units.
+    pub connection_supervision_timeout: u16,
+}
+
+impl Default for GapConnectionParameters {
+    fn default() -> Self {
+        GapConnectionParameters {
+            min_connection_interval: 6,
+            max_connection_interval: 24,
+            slave_latency: 0,
+            connection_supervision_timeout: 400,
+        }
+    }
+}
+
 #[derive(Debug, Clone, Copy)]
 pub enum GapAddressType {
     Public,
@@ -138,6 +174,32 @@ pub enum GapAddressType {
     Unknown(u8),
 }
 
+impl GapAddressType {
+    pub fn from_u8(address_type: u8) -> GapAddressType {
+        match address_type {
+            BLE_GAP_ADDR_TYPE_PUBLIC => GapAddressType::Public,
+            BLE_GAP_ADDR_TYPE_RANDOM_STATIC => GapAddressType::RandomStatic,
+            BLE_GAP_ADDR_TYPE_RANDOM_PRIVATE_RESOLVABLE => GapAddressType::PrivateResolvable,
+            BLE_GAP_ADDR_TYPE_RANDOM_PRIVATE_NON_RESOLVABLE => {
+                GapAddressType::PrivateNonResolvable
+            }
+            BLE_GAP_ADDR_TYPE_ANONYMOUS => GapAddressType::Anonymous,
+            unknown => GapAddressType::Unknown(unknown),
+        }
+    }
+
+    pub fn as_u8(self) -> u8 {
+        match self {
+            GapAddressType::Public => BLE_GAP_ADDR_TYPE_PUBLIC,
+            GapAddressType::RandomStatic => BLE_GAP_ADDR_TYPE_RANDOM_STATIC,
+            GapAddressType::PrivateResolvable => BLE_GAP_ADDR_TYPE_RANDOM_PRIVATE_RESOLVABLE,
+            GapAddressType::PrivateNonResolvable => BLE_GAP_ADDR_TYPE_RANDOM_PRIVATE_NON_RESOLVABLE,
+            GapAddressType::Anonymous => BLE_GAP_ADDR_TYPE_ANONYMOUS,
+            GapAddressType::Unknown(address_type) => address_type,
+        }
+    }
+}
+
 #[derive(Debug, Clone, Copy)]
 pub struct GapAddress {
     pub address_id_peer: bool,
@@ -148,11 +210,11 @@ pub struct GapAddress {
 #[derive(Debug, TryFromPrimitive, Clone, Copy)]
 #[repr(u32)]
 pub enum GapPhy {
-    Auto = ffi::BLE_GAP_PHY_AUTO,
-    OneMbps = ffi::BLE_GAP_PHY_1MBPS,
-    TwoMbps = ffi::BLE_GAP_PHY_2MBPS,
-    Coded = ffi::BLE_GAP_PHY_CODED,
-    NotConfigured = ffi::BLE_GAP_PHY_NOT_SET,
+    Auto = 0x00,
+    OneMbps = 0x01,
+    TwoMbps = 0x02,
+    Coded = 0x04,
+    NotConfigured = 0xff,
     #[num_enum(default)]
     Unknown,
 }
@@ -170,50 +232,63 @@ pub enum GapSetId {
     NotAvailable,
 }
 
+/// Bluetooth assigned numbers of the advertising data types.
 #[derive(Debug, Copy, Clone)]
 #[repr(u8)]
 pub enum AdvertisingDataType {
-    Flags = ffi::BLE_GAP_AD_TYPE_FLAGS as u8,
-    ServiceUUIDIncomplete16Bit = ffi::BLE_GAP_AD_TYPE_16BIT_SERVICE_UUID_MORE_AVAILABLE as u8,
-    ServiceUUIDComplete16Bit = ffi::BLE_GAP_AD_TYPE_16BIT_SERVICE_UUID_COMPLETE as u8,
-    ServiceUUIDIncomplete32Bit = ffi::BLE_GAP_AD_TYPE_32BIT_SERVICE_UUID_MORE_AVAILABLE as u8,
-    ServiceUUIDComplete32Bit = ffi::BLE_GAP_AD_TYPE_32BIT_SERVICE_UUID_COMPLETE as u8,
-    ServiceUUIDIncomplete128Bit = ffi::BLE_GAP_AD_TYPE_128BIT_SERVICE_UUID_MORE_AVAILABLE as u8,
-    ServiceUUIDComplete128Bit = ffi::BLE_GAP_AD_TYPE_128BIT_SERVICE_UUID_COMPLETE as u8,
-    ShortLocalName = ffi::BLE_GAP_AD_TYPE_SHORT_LOCAL_NAME as u8,
-    CompleteLocalName = ffi::BLE_GAP_AD_TYPE_COMPLETE_LOCAL_NAME as u8,
-    TxPowerLevel = ffi::BLE_GAP_AD_TYPE_TX_POWER_LEVEL as u8,
-    ClassOfDevice = ffi::BLE_GAP_AD_TYPE_CLASS_OF_DEVICE as u8,
-    SimplePairingHashC = ffi::BLE_GAP_AD_TYPE_SIMPLE_PAIRING_HASH_C as u8,
-    SimpleRandomizerR = ffi::BLE_GAP_AD_TYPE_SIMPLE_PAIRING_RANDOMIZER_R as u8,
-    SecurityManagerTKValue = ffi::BLE_GAP_AD_TYPE_SECURITY_MANAGER_TK_VALUE as u8,
-    SecurityManagerOOBFlags = ffi::BLE_GAP_AD_TYPE_SECURITY_MANAGER_OOB_FLAGS as u8,
-    PeripheralConnectionIntervalRange = ffi::BLE_GAP_AD_TYPE_SLAVE_CONNECTION_INTERVAL_RANGE as u8,
-    SolicitedServiceUUIDS16Bit = ffi::BLE_GAP_AD_TYPE_SOLICITED_SERVICE_UUIDS_16BIT as u8,
-    SolicitedServiceUUIDS128Bit = ffi::BLE_GAP_AD_TYPE_SOLICITED_SERVICE_UUIDS_128BIT as u8,
-    ServiceData = ffi::BLE_GAP_AD_TYPE_SERVICE_DATA as u8,
-    PublicTargetAddress = ffi::BLE_GAP_AD_TYPE_PUBLIC_TARGET_ADDRESS as u8,
-    RandomTargetAddress = ffi::BLE_GAP_AD_TYPE_RANDOM_TARGET_ADDRESS as u8,
-    Appearance = ffi::BLE_GAP_AD_TYPE_APPEARANCE as u8,
-    AdvertisingInterval = ffi::BLE_GAP_AD_TYPE_ADVERTISING_INTERVAL as u8,
-    LEBluetoothDeviceAddress = ffi::BLE_GAP_AD_TYPE_LE_BLUETOOTH_DEVICE_ADDRESS as u8,
-    LERole = ffi::BLE_GAP_AD_TYPE_LE_ROLE as u8,
-    SimplePairingHashC256 = ffi::BLE_GAP_AD_TYPE_SIMPLE_PAIRING_HASH_C256 as u8,
-    SimplePairingRandomizerR256 = ffi::BLE_GAP_AD_TYPE_SIMPLE_PAIRING_RANDOMIZER_R256 as u8,
-    ServiceData32BitUUID = ffi::BLE_GAP_AD_TYPE_SERVICE_DATA_32BIT_UUID as u8,
-    ServiceData128BitUUID = ffi::BLE_GAP_AD_TYPE_SERVICE_DATA_128BIT_UUID as u8,
-    LESecureConnectionConfirmationValue = ffi::BLE_GAP_AD_TYPE_LESC_CONFIRMATION_VALUE as u8,
-    LESecureConnectionRandomValue = ffi::BLE_GAP_AD_TYPE_LESC_RANDOM_VALUE as u8,
-    URI = ffi::BLE_GAP_AD_TYPE_URI as u8,
-    InformationData3D = ffi::BLE_GAP_AD_TYPE_3D_INFORMATION_DATA as u8,
-    ManufacturerSpecificData = ffi::BLE_GAP_AD_TYPE_MANUFACTURER_SPECIFIC_DATA as u8,
+    Flags = 0x01,
+    ServiceUUIDIncomplete16Bit = 0x02,
+    ServiceUUIDComplete16Bit = 0x03,
+    ServiceUUIDIncomplete32Bit = 0x04,
+    ServiceUUIDComplete32Bit = 0x05,
+    ServiceUUIDIncomplete128Bit = 0x06,
+    ServiceUUIDComplete128Bit = 0x07,
+    ShortLocalName = 0x08,
+    CompleteLocalName = 0x09,
+    TxPowerLevel = 0x0a,
+    ClassOfDevice = 0x0d,
+    SimplePairingHashC = 0x0e,
+    SimpleRandomizerR = 0x0f,
+    SecurityManagerTKValue = 0x10,
+    SecurityManagerOOBFlags = 0x11,
+    PeripheralConnectionIntervalRange = 0x12,
+    SolicitedServiceUUIDS16Bit = 0x14,
+    SolicitedServiceUUIDS128Bit = 0x15,
+    ServiceData = 0x16,
+    PublicTargetAddress = 0x17,
+    RandomTargetAddress = 0x18,
+    Appearance = 0x19,
+    AdvertisingInterval = 0x1a,
+    LEBluetoothDeviceAddress = 0x1b,
+    LERole = 0x1c,
+    SimplePairingHashC256 = 0x1d,
+    SimplePairingRandomizerR256 = 0x1e,
+    ServiceData32BitUUID = 0x20,
+    ServiceData128BitUUID = 0x21,
+    LESecureConnectionConfirmationValue = 0x22,
+    LESecureConnectionRandomValue = 0x23,
+    URI = 0x24,
+    InformationData3D = 0x3d,
+    ManufacturerSpecificData = 0xff,
 }
 
 
 
+/// Properties of the advertising PDU an advertising report was received in.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct GapAdvertisementReportType {
+    pub connectable: bool,
+    pub scannable: bool,
+    pub directed: bool,
+    pub scan_response: bool,
+    pub extended_pdu: bool,
+    /// Data status of an extended report: complete, more data pending or truncated.
+    pub status: u8,
+}
+
 #[derive(Debug, Clone)]
 pub struct GapAdvertisementReport {
-    //pub report type
+    pub report_type: GapAdvertisementReportType,
     pub peer_address: GapAddress,
     pub direct_address: GapAddress,
     pub primary_phy: GapPhy,
@@ -222,145 +297,88 @@ pub struct GapAdvertisementReport {
     pub rssi: i8,
     pub channel_index: u8,
     pub set_id: GapSetId,
-    pub data: Vec<u8>,
+    /// Advertising data. Reference counted, so cloning a report is cheap.
+    pub data: Bytes,
 }
 
-impl BleDriver {
+impl BleDriver<Configuring> {
     pub fn gap_set_connection_config(
         &mut self,
         connection_tag: u8,
         connection_count: u8,
         event_length: u16,
     ) -> Result<()> {
-        let gap_connection_config = ffi::ble_gap_conn_cfg_t {
-            conn_count: connection_count,
+        self.adapter.core.cfg_set(Config::GapConnection {
+            connection_tag,
+            connection_count,
             event_length,
-        };
-        let ble_config = ffi::ble_cfg_t {
-            conn_cfg: ffi::ble_conn_cfg_t {
-                conn_cfg_tag: connection_tag,
-                params: ffi::ble_conn_cfg_t__bindgen_ty_1 {
-                    gap_conn_cfg: gap_connection_config,
-                },
-            },
-        };
-
-        unsafe {
-            let error_code = ffi::sd_ble_cfg_set(
-                self.adapter,
-                ffi::BLE_CONN_CFGS_BLE_CONN_CFG_GAP,
-                &ble_config,
-                0,
-            );
-            if error_code == ffi::NRF_SUCCESS {
-                Ok(())
-            } else {
-                Err(Error::FFIError(error_code))
-            }
-        }
+        })
     }
 
     pub fn gap_set_role_count_config(&mut self, config: &GapConfigRoleCount) -> Result<()> {
-        let ble_config = ffi::ble_cfg_t {
-            gap_cfg: ffi::ble_gap_cfg_t {
-                role_count_cfg: ffi::ble_gap_cfg_role_count_t {
-                    adv_set_count: config.advertising_set_count,
-                    periph_role_count: config.peripheral_role_count,
-                    central_role_count: config.central_role_count,
-                    central_sec_count: config.central_security_count,
-                    _bitfield_align_1: [0; 0],
-                    _bitfield_1: ffi::ble_gap_cfg_role_count_t::new_bitfield_1(
-                        config.qos_channel_survey_role_available,
-                    ),
-                },
-            },
-        };
-
-        unsafe {
-            let error_code = ffi::sd_ble_cfg_set(
-                self.adapter,
-                ffi::BLE_GAP_CFGS_BLE_GAP_CFG_ROLE_COUNT,
-                &ble_config,
-                0,
-            );
-            if error_code == ffi::NRF_SUCCESS {
-                Ok(())
-            } else {
-                Err(Error::FFIError(error_code))
-            }
-        }
+        self.adapter.core.cfg_set(Config::GapRoleCount(config.clone()))
     }
+}
 
+impl BleDriver<Enabled> {
     pub fn gap_scan_start(&mut self, scan_parameters: &GapScanParameters) -> Result<()> {
-        let error_code;
+        self.adapter.core.gap_scan_start(scan_parameters)?;
+        self.adapter.core.record_scan_parameters(Some(scan_parameters));
+        Ok(())
+    }
 
-        if self.is_scanning {
-            let scan_params: *const ffi::ble_gap_scan_params_t = ptr::null();
-            unsafe {
-                error_code = ffi::sd_ble_gap_scan_start(self.adapter, scan_params, &*self.adv_data);
-            }
-        } else {
-            let scan_params = ffi::ble_gap_scan_params_t {
-                _bitfield_align_1: [0; 0],
-                _bitfield_1: ffi::ble_gap_scan_params_t::new_bitfield_1(
-                    scan_parameters.extended,
-                    0, // not supported in this softdevice
-                    scan_parameters.active,
-                    scan_parameters.filter_policy,
-                ),
-                scan_phys: scan_parameters.scan_phys,
-                interval: scan_parameters.interval,
-                window: scan_parameters.window,
-                timeout: scan_parameters.timeout,
-                channel_mask: scan_parameters.channel_mask,
-            };
-
-            unsafe {
-                error_code =
-                    ffi::sd_ble_gap_scan_start(self.adapter, &scan_params, &*self.adv_data);
-            }
-        }
+    pub fn gap_scan_stop(&mut self) -> Result<()> {
+        self.adapter.core.gap_scan_stop()?;
+        self.adapter.core.record_scan_parameters(None);
+        Ok(())
+    }
+}
 
-        if error_code == ffi::NRF_SUCCESS {
-            self.is_scanning = true;
-            Ok(())
+impl DriverCore {
+    pub(crate) fn gap_scan_start(&self, scan_parameters: &GapScanParameters) -> Result<()> {
+        // While scanning, only resume with the parameters already in use.
+        let scan_parameters = if self.is_scanning.load(Ordering::Acquire) {
+            None
         } else {
-            Err(Error::FFIError(error_code))
-        }
-    }
+            Some(scan_parameters)
+        };
+        self.command("sd_ble_gap_scan_start", None, &scan_parameters, || {
+            self.backend.gap_scan_start(scan_parameters)
+        })?;
 
-    pub fn handle_gap_event(&mut self, event_id: u32, gap_event: &ffi::ble_gap_evt_t) -> GapEvent {
-        unsafe {
-            let event = match event_id {
-                ffi::BLE_GAP_EVTS_BLE_GAP_EVT_ADV_REPORT => {
-                    if self.is_scanning {
-                        self.gap_scan_start(&GapScanParameters::default()).unwrap();
-                    }
-                    GapEvent::AdvertisingReport(GapAdvertisementReport::from_ffi(&gap_event.params.adv_report))
-                }
-                id => GapEvent::Unknown(id)
+        self.is_scanning.store(true, Ordering::Release);
+        Ok(())
+    }
 
-            };
+    pub(crate) fn gap_scan_stop(&self) -> Result<()> {
+        self.serialized(&Command::GapScanStop, || self.backend.gap_scan_stop())?;
+        self.is_scanning.store(false, Ordering::Release);
+        Ok(())
+    }
+}
 
-            event
+#[cfg(feature = "pc-ble-driver")]
+impl GapEvent {
+    pub(crate) unsafe fn from_ffi(event_id: u32, gap_event: &ffi::ble_gap_evt_t) -> GapEvent {
+        match event_id {
+            ffi::BLE_GAP_EVTS_BLE_GAP_EVT_ADV_REPORT => {
+                GapEvent::AdvertisingReport(GapAdvertisementReport::from_ffi(&gap_event.params.adv_report))
+            }
+            ffi::BLE_GAP_EVTS_BLE_GAP_EVT_TIMEOUT => GapEvent::Timeout(gap_event.params.timeout.src),
+            id => GapEvent::Unknown(id)
         }
     }
-
-    
 }
 
 impl GapAdvertisementReport {
 
     pub fn find_ad_data(advertisement: &GapAdvertisementReport, adtype: AdvertisingDataType) -> Option<Vec<u8>> {
         let mut index = 0;
-        //println!("{:X?}", advertisement.data);
 
         while index < advertisement.data.len() {
             let length = advertisement.data[index] as usize;
             let ad_type = advertisement.data[index + 1];
 
-            //println!("length: {}, index: {}", length, index);
-
             if ad_type == adtype as u8 {
                 return Some(advertisement.data[index + 2..index + length + 1].to_vec());
             }
@@ -371,6 +389,7 @@ impl GapAdvertisementReport {
         None
     }
 
+    #[cfg(feature = "pc-ble-driver")]
     fn from_ffi(adv_report: &ffi::ble_gap_evt_adv_report_t) -> GapAdvertisementReport {
         //let primary_phy = GapPhy::try_from(adv_report.primary_phy as u32);
         
@@ -387,10 +406,20 @@ impl GapAdvertisementReport {
 
         let data;
         unsafe {
-            data = slice::from_raw_parts(adv_report.data.p_data, adv_report.data.len as usize).to_vec();
+            data = Bytes::copy_from_slice(slice::from_raw_parts(adv_report.data.p_data, adv_report.data.len as usize));
         }
     
+        let report_type = GapAdvertisementReportType {
+            connectable: adv_report.type_.connectable() != 0,
+            scannable: adv_report.type_.scannable() != 0,
+            directed: adv_report.type_.directed() != 0,
+            scan_response: adv_report.type_.scan_response() != 0,
+            extended_pdu: adv_report.type_.extended_pdu() != 0,
+            status: adv_report.type_.status() as u8,
+        };
+
         GapAdvertisementReport {
+            report_type,
             peer_address: GapAddress::from(&adv_report.peer_addr),
             direct_address: GapAddress::from(&adv_report.direct_addr),
             primary_phy: GapPhy::try_from(adv_report.primary_phy as u32).unwrap(),
@@ -406,30 +435,55 @@ impl GapAdvertisementReport {
 }
 
 
+#[cfg(feature = "pc-ble-driver")]
 impl GapAddress {
     fn from(gap_address: &ffi::ble_gap_addr_t) -> GapAddress {
-        let address_type = match gap_address.addr_type() as u32 {
-            ffi::BLE_GAP_ADDR_TYPE_PUBLIC => GapAddressType::Public,
-            ffi::BLE_GAP_ADDR_TYPE_RANDOM_STATIC => GapAddressType::RandomStatic,
-            ffi::BLE_GAP_ADDR_TYPE_RANDOM_PRIVATE_RESOLVABLE => GapAddressType::PrivateResolvable,
-            ffi::BLE_GAP_ADDR_TYPE_RANDOM_PRIVATE_NON_RESOLVABLE => {
-                GapAddressType::PrivateNonResolvable
-            }
-            ffi::BLE_GAP_ADDR_TYPE_ANONYMOUS => GapAddressType::Anonymous,
-            unknown => GapAddressType::Unknown(unknown as u8),
-        };
-
         GapAddress {
             address_id_peer: gap_address.addr_id_peer() != 0,
-            address_type,
+            address_type: GapAddressType::from_u8(gap_address.addr_type()),
             address: gap_address.addr,
         }
     }
 }
 
-fn check_name(data: &ffi::ble_data_t) {
-    unsafe {
-        let safe_data = slice::from_raw_parts(data.p_data, data.len as usize).to_vec();
-        println!("{:X?}", safe_data);
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blocking::BlockingBleDriver;
+    use crate::codec::Event;
+    use crate::simulated::SimulatedBackend;
+    use crate::test_support::simulated_driver;
+    use crate::EventType;
+    use std::time::Duration;
+
+    const BLE_GAP_TIMEOUT_SRC_SCAN: u8 = 1;
+
+    #[test]
+    fn scanning_restarts_after_a_scan_timeout() {
+        let timeout = Event::GapTimeout {
+            connection_handle: 0xffff,
+            source: BLE_GAP_TIMEOUT_SRC_SCAN,
+        };
+        let (backend, driver) =
+            simulated_driver(SimulatedBackend::new().event_after(Duration::from_millis(100), timeout));
+
+        let mut adapter = BlockingBleDriver::open(driver).unwrap();
+        adapter.ble_enable().unwrap();
+        adapter.gap_scan_start(&GapScanParameters::default()).unwrap();
+        loop {
+            match adapter.recv_event_timeout(Duration::from_secs(1)).unwrap() {
+                Some(EventType::BleGap(_, GapEvent::Timeout(source))) => {
+                    assert_eq!(source, BLE_GAP_TIMEOUT_SRC_SCAN);
+                    break;
+                }
+                Some(_) => {}
+                None => panic!("no scan timeout received"),
+            }
+        }
+        assert!(!backend.is_scanning());
+
+        // Starting again passes the parameters instead of resuming.
+        adapter.gap_scan_start(&GapScanParameters::default()).unwrap();
+        assert!(backend.is_scanning());
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,331 @@
+use crate::{
+    ble::AdapterInfo,
+    event_bus::{EventBus, EventFilter},
+    event_queue::{EventQueueStats, EventReceiver, OverflowPolicy},
+    gap::{GapConfigRoleCount, GapScanParameters},
+    lifecycle::{AnyBleDriver, Configuring, TransitionError, TransitionResult},
+    rpc::{LogSeverity, ResetMode},
+    sd_api_v6::{rt::{self, Receiver}, BleDriver, EventType},
+    stats::{DriverStats, StatsCollector},
+    Error, Result,
+};
+use futures_core::Stream;
+use std::future;
+use std::pin::Pin;
+use std::sync::mpsc::{self, Sender};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::thread;
+use std::time::Duration;
+
+type Command = Box<dyn FnOnce(AnyBleDriver) -> AnyBleDriver + Send>;
+
+/// Cloneable handle used to issue commands to an open adapter.
+///
+/// Commands are executed in order by a dedicated driver thread that owns the
+/// driver as an `AnyBleDriver`, so any number of tasks can issue commands while another task
+/// consumes the `EventStream`. The adapter is closed when the last handle is
+/// dropped.
+#[derive(Debug, Clone)]
+pub struct BleDriverHandle {
+    commands: Sender<Command>,
+    adapter_info: Arc<Mutex<Option<AdapterInfo>>>,
+    events: Arc<EventBus>,
+    stats: Arc<StatsCollector>,
+}
+
+/// Stream of events received from an open adapter.
+#[derive(Debug)]
+pub struct EventStream {
+    receiver: EventReceiver,
+}
+
+impl BleDriverHandle {
+    pub(crate) fn spawn(driver: BleDriver<Configuring>, events: Arc<EventBus>) -> BleDriverHandle {
+        let adapter_info = Arc::clone(&driver.adapter.core.adapter_info);
+        let stats = Arc::clone(&driver.adapter.core.stats);
+        let (commands, receiver) = mpsc::channel::<Command>();
+
+        thread::spawn(move || {
+            let mut driver = AnyBleDriver::Configuring(driver);
+            while let Ok(command) = receiver.recv() {
+                driver = command(driver);
+            }
+        });
+
+        BleDriverHandle {
+            commands,
+            adapter_info,
+            events,
+            stats,
+        }
+    }
+
+    /// Runs `command` on the driver thread and waits for its result.
+    pub async fn execute<T, F>(&self, command: F) -> Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut AnyBleDriver) -> Result<T> + Send + 'static,
+    {
+        self.transition(|mut driver| {
+            let result = command(&mut driver);
+            (driver, result)
+        })
+        .await
+    }
+
+    /// Runs `command` on the driver thread and blocks the calling thread
+    /// until it has completed. Must not be called from within an async runtime.
+    pub fn execute_blocking<T, F>(&self, command: F) -> Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut AnyBleDriver) -> Result<T> + Send + 'static,
+    {
+        self.transition_blocking(|mut driver| {
+            let result = command(&mut driver);
+            (driver, result)
+        })
+    }
+
+    /// Runs `transition` on the driver thread, which continues with the
+    /// driver it returns, and waits for its result.
+    pub(crate) async fn transition<T, F>(&self, transition: F) -> Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(AnyBleDriver) -> (AnyBleDriver, Result<T>) + Send + 'static,
+    {
+        let response = self.submit(transition)?;
+        response.await.ok_or(Error::DriverClosed)?
+    }
+
+    pub(crate) fn transition_blocking<T, F>(&self, transition: F) -> Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(AnyBleDriver) -> (AnyBleDriver, Result<T>) + Send + 'static,
+    {
+        let response = self.submit(transition)?;
+        response.blocking_recv().ok_or(Error::DriverClosed)?
+    }
+
+    fn submit<T, F>(&self, transition: F) -> Result<Receiver<Result<T>>>
+    where
+        T: Send + 'static,
+        F: FnOnce(AnyBleDriver) -> (AnyBleDriver, Result<T>) + Send + 'static,
+    {
+        let (reply, response) = rt::oneshot();
+        self.commands
+            .send(Box::new(move |driver| {
+                let (driver, result) = transition(driver);
+                reply.send(result);
+                driver
+            }))
+            .map_err(|_| Error::DriverClosed)?;
+
+        Ok(response)
+    }
+
+    /// Version information of the attached firmware, updated by the driver
+    /// thread after every reset and recovery.
+    pub fn adapter_info(&self) -> Option<AdapterInfo> {
+        self.adapter_info.lock().unwrap().clone()
+    }
+
+    /// Subscribes to the events matching `filter`. Every subscriber has its
+    /// own queue and receives events independently of the others.
+    pub fn subscribe(&self, filter: EventFilter) -> EventStream {
+        EventStream::new(self.events.subscribe(filter))
+    }
+
+    /// Number of events dropped because an event queue was full, summed
+    /// over all subscribers.
+    pub fn event_queue_stats(&self) -> EventQueueStats {
+        self.events.stats()
+    }
+
+    pub fn reset_event_queue_stats(&self) {
+        self.events.reset_stats()
+    }
+
+    /// Health counters of the adapter. See `BleDriver::stats`.
+    pub fn stats(&self) -> DriverStats {
+        self.stats.snapshot(&self.events)
+    }
+
+    pub fn reset_stats(&self) {
+        self.stats.reset(&self.events)
+    }
+
+    /// Limits every event queue to `capacity` events. See `BleDriver::set_event_queue_capacity`.
+    pub fn set_event_queue_capacity(&self, capacity: Option<usize>, policy: OverflowPolicy) {
+        self.events.set_capacity(capacity, policy)
+    }
+
+    pub async fn close(&self) -> Result<()> {
+        self.transition(|driver| settle(driver.close())).await
+    }
+
+    /// Resets the connectivity chip. See `BleDriver::reset`.
+    pub async fn reset(&self, mode: ResetMode) -> Result<()> {
+        self.transition(move |driver| settle(driver.reset(mode))).await
+    }
+
+    pub async fn ble_enable(&self) -> Result<()> {
+        self.transition(|driver| settle(driver.ble_enable())).await
+    }
+
+    pub async fn ble_version_get(&self) -> Result<AdapterInfo> {
+        self.execute(|driver| driver.ble_version_get()).await
+    }
+
+    pub async fn user_mem_reply(&self, connection_handle: u16, length: Option<u16>) -> Result<()> {
+        self.execute(move |driver| driver.enabled()?.user_mem_reply(connection_handle, length))
+            .await
+    }
+
+    pub async fn set_log_severity_filter(&self, severity: LogSeverity) -> Result<()> {
+        self.execute(move |driver| driver.set_log_severity_filter(severity))
+            .await
+    }
+
+    pub async fn set_log_events(&self, enabled: bool) -> Result<()> {
+        self.execute(move |driver| {
+            driver.set_log_events(enabled);
+            Ok(())
+        })
+        .await
+    }
+
+    pub async fn set_auto_recover(&self, enabled: bool) -> Result<()> {
+        self.execute(move |driver| {
+            driver.set_auto_recover(enabled);
+            Ok(())
+        })
+        .await
+    }
+
+    pub async fn gap_set_connection_config(
+        &self,
+        connection_tag: u8,
+        connection_count: u8,
+        event_length: u16,
+    ) -> Result<()> {
+        self.execute(move |driver| {
+            driver.configuring()?.gap_set_connection_config(connection_tag, connection_count, event_length)
+        })
+        .await
+    }
+
+    pub async fn gap_set_role_count_config(&self, config: &GapConfigRoleCount) -> Result<()> {
+        let config = config.clone();
+        self.execute(move |driver| driver.configuring()?.gap_set_role_count_config(&config))
+            .await
+    }
+
+    pub async fn gap_scan_start(&self, scan_parameters: &GapScanParameters) -> Result<()> {
+        let scan_parameters = scan_parameters.clone();
+        self.execute(move |driver| driver.enabled()?.gap_scan_start(&scan_parameters))
+            .await
+    }
+
+    pub async fn gap_scan_stop(&self) -> Result<()> {
+        self.execute(|driver| driver.enabled()?.gap_scan_stop()).await
+    }
+
+    pub async fn gatt_set_connection_config(&self, connection_tag: u8, att_mtu: u16) -> Result<()> {
+        self.execute(move |driver| driver.configuring()?.gatt_set_connection_config(connection_tag, att_mtu))
+            .await
+    }
+
+    pub async fn gattc_set_connection_config(
+        &self,
+        connection_tag: u8,
+        write_cmd_tx_queue_size: u8,
+    ) -> Result<()> {
+        self.execute(move |driver| {
+            driver.configuring()?.gattc_set_connection_config(connection_tag, write_cmd_tx_queue_size)
+        })
+        .await
+    }
+
+    pub async fn gatts_set_connection_config(
+        &self,
+        connection_tag: u8,
+        hvn_tx_queue_size: u8,
+    ) -> Result<()> {
+        self.execute(move |driver| {
+            driver.configuring()?.gatts_set_connection_config(connection_tag, hvn_tx_queue_size)
+        })
+        .await
+    }
+}
+
+/// Splits the outcome of a state transition into the driver to continue
+/// with and the result to report.
+pub(crate) fn settle(result: TransitionResult<AnyBleDriver, AnyBleDriver>) -> (AnyBleDriver, Result<()>) {
+    match result {
+        Ok(driver) => (driver, Ok(())),
+        Err(TransitionError { error, driver }) => (driver, Err(error)),
+    }
+}
+
+impl EventStream {
+    pub(crate) fn new(receiver: EventReceiver) -> EventStream {
+        EventStream { receiver }
+    }
+
+    /// Number of events dropped from this stream's queue.
+    pub fn stats(&self) -> EventQueueStats {
+        self.receiver.queue().stats()
+    }
+
+    pub(crate) fn poll_recv(&mut self, cx: &mut Context<'_>) -> Poll<Option<EventType>> {
+        self.receiver.poll_recv(cx)
+    }
+
+    /// Waits for the next event. Returns `None` once the driver has been dropped.
+    pub async fn recv(&mut self) -> Option<EventType> {
+        future::poll_fn(|cx| self.receiver.poll_recv(cx)).await
+    }
+
+    /// Waits for the next event for at most `timeout`.
+    ///
+    /// Returns `Ok(None)` on timeout and `Error::DriverClosed` once the
+    /// driver has been dropped. The timer is provided by the runtime
+    /// selected with the `rt-*` features.
+    pub async fn recv_timeout(&mut self, timeout: Duration) -> Result<Option<EventType>> {
+        match rt::timeout(timeout, self.recv()).await {
+            Some(Some(event)) => Ok(Some(event)),
+            Some(None) => Err(Error::DriverClosed),
+            None => Ok(None),
+        }
+    }
+}
+
+impl Stream for EventStream {
+    type Item = EventType;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<EventType>> {
+        self.poll_recv(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ble::NORDIC_COMPANY_ID;
+    use crate::simulated::SimulatedBackend;
+    use crate::test_support::simulated_driver;
+
+    #[tokio::test]
+    async fn adapter_info_follows_resets() {
+        let (backend, driver) = simulated_driver(SimulatedBackend::new());
+        let (handle, _events) = driver.open().unwrap();
+        assert_eq!(handle.adapter_info().unwrap().subversion_number, 0x00b6);
+
+        // S140 v6.1.1 comes up after the reset.
+        backend.set_version(9, NORDIC_COMPANY_ID, 0x00b7);
+        handle.reset(ResetMode::SoftReset).await.unwrap();
+        assert_eq!(handle.adapter_info().unwrap().subversion_number, 0x00b7);
+        assert_eq!(handle.clone().adapter_info(), handle.adapter_info());
+    }
+}
@@ -0,0 +1,259 @@
+//! Lifecycle states of a `BleDriver`.
+//!
+//! The SoftDevice only accepts `sd_ble_cfg_set` before `sd_ble_enable` and
+//! radio operations after it. `BleDriver<S>` encodes this in its type: a
+//! driver is created `Closed`, `open_adapter` makes it `Configuring` and
+//! `ble_enable` makes it `Enabled`, so calling a configuration setter after
+//! enabling or scanning before it does not compile.
+//!
+//! The driver thread behind a `BleDriverHandle` only knows the state at run
+//! time. It owns an `AnyBleDriver`, whose calls fail with
+//! `Error::InvalidState` when made in the wrong state.
+
+use crate::{ble::AdapterInfo, rpc::{LogSeverity, ResetMode}, sd_api_v6::BleDriver, Error, Result};
+use std::fmt;
+
+/// The adapter is not open. The initial state of every driver.
+#[derive(Debug)]
+pub struct Closed;
+
+/// The adapter is open and the SoftDevice accepts configuration calls.
+#[derive(Debug)]
+pub struct Configuring;
+
+/// The SoftDevice has been enabled and accepts radio operations.
+#[derive(Debug)]
+pub struct Enabled;
+
+mod sealed {
+    pub trait Sealed {}
+
+    impl Sealed for super::Closed {}
+    impl Sealed for super::Configuring {}
+    impl Sealed for super::Enabled {}
+}
+
+/// A lifecycle state of `BleDriver`.
+pub trait State: sealed::Sealed + fmt::Debug {
+    const LIFECYCLE: Lifecycle;
+}
+
+/// A state in which the adapter is open.
+pub trait Open: State {}
+
+impl State for Closed {
+    const LIFECYCLE: Lifecycle = Lifecycle::Closed;
+}
+
+impl State for Configuring {
+    const LIFECYCLE: Lifecycle = Lifecycle::Configuring;
+}
+
+impl State for Enabled {
+    const LIFECYCLE: Lifecycle = Lifecycle::Enabled;
+}
+
+impl Open for Configuring {}
+impl Open for Enabled {}
+
+/// The lifecycle state of a driver, as known at run time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Lifecycle {
+    Closed,
+    Configuring,
+    Enabled,
+}
+
+impl fmt::Display for Lifecycle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Lifecycle::Closed => "closed",
+            Lifecycle::Configuring => "configuring",
+            Lifecycle::Enabled => "enabled",
+        })
+    }
+}
+
+/// A failed state transition. Hands back the driver in the state it was
+/// left in, so the caller can retry or close it.
+#[derive(Debug)]
+pub struct TransitionError<D> {
+    pub error: Error,
+    pub driver: D,
+}
+
+impl<D> TransitionError<D> {
+    pub(crate) fn new(error: Error, driver: D) -> TransitionError<D> {
+        TransitionError { error, driver }
+    }
+
+    fn map<E>(self, f: impl FnOnce(D) -> E) -> TransitionError<E> {
+        TransitionError::new(self.error, f(self.driver))
+    }
+}
+
+/// Dropping the driver closes the adapter, so `?` can be used wherever the
+/// driver is not needed after a failure.
+impl<D> From<TransitionError<D>> for Error {
+    fn from(error: TransitionError<D>) -> Error {
+        error.error
+    }
+}
+
+/// Result of a state transition.
+pub type TransitionResult<T, D> = std::result::Result<T, TransitionError<D>>;
+
+/// A `BleDriver` in a state only known at run time, as owned by the driver
+/// thread of a `BleDriverHandle`.
+#[derive(Debug)]
+pub enum AnyBleDriver {
+    Closed(BleDriver<Closed>),
+    Configuring(BleDriver<Configuring>),
+    Enabled(BleDriver<Enabled>),
+}
+
+macro_rules! with_driver {
+    ($any:expr, $driver:ident => $body:expr) => {
+        match $any {
+            AnyBleDriver::Closed($driver) => $body,
+            AnyBleDriver::Configuring($driver) => $body,
+            AnyBleDriver::Enabled($driver) => $body,
+        }
+    };
+}
+
+impl AnyBleDriver {
+    pub fn lifecycle(&self) -> Lifecycle {
+        match self {
+            AnyBleDriver::Closed(_) => Lifecycle::Closed,
+            AnyBleDriver::Configuring(_) => Lifecycle::Configuring,
+            AnyBleDriver::Enabled(_) => Lifecycle::Enabled,
+        }
+    }
+
+    /// The driver, if the SoftDevice is still accepting configuration calls.
+    pub fn configuring(&mut self) -> Result<&mut BleDriver<Configuring>> {
+        match self {
+            AnyBleDriver::Configuring(driver) => Ok(driver),
+            _ => Err(Error::InvalidState(self.lifecycle())),
+        }
+    }
+
+    /// The driver, if the SoftDevice has been enabled.
+    pub fn enabled(&mut self) -> Result<&mut BleDriver<Enabled>> {
+        match self {
+            AnyBleDriver::Enabled(driver) => Ok(driver),
+            _ => Err(Error::InvalidState(self.lifecycle())),
+        }
+    }
+
+    pub fn ble_enable(self) -> TransitionResult<AnyBleDriver, AnyBleDriver> {
+        match self {
+            AnyBleDriver::Configuring(driver) => driver
+                .ble_enable()
+                .map(AnyBleDriver::Enabled)
+                .map_err(|error| error.map(AnyBleDriver::Configuring)),
+            driver => Err(TransitionError::new(Error::InvalidState(driver.lifecycle()), driver)),
+        }
+    }
+
+    /// Closes the adapter. Closing a closed driver does nothing.
+    pub fn close(self) -> TransitionResult<AnyBleDriver, AnyBleDriver> {
+        match self {
+            AnyBleDriver::Closed(driver) => Ok(AnyBleDriver::Closed(driver)),
+            AnyBleDriver::Configuring(driver) => driver
+                .close()
+                .map(AnyBleDriver::Closed)
+                .map_err(|error| error.map(AnyBleDriver::Configuring)),
+            AnyBleDriver::Enabled(driver) => driver
+                .close()
+                .map(AnyBleDriver::Closed)
+                .map_err(|error| error.map(AnyBleDriver::Enabled)),
+        }
+    }
+
+    pub fn reset(self, mode: ResetMode) -> TransitionResult<AnyBleDriver, AnyBleDriver> {
+        match self {
+            AnyBleDriver::Configuring(driver) => driver
+                .reset(mode)
+                .map(AnyBleDriver::Configuring)
+                .map_err(|error| error.map(AnyBleDriver::Configuring)),
+            AnyBleDriver::Enabled(driver) => driver
+                .reset(mode)
+                .map(AnyBleDriver::Configuring)
+                .map_err(|error| error.map(AnyBleDriver::Enabled)),
+            driver => Err(TransitionError::new(Error::InvalidState(driver.lifecycle()), driver)),
+        }
+    }
+
+    pub fn ble_version_get(&mut self) -> Result<AdapterInfo> {
+        match self {
+            AnyBleDriver::Configuring(driver) => driver.ble_version_get(),
+            AnyBleDriver::Enabled(driver) => driver.ble_version_get(),
+            AnyBleDriver::Closed(_) => Err(Error::InvalidState(Lifecycle::Closed)),
+        }
+    }
+
+    pub fn set_log_severity_filter(&mut self, severity: LogSeverity) -> Result<()> {
+        with_driver!(self, driver => driver.set_log_severity_filter(severity))
+    }
+
+    pub fn set_log_events(&mut self, enabled: bool) {
+        with_driver!(self, driver => driver.set_log_events(enabled))
+    }
+
+    pub fn set_auto_recover(&mut self, enabled: bool) {
+        with_driver!(self, driver => driver.set_auto_recover(enabled))
+    }
+
+    pub fn adapter_info(&self) -> Option<AdapterInfo> {
+        with_driver!(self, driver => driver.adapter_info())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builder::BleDriverBuilder;
+    use crate::gap::GapScanParameters;
+    use crate::simulated::SimulatedBackend;
+    use crate::test_support::simulated_driver;
+
+    #[test]
+    fn lifecycle_transitions() {
+        let (backend, driver) = simulated_driver(SimulatedBackend::new());
+
+        let mut driver = driver.open_adapter().unwrap();
+        driver.gap_set_connection_config(1, 1, 6).unwrap();
+        let mut driver = driver.ble_enable().unwrap();
+        driver.gap_scan_start(&GapScanParameters::default()).unwrap();
+        assert!(backend.is_scanning());
+        driver.gap_scan_stop().unwrap();
+        assert!(!backend.is_scanning());
+        driver.gap_scan_start(&GapScanParameters::default()).unwrap();
+
+        let driver = driver.reset(ResetMode::SoftReset).unwrap();
+        assert!(!backend.is_enabled());
+        let driver = driver.ble_enable().unwrap();
+        assert!(backend.is_enabled());
+
+        // Dropping an open driver closes the adapter.
+        drop(driver);
+        assert!(!backend.is_open());
+
+        let driver = BleDriverBuilder::new("simulated")
+            .backend(backend.clone())
+            .build()
+            .unwrap();
+        let mut driver = AnyBleDriver::Configuring(driver.open_adapter().unwrap());
+        assert!(matches!(
+            driver.enabled().unwrap_err(),
+            Error::InvalidState(Lifecycle::Configuring)
+        ));
+        let mut driver = driver.ble_enable().unwrap();
+        assert!(driver.configuring().is_err());
+        let driver = driver.close().unwrap();
+        assert_eq!(driver.lifecycle(), Lifecycle::Closed);
+        assert!(!backend.is_open());
+    }
+}
@@ -0,0 +1,280 @@
+//! Several adapters driven side by side, e.g. a gateway with one dongle per
+//! antenna.
+//!
+//! ```no_run
+//! use nrf_sd_api::{gap::GapScanParameters, manager::AdapterManager, BleDriver};
+//!
+//! # async fn run() -> nrf_sd_api::Result<()> {
+//! let (mut manager, mut events) = AdapterManager::new();
+//! manager.open(BleDriver::new("/dev/ttyACM0")?)?;
+//! manager.open(BleDriver::new("/dev/ttyACM1")?)?;
+//! for (_, adapter) in manager.adapters() {
+//!     adapter.ble_enable().await?;
+//!     adapter.gap_scan_start(&GapScanParameters::default()).await?;
+//! }
+//! while let Some((adapter_id, event)) = events.recv().await {
+//!     println!("{}: {:?}", adapter_id, event);
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::{
+    handle::{BleDriverHandle, EventStream},
+    sd_api_v6::{BleDriver, EventType},
+    Error, Result,
+};
+#[cfg(feature = "pc-ble-driver")]
+use crate::serial_port::SerialPortInfo;
+use futures_core::Stream;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+
+/// Identifies an adapter within an `AdapterManager`. IDs are assigned in
+/// the order the adapters are opened and never reused.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct AdapterId(u32);
+
+impl AdapterId {
+    pub fn as_u32(&self) -> u32 {
+        self.0
+    }
+}
+
+impl fmt::Display for AdapterId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "adapter {}", self.0)
+    }
+}
+
+/// Opens several adapters and merges their events into one stream.
+///
+/// Every adapter runs on its own driver thread. Commands are issued to a
+/// specific adapter through its `BleDriverHandle`. Dropping the manager
+/// closes the adapters once their handles are no longer used elsewhere.
+#[derive(Debug)]
+pub struct AdapterManager {
+    adapters: BTreeMap<AdapterId, BleDriverHandle>,
+    next_id: u32,
+    merged: Arc<Mutex<MergedStreams>>,
+}
+
+/// Events of all adapters of an `AdapterManager`, tagged with the ID of
+/// the adapter they were received from.
+#[derive(Debug)]
+pub struct AdapterEventStream {
+    merged: Arc<Mutex<MergedStreams>>,
+}
+
+#[derive(Debug)]
+struct MergedStreams {
+    streams: Vec<(AdapterId, EventStream)>,
+    /// Index of the stream polled first, rotated so that a busy adapter
+    /// cannot starve the others.
+    next: usize,
+    waker: Option<Waker>,
+    manager_dropped: bool,
+}
+
+impl AdapterManager {
+    pub fn new() -> (AdapterManager, AdapterEventStream) {
+        let merged = Arc::new(Mutex::new(MergedStreams {
+            streams: Vec::new(),
+            next: 0,
+            waker: None,
+            manager_dropped: false,
+        }));
+        let manager = AdapterManager {
+            adapters: BTreeMap::new(),
+            next_id: 0,
+            merged: Arc::clone(&merged),
+        };
+
+        (manager, AdapterEventStream { merged })
+    }
+
+    /// Opens every Segger J-Link and nRF52840 dongle found by
+    /// `BleDriver::list_ports`.
+    #[cfg(feature = "pc-ble-driver")]
+    pub fn open_all_nordic() -> Result<(AdapterManager, AdapterEventStream)> {
+        let ports: Vec<_> = BleDriver::list_ports()?
+            .into_iter()
+            .filter(SerialPortInfo::is_nordic_device)
+            .collect();
+        if ports.is_empty() {
+            return Err(Error::NoAdapterFound);
+        }
+
+        let (mut manager, events) = AdapterManager::new();
+        for port in ports {
+            manager.open(BleDriver::new(&port.port)?)?;
+        }
+        Ok((manager, events))
+    }
+
+    /// Opens `driver` and adds its events to the merged stream.
+    pub fn open(&mut self, driver: BleDriver) -> Result<AdapterId> {
+        let (handle, events) = driver.open()?;
+        let id = AdapterId(self.next_id);
+        self.next_id += 1;
+        self.adapters.insert(id, handle);
+
+        let mut merged = self.merged.lock().unwrap();
+        merged.streams.push((id, events));
+        if let Some(waker) = merged.waker.take() {
+            waker.wake();
+        }
+        Ok(id)
+    }
+
+    /// Handle for issuing commands to the adapter `id`.
+    pub fn adapter(&self, id: AdapterId) -> Option<&BleDriverHandle> {
+        self.adapters.get(&id)
+    }
+
+    /// All adapters in the order they were opened.
+    pub fn adapters(&self) -> impl Iterator<Item = (AdapterId, &BleDriverHandle)> {
+        self.adapters.iter().map(|(id, handle)| (*id, handle))
+    }
+
+    pub fn len(&self) -> usize {
+        self.adapters.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.adapters.is_empty()
+    }
+
+    /// Closes the adapter `id` and removes it from the manager. Events it
+    /// has already received are still delivered.
+    pub async fn close(&mut self, id: AdapterId) -> Result<()> {
+        let handle = self.adapters.remove(&id).ok_or(Error::UnknownAdapter(id))?;
+        handle.close().await
+    }
+}
+
+impl Drop for AdapterManager {
+    fn drop(&mut self) {
+        let mut merged = self.merged.lock().unwrap();
+        merged.manager_dropped = true;
+        if let Some(waker) = merged.waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+impl AdapterEventStream {
+    pub(crate) fn poll_recv(&mut self, cx: &mut Context<'_>) -> Poll<Option<(AdapterId, EventType)>> {
+        let mut merged = self.merged.lock().unwrap();
+        let merged = &mut *merged;
+
+        let count = merged.streams.len();
+        let mut finished = Vec::new();
+        for offset in 0..count {
+            let index = (merged.next + offset) % count;
+            let (id, stream) = &mut merged.streams[index];
+            match stream.poll_recv(cx) {
+                Poll::Ready(Some(event)) => {
+                    let id = *id;
+                    merged.next = (index + 1) % count;
+                    return Poll::Ready(Some((id, event)));
+                }
+                Poll::Ready(None) => finished.push(index),
+                Poll::Pending => {}
+            }
+        }
+
+        // Forget the adapters whose driver has gone away.
+        finished.sort_unstable();
+        for index in finished.into_iter().rev() {
+            merged.streams.remove(index);
+        }
+        merged.next = 0;
+        if merged.streams.is_empty() && merged.manager_dropped {
+            return Poll::Ready(None);
+        }
+        merged.waker = Some(cx.waker().clone());
+        Poll::Pending
+    }
+
+    /// Waits for the next event of any adapter. Returns `None` once the
+    /// manager and all of its adapters have been dropped.
+    pub async fn recv(&mut self) -> Option<(AdapterId, EventType)> {
+        future::poll_fn(|cx| self.poll_recv(cx)).await
+    }
+}
+
+impl Stream for AdapterEventStream {
+    type Item = (AdapterId, EventType);
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.poll_recv(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gap::{GapEvent, GapScanParameters};
+    use crate::simulated::SimulatedBackend;
+    use crate::test_support::{advertising_report, simulated_driver};
+    use bytes::Bytes;
+    use std::time::Duration;
+
+    fn simulated(data: &'static [u8]) -> BleDriver {
+        simulated_driver(SimulatedBackend::new().advertising_report(advertising_report(data))).1
+    }
+
+    async fn next_report(events: &mut AdapterEventStream) -> (AdapterId, Bytes) {
+        loop {
+            let event = tokio::time::timeout(Duration::from_secs(1), events.recv())
+                .await
+                .expect("no advertising report received");
+            if let Some((id, EventType::BleGap(_, GapEvent::AdvertisingReport(report)))) = event {
+                return (id, report.data);
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn merges_events_of_all_adapters() {
+        let (mut manager, mut events) = AdapterManager::new();
+        let first = manager.open(simulated(b"first")).unwrap();
+        let second = manager.open(simulated(b"second")).unwrap();
+        assert_ne!(first, second);
+        assert_eq!(manager.len(), 2);
+
+        let adapter = manager.adapter(first).unwrap();
+        adapter.ble_enable().await.unwrap();
+        adapter.gap_scan_start(&GapScanParameters::default()).await.unwrap();
+        assert_eq!(next_report(&mut events).await, (first, Bytes::from_static(b"first")));
+
+        // Commands only reach the adapter they target.
+        let adapter = manager.adapter(second).unwrap();
+        adapter.ble_enable().await.unwrap();
+        manager.close(first).await.unwrap();
+        assert!(manager.adapter(first).is_none());
+        assert!(matches!(manager.close(first).await, Err(Error::UnknownAdapter(_))));
+
+        let adapter = manager.adapter(second).unwrap();
+        adapter.gap_scan_start(&GapScanParameters::default()).await.unwrap();
+        loop {
+            let (id, data) = next_report(&mut events).await;
+            if id == second {
+                assert_eq!(data, Bytes::from_static(b"second"));
+                break;
+            }
+        }
+
+        drop(manager);
+        while tokio::time::timeout(Duration::from_secs(1), events.recv())
+            .await
+            .unwrap()
+            .is_some()
+        {}
+    }
+}
@@ -1,39 +1,159 @@
+pub mod backend;
 pub mod ble_driver;
+pub mod builder;
+#[cfg(feature = "pc-ble-driver")]
+pub mod capture;
+pub mod event_bus;
+pub mod event_queue;
 pub mod gap;
 pub mod ble;
+pub mod blocking;
+#[cfg(feature = "pc-ble-driver")]
+mod buffer;
 pub mod gatt;
 pub mod gattc;
 pub mod gatts;
+pub mod handle;
+pub mod lifecycle;
+pub mod manager;
+pub mod native;
+#[cfg(feature = "pc-ble-driver")]
+pub mod pc_ble_driver;
+pub mod pcap;
+pub mod recovery;
+pub mod rpc;
+mod rt;
+pub mod serial_port;
+pub mod simulated;
+pub mod stats;
+pub mod version;
+mod trace;
+#[cfg(test)]
+pub(crate) mod test_support;
 
 
-use nrf_ble_driver_sys::ffi;
-use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender};
+use std::marker::PhantomData;
+use std::sync::atomic::AtomicBool;
+use std::sync::{Arc, Condvar, Mutex, Weak};
 
+use self::backend::BleBackend;
+use self::ble::AdapterInfo;
+use self::builder::TransportConfig;
+use self::event_bus::{EventCategory, EventSender};
 use self::gap::GapEvent;
+use self::lifecycle::{Closed, State};
+use self::pcap::PcapWriter;
+use self::recovery::RecoveryState;
+use self::stats::StatsCollector;
+use self::rpc::{LogSeverity, RpcStatus};
 
 
 pub type BluetoothAddress = [u8; 6];
 
+const BLE_CONN_HANDLE_INVALID: u16 = 0xffff;
 
+
+/// Driver of one connectivity adapter, in lifecycle state `S`.
+///
+/// See `lifecycle` for the states and the calls each of them allows.
 #[derive(Debug)]
-pub struct BleDriver {
-    adapter: *mut ffi::adapter_t,
-    adv_data: Box<ffi::ble_data_t>,
+pub struct BleDriver<S: State = Closed> {
+    // Boxed so that transitions and `TransitionError`s only move a pointer.
+    adapter: Box<Adapter>,
+    state: PhantomData<S>,
+}
+
+/// The part of a `BleDriver` that is kept across state transitions.
+/// Closes the adapter when dropped.
+#[derive(Debug)]
+pub(crate) struct Adapter {
+    core: Arc<DriverCore>,
     is_open: bool,
-    event_receiver: UnboundedReceiver<EventType>,
-    callback_event: UnboundedSender<EventType>,
-    is_scanning: bool,
 }
 
+/// State shared with the backend's event callbacks.
+///
+/// Backends reach the core through a weak reference, so callbacks running
+/// on the backend's own threads stay valid however often the owning
+/// `BleDriver` is moved. They only ever take a shared reference, so
+/// everything they touch is atomic or `Sync`.
 #[derive(Debug)]
+pub(crate) struct DriverCore {
+    this: Weak<DriverCore>,
+    backend: Arc<dyn BleBackend>,
+    callback_event: EventSender,
+    is_scanning: AtomicBool,
+    log_events: AtomicBool,
+    auto_recover: AtomicBool,
+    recovering: AtomicBool,
+    shutdown: AtomicBool,
+    recovery: Mutex<RecoveryState>,
+    /// Set while `BleDriver::reset` waits for the firmware to restart.
+    resetting: AtomicBool,
+    /// Number of `RESET_PERFORMED` statuses received while resetting.
+    resets_performed: Mutex<u64>,
+    reset_performed: Condvar,
+    pcap: Mutex<Option<PcapWriter>>,
+    stats: Arc<StatsCollector>,
+    config: TransportConfig,
+    /// Version information of the firmware, read again after every reset.
+    /// Shared with the `BleDriverHandle`s of the driver.
+    adapter_info: Arc<Mutex<Option<AdapterInfo>>>,
+}
+
+/// Events received from the adapter. BLE events carry the connection
+/// handle they belong to, or `BLE_CONN_HANDLE_INVALID` if they belong to none.
+#[derive(Debug, Clone)]
 pub enum EventType {
-    RpcLog(i32, String),
-    RpcStatus(i32, String),
-    BleCommon(u32),
-    BleGap(GapEvent),
-    BleGattClient(u32),
-    BleGattServer(u32),
-    BleL2cap(u32),
+    RpcLog(LogSeverity, String),
+    RpcStatus(RpcStatus, String),
+    BleCommon(u16, u32),
+    BleGap(u16, GapEvent),
+    BleGattClient(u16, u32),
+    BleGattServer(u16, u32),
+    BleL2cap(u16, u32),
+    /// The link to the connectivity firmware was lost and has been restored.
+    Reconnected,
+    /// Recovery after a link loss gave up, reporting the last error. The
+    /// driver has to be closed.
+    RecoveryFailed(String),
     Unknown(u32),
     Invalid,
+}
+
+impl EventType {
+    pub fn category(&self) -> EventCategory {
+        match self {
+            EventType::RpcLog(..) | EventType::RpcStatus(..) => EventCategory::Rpc,
+            EventType::BleCommon(..) => EventCategory::Common,
+            EventType::BleGap(..) => EventCategory::Gap,
+            EventType::BleGattClient(..) => EventCategory::GattClient,
+            EventType::BleGattServer(..) => EventCategory::GattServer,
+            EventType::BleL2cap(..) => EventCategory::L2cap,
+            EventType::Reconnected
+            | EventType::RecoveryFailed(_)
+            | EventType::Unknown(_)
+            | EventType::Invalid => {
+                EventCategory::Driver
+            }
+        }
+    }
+
+    /// Connection handle of a BLE event, if it belongs to a connection.
+    pub fn connection_handle(&self) -> Option<u16> {
+        let handle = match self {
+            EventType::BleCommon(handle, _)
+            | EventType::BleGap(handle, _)
+            | EventType::BleGattClient(handle, _)
+            | EventType::BleGattServer(handle, _)
+            | EventType::BleL2cap(handle, _) => *handle,
+            _ => return None,
+        };
+
+        if handle == BLE_CONN_HANDLE_INVALID {
+            None
+        } else {
+            Some(handle)
+        }
+    }
 }
\ No newline at end of file
@@ -0,0 +1,437 @@
+//! `BleBackend` that talks to the connectivity firmware without
+//! pc-ble-driver: SoftDevice calls are encoded with `crate::codec` and
+//! carried over the three-wire link of `crate::h5`.
+//!
+//! ```no_run
+//! use nrf_sd_api::builder::{BleDriverBuilder, LinkLayer};
+//!
+//! let driver = BleDriverBuilder::new("/dev/ttyACM0")
+//!     .link_layer(LinkLayer::Native)
+//!     .build()
+//!     .unwrap();
+//! ```
+
+use crate::{backend::{BleBackend, EventSink}, builder::TransportConfig, codec::{command::opcode, Command, Config, Event, Response, PACKET_TYPE_EVENT, PACKET_TYPE_RESET_COMMAND, PACKET_TYPE_RESPONSE}, gap::GapScanParameters, h5::H5Link, rpc::{LogSeverity, ResetMode, RpcStatus}, Error, Result};
+use std::fs::File;
+use std::io::{self, Read};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+const NRF_ERROR_NOT_SUPPORTED: u32 = 6;
+const NRF_ERROR_INVALID_STATE: u32 = 8;
+/// Size of the advertising report buffer lent to the SoftDevice,
+/// `BLE_GAP_SCAN_BUFFER_EXTENDED_MAX_SUPPORTED`.
+const SCAN_BUFFER_LENGTH: u16 = 255;
+/// How often the dispatcher thread checks whether the backend was closed.
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+#[derive(Debug)]
+enum Delivery {
+    Event(Event),
+    Status(RpcStatus, String),
+}
+
+#[derive(Debug)]
+struct Connection {
+    link: Arc<H5Link<File>>,
+    /// Responses to commands, in the order the firmware sent them.
+    responses: Mutex<Receiver<Response>>,
+    deliveries: Sender<Delivery>,
+    closed: Arc<AtomicBool>,
+}
+
+/// A `BleBackend` driving the connectivity firmware over the native
+/// three-wire link layer, selected with `LinkLayer::Native`.
+///
+/// A dispatcher thread hands responses to the waiting command and queues
+/// events, which are delivered on a thread of their own so that event
+/// handlers may issue commands. Capture files and user memory blocks are
+/// not supported.
+#[derive(Debug)]
+pub struct NativeBackend {
+    config: TransportConfig,
+    connection: Mutex<Option<Arc<Connection>>>,
+}
+
+impl NativeBackend {
+    /// A backend for the serial port and link parameters in `config`. The
+    /// port is opened by `BleBackend::open`.
+    pub fn new(config: &TransportConfig) -> NativeBackend {
+        NativeBackend {
+            config: config.clone(),
+            connection: Mutex::new(None),
+        }
+    }
+
+    fn connection(&self, operation: &'static str) -> Result<Arc<Connection>> {
+        self.connection
+            .lock()
+            .unwrap()
+            .clone()
+            .ok_or_else(|| Error::nrf(operation, NRF_ERROR_INVALID_STATE))
+    }
+
+    /// Sends `command` and waits for the firmware's response to it.
+    fn call(&self, command: Command) -> Result<Response> {
+        let connection = self.connection(opcode::name(command.opcode()))?;
+        // One command at a time, as the firmware only answers in order.
+        let responses = connection.responses.lock().unwrap();
+        // Responses to earlier commands that timed out.
+        while responses.try_recv().is_ok() {}
+
+        connection.link.send(&command.encode())?;
+
+        let deadline = Instant::now() + self.config.response_timeout;
+        loop {
+            let timeout = deadline.saturating_duration_since(Instant::now());
+            match responses.recv_timeout(timeout) {
+                Ok(response) if response.opcode == command.opcode() => return Ok(response),
+                Ok(_) => {}
+                Err(RecvTimeoutError::Timeout) => return Err(Error::LinkTimeout),
+                Err(RecvTimeoutError::Disconnected) => return Err(Error::LinkClosed),
+            }
+        }
+    }
+}
+
+impl BleBackend for NativeBackend {
+    fn open(&self, events: EventSink) -> Result<()> {
+        let mut connection = self.connection.lock().unwrap();
+        if connection.is_some() {
+            return Err(Error::nrf("sd_rpc_open", NRF_ERROR_INVALID_STATE));
+        }
+
+        let port = open_serial_port(&self.config)?;
+        let reader = SerialReader(port.try_clone().map_err(Error::Io)?);
+        let link = Arc::new(H5Link::open(reader, port, self.config.h5_config())?);
+
+        let (response_sender, responses) = mpsc::channel();
+        let (deliveries, delivery_receiver) = mpsc::channel();
+        let closed = Arc::new(AtomicBool::new(false));
+        let _result = deliveries.send(Delivery::Status(
+            RpcStatus::ConnectionActive,
+            String::from("Connection active"),
+        ));
+
+        let dispatch_link = Arc::clone(&link);
+        let dispatch_deliveries = deliveries.clone();
+        let dispatch_closed = Arc::clone(&closed);
+        thread::spawn(move || {
+            dispatch(dispatch_link, dispatch_closed, response_sender, dispatch_deliveries)
+        });
+        thread::spawn(move || deliver(delivery_receiver, events));
+
+        *connection = Some(Arc::new(Connection {
+            link,
+            responses: Mutex::new(responses),
+            deliveries,
+            closed,
+        }));
+        Ok(())
+    }
+
+    fn close(&self) -> Result<()> {
+        let connection = self
+            .connection
+            .lock()
+            .unwrap()
+            .take()
+            .ok_or(Error::nrf("sd_rpc_close", NRF_ERROR_INVALID_STATE))?;
+        connection.closed.store(true, Ordering::Release);
+        connection.link.close();
+        Ok(())
+    }
+
+    fn set_log_severity_filter(&self, _severity: LogSeverity) -> Result<()> {
+        // Problems on the link are reported as statuses, not log messages.
+        Ok(())
+    }
+
+    fn conn_reset(&self, mode: ResetMode) -> Result<()> {
+        let connection = self.connection("sd_rpc_conn_reset")?;
+        let _guard = connection.responses.lock().unwrap();
+
+        // The chip may restart before it gets to acknowledge the reset.
+        match connection.link.send(&[PACKET_TYPE_RESET_COMMAND, mode as u8]) {
+            Ok(()) | Err(Error::LinkTimeout) => {}
+            Err(error) => return Err(error),
+        }
+        connection.link.reset()?;
+
+        let status = Delivery::Status(RpcStatus::ResetPerformed, String::from("Reset performed"));
+        let _result = connection.deliveries.send(status);
+        Ok(())
+    }
+
+    fn version_get(&self) -> Result<(u8, u16, u16)> {
+        self.call(Command::VersionGet)?.version()
+    }
+
+    fn cfg_set(&self, config: &Config) -> Result<()> {
+        self.call(Command::CfgSet(config.clone()))?.check()
+    }
+
+    fn ble_enable(&self) -> Result<()> {
+        self.call(Command::Enable { app_ram_base: Some(0) })?.check()
+    }
+
+    fn gap_scan_start(&self, parameters: Option<&GapScanParameters>) -> Result<()> {
+        self.call(Command::GapScanStart {
+            parameters: parameters.cloned(),
+            buffer_length: SCAN_BUFFER_LENGTH,
+        })?
+        .check()
+    }
+
+    fn gap_scan_stop(&self) -> Result<()> {
+        self.call(Command::GapScanStop)?.check()
+    }
+
+    fn user_mem_reply(&self, _connection_handle: u16, _length: Option<u16>) -> Result<()> {
+        self.connection("sd_ble_user_mem_reply")?;
+        Err(Error::nrf("sd_ble_user_mem_reply", NRF_ERROR_NOT_SUPPORTED))
+    }
+}
+
+/// Sorts the packets received over `link` into command responses and
+/// events until the link is closed.
+fn dispatch(
+    link: Arc<H5Link<File>>,
+    closed: Arc<AtomicBool>,
+    responses: Sender<Response>,
+    deliveries: Sender<Delivery>,
+) {
+    while !closed.load(Ordering::Acquire) {
+        let packet = match link.recv_timeout(POLL_INTERVAL) {
+            Ok(Some(packet)) => packet,
+            Ok(None) => continue,
+            Err(_) => {
+                if !closed.load(Ordering::Acquire) {
+                    let status = Delivery::Status(
+                        RpcStatus::IoResourcesUnavailable,
+                        String::from("Serial port closed"),
+                    );
+                    let _result = deliveries.send(status);
+                }
+                break;
+            }
+        };
+
+        let delivery = match packet.first() {
+            Some(&PACKET_TYPE_RESPONSE) => match Response::decode(&packet) {
+                Ok(response) => {
+                    let _result = responses.send(response);
+                    continue;
+                }
+                Err(error) => Delivery::Status(RpcStatus::PacketDecodeError, error.to_string()),
+            },
+            Some(&PACKET_TYPE_EVENT) => match Event::decode(&packet) {
+                Ok(event) => Delivery::Event(event),
+                Err(error) => Delivery::Status(RpcStatus::PacketDecodeError, error.to_string()),
+            },
+            packet_type => Delivery::Status(
+                RpcStatus::PacketUnexpected,
+                format!("Unexpected packet type {:?}", packet_type),
+            ),
+        };
+        let _result = deliveries.send(delivery);
+    }
+}
+
+fn deliver(deliveries: Receiver<Delivery>, events: EventSink) {
+    for delivery in deliveries {
+        match delivery {
+            Delivery::Event(event) => {
+                events.traffic(&event);
+                events.event(event.into());
+            }
+            Delivery::Status(status, message) => events.status(status, message),
+        }
+    }
+}
+
+/// Reads from a serial port opened by `open_serial_port`, whose reads
+/// return nothing after a short timeout.
+#[derive(Debug)]
+struct SerialReader(File);
+
+impl Read for SerialReader {
+    fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+        match self.0.read(buffer)? {
+            0 => Err(io::ErrorKind::TimedOut.into()),
+            count => Ok(count),
+        }
+    }
+}
+
+/// Opens the serial port in raw mode with the baud rate, flow control and
+/// parity of `config`.
+#[cfg(unix)]
+fn open_serial_port(config: &TransportConfig) -> Result<File> {
+    use crate::builder::{FlowControl, Parity};
+    use std::fs::OpenOptions;
+    use std::os::unix::fs::OpenOptionsExt;
+    use std::os::unix::io::AsRawFd;
+
+    let port = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .custom_flags(libc::O_NOCTTY)
+        .open(&config.port_name)
+        .map_err(Error::Io)?;
+    let fd = port.as_raw_fd();
+
+    unsafe {
+        let mut termios = std::mem::zeroed();
+        if libc::tcgetattr(fd, &mut termios) != 0 {
+            return Err(Error::Io(io::Error::last_os_error()));
+        }
+        libc::cfmakeraw(&mut termios);
+        let speed = speed(config.baud_rate.as_u32());
+        if libc::cfsetispeed(&mut termios, speed) != 0 || libc::cfsetospeed(&mut termios, speed) != 0 {
+            return Err(Error::Io(io::Error::last_os_error()));
+        }
+
+        termios.c_cflag |= libc::CLOCAL | libc::CREAD;
+        match config.flow_control {
+            FlowControl::None => termios.c_cflag &= !libc::CRTSCTS,
+            FlowControl::Hardware => termios.c_cflag |= libc::CRTSCTS,
+        }
+        match config.parity {
+            Parity::None => termios.c_cflag &= !libc::PARENB,
+            Parity::Even => termios.c_cflag = (termios.c_cflag | libc::PARENB) & !libc::PARODD,
+        }
+        // Return after at most 100 ms without data, see `SerialReader`.
+        termios.c_cc[libc::VMIN] = 0;
+        termios.c_cc[libc::VTIME] = 1;
+
+        if libc::tcsetattr(fd, libc::TCSANOW, &termios) != 0 {
+            return Err(Error::Io(io::Error::last_os_error()));
+        }
+    }
+
+    Ok(port)
+}
+
+#[cfg(not(unix))]
+fn open_serial_port(_config: &TransportConfig) -> Result<File> {
+    Err(Error::InvalidConfiguration("the native link layer requires a Unix serial port"))
+}
+
+#[cfg(target_os = "linux")]
+fn speed(baud_rate: u32) -> libc::speed_t {
+    match baud_rate {
+        115_200 => libc::B115200,
+        230_400 => libc::B230400,
+        460_800 => libc::B460800,
+        921_600 => libc::B921600,
+        _ => libc::B1000000,
+    }
+}
+
+/// Other Unix systems take the baud rate itself.
+#[cfg(all(unix, not(target_os = "linux")))]
+fn speed(baud_rate: u32) -> libc::speed_t {
+    baud_rate as libc::speed_t
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use super::*;
+    use crate::blocking::BlockingBleDriver;
+    use crate::builder::{BleDriverBuilder, LinkLayer};
+    use crate::codec::event::event_id;
+    use crate::codec::PACKET_TYPE_COMMAND;
+    use crate::gap::{GapEvent, GapScanParameters};
+    use crate::h5::H5Config;
+    use crate::test_support::pty_pair;
+    use crate::EventType;
+    use std::os::unix::io::AsRawFd;
+
+    /// Connectivity firmware on the other end of the pty. Answers every
+    /// command successfully, reports a scan timeout after scanning starts
+    /// and returns the opcodes received once the port is closed.
+    fn firmware(port: File) -> thread::JoinHandle<Vec<u8>> {
+        thread::spawn(move || {
+            let link = H5Link::open(port.try_clone().unwrap(), port, H5Config::default()).unwrap();
+            let mut opcodes = Vec::new();
+
+            while let Ok(packet) = link.recv_timeout(Duration::from_secs(5)) {
+                let packet = packet.expect("no packet from the driver");
+                let opcode = match packet[..] {
+                    [PACKET_TYPE_RESET_COMMAND, _] => {
+                        link.reset().unwrap();
+                        continue;
+                    }
+                    [PACKET_TYPE_COMMAND, opcode, ..] => opcode,
+                    _ => panic!("unexpected packet {:02x?}", packet),
+                };
+                opcodes.push(opcode);
+
+                let mut response = vec![PACKET_TYPE_RESPONSE, opcode, 0, 0, 0, 0];
+                if opcode == opcode::SD_BLE_VERSION_GET {
+                    // S140 v6.1.0.
+                    response.extend_from_slice(&[0x09, 0x59, 0x00, 0xb6, 0x00]);
+                }
+                link.send(&response).unwrap();
+
+                if opcode == opcode::SD_BLE_GAP_SCAN_START {
+                    let mut event = vec![PACKET_TYPE_EVENT];
+                    event.extend_from_slice(&event_id::BLE_GAP_EVT_TIMEOUT.to_le_bytes());
+                    // No connection, BLE_GAP_TIMEOUT_SRC_SCAN.
+                    event.extend_from_slice(&[0xff, 0xff, 0x01]);
+                    link.send(&event).unwrap();
+                }
+            }
+            opcodes
+        })
+    }
+
+    #[test]
+    fn drives_firmware_over_pty() {
+        let (master, slave) = pty_pair();
+        let port = std::fs::read_link(format!("/proc/self/fd/{}", slave.as_raw_fd())).unwrap();
+        let firmware = firmware(master);
+
+        let driver = BleDriverBuilder::new(port.to_str().unwrap())
+            .link_layer(LinkLayer::Native)
+            .build()
+            .unwrap();
+        let mut adapter = BlockingBleDriver::open(driver).unwrap();
+        assert!(adapter.adapter_info().unwrap().is_compatible());
+        adapter.gap_set_connection_config(1, 1, 6).unwrap();
+        adapter.ble_enable().unwrap();
+        adapter.gap_scan_start(&GapScanParameters::default()).unwrap();
+
+        loop {
+            match adapter.recv_event_timeout(Duration::from_secs(1)).unwrap() {
+                Some(EventType::BleGap(_, GapEvent::Timeout(source))) => {
+                    assert_eq!(source, 1);
+                    break;
+                }
+                Some(_) => {}
+                None => panic!("no scan timeout received"),
+            }
+        }
+
+        // The link is set up again after the reset and the version read anew.
+        adapter.reset(ResetMode::SoftReset).unwrap();
+        adapter.close().unwrap();
+
+        // The firmware's link closes once no end of the pty is left open.
+        drop(slave);
+        assert_eq!(
+            firmware.join().unwrap(),
+            [
+                opcode::SD_BLE_VERSION_GET,
+                opcode::SD_BLE_CFG_SET,
+                opcode::SD_BLE_ENABLE,
+                opcode::SD_BLE_GAP_SCAN_START,
+                opcode::SD_BLE_VERSION_GET,
+            ]
+        );
+    }
+}
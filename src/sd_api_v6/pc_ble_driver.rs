@@ -0,0 +1,456 @@
+use crate::{backend::{BleBackend, EventSink}, builder::TransportConfig, capture::CaptureWriter, codec::{BleUuid, Config, Event}, gap::{GapEvent, GapScanParameters}, rpc::{LogSeverity, ResetMode, RpcStatus}, sd_api_v6::{buffer::{BufferRegistry, BufferUse, SoftDeviceBuffer}, *}, Error, Result};
+use nrf_ble_driver_sys::ffi;
+use num_enum::TryFromPrimitive;
+use lazy_static::lazy_static;
+use std::collections::HashMap;
+use std::ffi::{CStr, CString};
+use std::os::raw::c_char;
+use bytes::Bytes;
+use std::{ptr, slice};
+use std::sync::{Arc, Mutex};
+
+/// `BleBackend` implemented with the pc-ble-driver C library, talking to
+/// the connectivity firmware over a serial port.
+#[derive(Debug)]
+pub struct PcBleDriverBackend {
+    adapter: *mut ffi::adapter_t,
+    /// Buffers the SoftDevice writes into, freed as it releases them.
+    buffers: Arc<BufferRegistry>,
+    capture: Option<Arc<Mutex<CaptureWriter>>>,
+}
+
+/// What the pc-ble-driver callbacks need to reach.
+#[derive(Debug)]
+struct Callbacks {
+    events: EventSink,
+    buffers: Arc<BufferRegistry>,
+    capture: Option<Arc<Mutex<CaptureWriter>>>,
+}
+
+lazy_static! {
+    /// Callbacks of every open adapter, keyed by its `adapter_t` address.
+    ///
+    /// pc-ble-driver calls the same handlers for all adapters, so they look
+    /// up the adapter they are called for here instead of trusting
+    /// `user_data`. Entries are removed before the adapter is deleted, so a
+    /// late callback finds nothing rather than freed memory.
+    static ref ADAPTERS: Mutex<HashMap<usize, Arc<Callbacks>>> = Mutex::new(HashMap::new());
+}
+
+// SAFETY: the adapter pointer and the lent buffers are only handed to
+// pc-ble-driver, which serializes access to them internally.
+unsafe impl Send for PcBleDriverBackend {}
+unsafe impl Sync for PcBleDriverBackend {}
+
+fn check(operation: &'static str, error_code: u32) -> Result<()> {
+    if error_code == ffi::NRF_SUCCESS {
+        Ok(())
+    } else {
+        Err(Error::nrf(operation, error_code))
+    }
+}
+
+impl PcBleDriverBackend {
+    /// Creates the UART physical layer, the three-wire data link layer and
+    /// the transport layer of an adapter described by `config`.
+    pub fn new(config: &TransportConfig) -> Result<PcBleDriverBackend> {
+        let port = CString::new(config.port_name.as_str()).map_err(Error::NullError)?;
+        let (baud_rate, flow_control, parity, retransmission_interval, response_timeout) =
+            config.to_ffi();
+        let adapter = unsafe {
+            let phy =
+                ffi::sd_rpc_physical_layer_create_uart(port.as_ptr(), baud_rate, flow_control, parity);
+
+            if phy.is_null() {
+                return Err(Error::InitializationError("sd_rpc_physical_layer_create_uart"));
+            }
+
+            let link_layer =
+                ffi::sd_rpc_data_link_layer_create_bt_three_wire(phy, retransmission_interval);
+            if link_layer.is_null() {
+                return Err(Error::InitializationError("sd_rpc_data_link_layer_create_bt_three_wire"));
+            }
+
+            let transport_layer = ffi::sd_rpc_transport_layer_create(link_layer, response_timeout);
+            if transport_layer.is_null() {
+                return Err(Error::InitializationError("sd_rpc_transport_layer_create"));
+            }
+
+            let adapter = ffi::sd_rpc_adapter_create(transport_layer);
+            if adapter.is_null() {
+                return Err(Error::InitializationError("sd_rpc_adapter_create"));
+            }
+            adapter
+        };
+
+        Ok(PcBleDriverBackend {
+            adapter,
+            buffers: Arc::new(BufferRegistry::new()),
+            capture: None,
+        })
+    }
+
+    /// Records every event, status change and the firmware version to
+    /// `capture` from the next time the adapter is opened.
+    pub fn set_capture(&mut self, capture: CaptureWriter) {
+        self.capture = Some(Arc::new(Mutex::new(capture)));
+    }
+}
+
+impl BleBackend for PcBleDriverBackend {
+    fn open(&self, events: EventSink) -> Result<()> {
+        let callbacks = Callbacks {
+            events,
+            buffers: Arc::clone(&self.buffers),
+            capture: self.capture.clone(),
+        };
+        ADAPTERS
+            .lock()
+            .unwrap()
+            .insert(self.adapter as usize, Arc::new(callbacks));
+
+        unsafe {
+            check("sd_rpc_open", ffi::sd_rpc_open(
+                self.adapter,
+                Some(sd_rpc_status_handler),
+                Some(sd_rpc_event_handler),
+                Some(sd_rpc_log_handler),
+                ptr::null_mut(),
+            ))
+        }
+    }
+
+    fn close(&self) -> Result<()> {
+        unsafe { check("sd_rpc_close", ffi::sd_rpc_close(self.adapter))? }
+        self.buffers.release_all();
+        Ok(())
+    }
+
+    fn set_log_severity_filter(&self, severity: LogSeverity) -> Result<()> {
+        unsafe {
+            check("sd_rpc_log_handler_severity_filter_set", ffi::sd_rpc_log_handler_severity_filter_set(
+                self.adapter,
+                severity.to_ffi(),
+            ))
+        }
+    }
+
+    fn conn_reset(&self, mode: ResetMode) -> Result<()> {
+        unsafe { check("sd_rpc_conn_reset", ffi::sd_rpc_conn_reset(self.adapter, mode.to_ffi())) }
+    }
+
+    fn version_get(&self) -> Result<(u8, u16, u16)> {
+        let mut version = ffi::ble_version_t::default();
+        unsafe {
+            check("sd_ble_version_get", ffi::sd_ble_version_get(self.adapter, &mut version))?;
+        }
+        let version = (version.version_number, version.company_id, version.subversion_number);
+        if let Some(capture) = &self.capture {
+            let _result = capture.lock().unwrap().version(version);
+        }
+        Ok(version)
+    }
+
+    fn cfg_set(&self, config: &Config) -> Result<()> {
+        let ble_config = config_to_ffi(config);
+        unsafe { check("sd_ble_cfg_set", ffi::sd_ble_cfg_set(self.adapter, config.cfg_id(), &ble_config, 0)) }
+    }
+
+    fn ble_enable(&self) -> Result<()> {
+        let mut ram_base: u32 = 0;
+        unsafe { check("sd_ble_enable", ffi::sd_ble_enable(self.adapter, &mut ram_base)) }
+    }
+
+    fn gap_scan_start(&self, parameters: Option<&GapScanParameters>) -> Result<()> {
+        let parameters = match parameters {
+            // Resuming hands the paused buffer back to the SoftDevice.
+            None => {
+                let error_code = self.buffers.with(BufferUse::ScanReport, |buffer| unsafe {
+                    ffi::sd_ble_gap_scan_start(self.adapter, ptr::null(), buffer.ble_data())
+                });
+                return check("sd_ble_gap_scan_start", error_code.unwrap_or(ffi::NRF_ERROR_INVALID_STATE));
+            }
+            Some(parameters) => parameters,
+        };
+
+        let scan_params = ffi::ble_gap_scan_params_t {
+            _bitfield_align_1: [0; 0],
+            _bitfield_1: ffi::ble_gap_scan_params_t::new_bitfield_1(
+                parameters.extended,
+                0, // not supported in this softdevice
+                parameters.active,
+                parameters.filter_policy,
+            ),
+            scan_phys: parameters.scan_phys,
+            interval: parameters.interval,
+            window: parameters.window,
+            timeout: parameters.timeout,
+            channel_mask: parameters.channel_mask,
+        };
+        // The first report can arrive before the call returns, so the buffer
+        // has to be registered for resuming before the SoftDevice gets it.
+        let buffer = SoftDeviceBuffer::new(ffi::BLE_GAP_SCAN_BUFFER_EXTENDED_MAX as u16);
+        let ble_data = buffer.ble_data();
+        let previous = self.buffers.lend(BufferUse::ScanReport, buffer);
+        let result = unsafe {
+            check("sd_ble_gap_scan_start", ffi::sd_ble_gap_scan_start(self.adapter, &scan_params, ble_data))
+        };
+        if result.is_err() {
+            // Rejected, so the SoftDevice still uses the buffer it had before.
+            match previous {
+                Some(previous) => {
+                    self.buffers.lend(BufferUse::ScanReport, previous);
+                }
+                None => {
+                    self.buffers.release(BufferUse::ScanReport);
+                }
+            }
+        }
+        result
+    }
+
+    fn gap_scan_stop(&self) -> Result<()> {
+        unsafe { check("sd_ble_gap_scan_stop", ffi::sd_ble_gap_scan_stop(self.adapter))? }
+        self.buffers.release(BufferUse::ScanReport);
+        Ok(())
+    }
+
+    fn user_mem_reply(&self, connection_handle: u16, length: Option<u16>) -> Result<()> {
+        let buffer = length.map(SoftDeviceBuffer::new);
+        let block = buffer.as_ref().map_or(ptr::null(), SoftDeviceBuffer::mem_block);
+        unsafe {
+            check("sd_ble_user_mem_reply", ffi::sd_ble_user_mem_reply(self.adapter, connection_handle, block))?;
+        }
+        if let Some(buffer) = buffer {
+            self.buffers.lend(BufferUse::UserMemory { connection_handle }, buffer);
+        }
+        Ok(())
+    }
+}
+
+impl Drop for PcBleDriverBackend {
+    fn drop(&mut self) {
+        ADAPTERS.lock().unwrap().remove(&(self.adapter as usize));
+        unsafe {
+            ffi::sd_rpc_adapter_delete(self.adapter);
+        }
+    }
+}
+
+fn config_to_ffi(config: &Config) -> ffi::ble_cfg_t {
+    let conn_cfg = |conn_cfg_tag, params| ffi::ble_cfg_t {
+        conn_cfg: ffi::ble_conn_cfg_t {
+            conn_cfg_tag,
+            params,
+        },
+    };
+
+    match config {
+        Config::GapConnection {
+            connection_tag,
+            connection_count,
+            event_length,
+        } => conn_cfg(
+            *connection_tag,
+            ffi::ble_conn_cfg_t__bindgen_ty_1 {
+                gap_conn_cfg: ffi::ble_gap_conn_cfg_t {
+                    conn_count: *connection_count,
+                    event_length: *event_length,
+                },
+            },
+        ),
+        Config::GapRoleCount(role_count) => ffi::ble_cfg_t {
+            gap_cfg: ffi::ble_gap_cfg_t {
+                role_count_cfg: ffi::ble_gap_cfg_role_count_t {
+                    adv_set_count: role_count.advertising_set_count,
+                    periph_role_count: role_count.peripheral_role_count,
+                    central_role_count: role_count.central_role_count,
+                    central_sec_count: role_count.central_security_count,
+                    _bitfield_align_1: [0; 0],
+                    _bitfield_1: ffi::ble_gap_cfg_role_count_t::new_bitfield_1(
+                        role_count.qos_channel_survey_role_available,
+                    ),
+                },
+            },
+        },
+        Config::GattConnection {
+            connection_tag,
+            att_mtu,
+        } => conn_cfg(
+            *connection_tag,
+            ffi::ble_conn_cfg_t__bindgen_ty_1 {
+                gatt_conn_cfg: ffi::ble_gatt_conn_cfg_t { att_mtu: *att_mtu },
+            },
+        ),
+        Config::GattcConnection {
+            connection_tag,
+            write_cmd_tx_queue_size,
+        } => conn_cfg(
+            *connection_tag,
+            ffi::ble_conn_cfg_t__bindgen_ty_1 {
+                gattc_conn_cfg: ffi::ble_gattc_conn_cfg_t {
+                    write_cmd_tx_queue_size: *write_cmd_tx_queue_size,
+                },
+            },
+        ),
+        Config::GattsConnection {
+            connection_tag,
+            hvn_tx_queue_size,
+        } => conn_cfg(
+            *connection_tag,
+            ffi::ble_conn_cfg_t__bindgen_ty_1 {
+                gatts_conn_cfg: ffi::ble_gatts_conn_cfg_t {
+                    hvn_tx_queue_size: *hvn_tx_queue_size,
+                },
+            },
+        ),
+    }
+}
+
+pub(crate) unsafe fn event_from_ffi(ble_event: *const ffi::ble_evt_t) -> EventType {
+    let event_id: u32 = (*ble_event).header.evt_id.into();
+
+    let evt = &(*ble_event).evt;
+
+    match event_id {
+        ffi::BLE_EVT_INVALID => EventType::Invalid,
+        id@ ffi::BLE_EVT_BASE..=ffi::BLE_EVT_LAST => EventType::BleCommon(evt.common_evt.conn_handle, id),
+        id@ffi::BLE_GAP_EVT_BASE..=ffi::BLE_GAP_EVT_LAST => EventType::BleGap(evt.gap_evt.conn_handle, GapEvent::from_ffi(id, &evt.gap_evt)),
+        id@ffi::BLE_GATTC_EVT_BASE..=ffi::BLE_GATTC_EVT_LAST => EventType::BleGattClient(evt.gattc_evt.conn_handle, id),
+        id@ffi::BLE_GATTS_EVT_BASE..=ffi::BLE_GATTS_EVT_LAST => EventType::BleGattServer(evt.gatts_evt.conn_handle, id),
+        id@ffi::BLE_L2CAP_EVT_BASE..=ffi::BLE_L2CAP_EVT_LAST => EventType::BleL2cap(evt.l2cap_evt.conn_handle, id),
+        id => EventType::Unknown(id),
+    }
+}
+
+/// Delivers an event received from pc-ble-driver to `events`.
+///
+/// # Safety
+///
+/// `ble_event` must point to a valid event buffer.
+pub(crate) unsafe fn forward_event(events: &EventSink, ble_event: *const ffi::ble_evt_t) {
+    if let Some(traffic) = gatt_traffic_from_ffi(ble_event) {
+        events.traffic(&traffic);
+    }
+    events.event(event_from_ffi(ble_event));
+}
+
+/// Decodes the GATT events carrying an attribute value, which `EventType`
+/// does not include.
+unsafe fn gatt_traffic_from_ffi(ble_event: *const ffi::ble_evt_t) -> Option<Event> {
+    let evt = &(*ble_event).evt;
+    // Attribute values extend past the declared one-byte array.
+    let value = |data: &[u8; 1], len: u16| {
+        Bytes::copy_from_slice(slice::from_raw_parts(data.as_ptr(), len as usize))
+    };
+
+    let event = match (*ble_event).header.evt_id as u32 {
+        ffi::BLE_GATTC_EVTS_BLE_GATTC_EVT_READ_RSP => {
+            let read_rsp = &evt.gattc_evt.params.read_rsp;
+            Event::GattcReadResponse {
+                connection_handle: evt.gattc_evt.conn_handle,
+                gatt_status: evt.gattc_evt.gatt_status,
+                handle: read_rsp.handle,
+                offset: read_rsp.offset,
+                data: value(&read_rsp.data, read_rsp.len),
+            }
+        }
+        ffi::BLE_GATTC_EVTS_BLE_GATTC_EVT_WRITE_RSP => {
+            let write_rsp = &evt.gattc_evt.params.write_rsp;
+            Event::GattcWriteResponse {
+                connection_handle: evt.gattc_evt.conn_handle,
+                gatt_status: evt.gattc_evt.gatt_status,
+                handle: write_rsp.handle,
+                write_op: write_rsp.write_op,
+                offset: write_rsp.offset,
+                data: value(&write_rsp.data, write_rsp.len),
+            }
+        }
+        ffi::BLE_GATTC_EVTS_BLE_GATTC_EVT_HVX => {
+            let hvx = &evt.gattc_evt.params.hvx;
+            Event::GattcHvx {
+                connection_handle: evt.gattc_evt.conn_handle,
+                handle: hvx.handle,
+                hvx_type: hvx.type_,
+                data: value(&hvx.data, hvx.len),
+            }
+        }
+        ffi::BLE_GATTS_EVTS_BLE_GATTS_EVT_WRITE => {
+            let write = &evt.gatts_evt.params.write;
+            Event::GattsWrite {
+                connection_handle: evt.gatts_evt.conn_handle,
+                handle: write.handle,
+                uuid: BleUuid {
+                    uuid: write.uuid.uuid,
+                    uuid_type: write.uuid.type_,
+                },
+                op: write.op,
+                offset: write.offset,
+                data: value(&write.data, write.len),
+            }
+        }
+        _ => return None,
+    };
+
+    Some(event)
+}
+
+/// The callbacks registered for `adapter`. The registry lock is released
+/// before they run, so adapters deliver their events concurrently.
+fn callbacks(adapter: *mut ffi::adapter_t) -> Option<Arc<Callbacks>> {
+    ADAPTERS.lock().unwrap().get(&(adapter as usize)).cloned()
+}
+
+extern "C" fn sd_rpc_status_handler(
+    adapter: *mut ffi::adapter_t,
+    code: ffi::sd_rpc_app_status_t,
+    message: *const c_char,
+) {
+    unsafe {
+        if let Some(callbacks) = callbacks(adapter) {
+            let message = message_to_string(message);
+            if let Some(capture) = &callbacks.capture {
+                let _result = capture.lock().unwrap().status(code, &message);
+            }
+            let status = RpcStatus::from(code);
+            // The restarted firmware holds on to none of the buffers.
+            if status == RpcStatus::ResetPerformed {
+                callbacks.buffers.release_all();
+            }
+            callbacks.events.status(status, message);
+        }
+    }
+}
+
+extern "C" fn sd_rpc_event_handler(adapter: *mut ffi::adapter_t, rpc_event: *mut ffi::ble_evt_t) {
+    unsafe {
+        if let Some(callbacks) = callbacks(adapter) {
+            if let Some(capture) = &callbacks.capture {
+                let _result = capture.lock().unwrap().event(rpc_event);
+            }
+            forward_event(&callbacks.events, rpc_event);
+            callbacks.buffers.on_event(rpc_event);
+        }
+    }
+}
+
+extern "C" fn sd_rpc_log_handler(
+    adapter: *mut ffi::adapter_t,
+    severity: ffi::sd_rpc_log_severity_t,
+    message: *const c_char,
+) {
+    unsafe {
+        if let Some(callbacks) = callbacks(adapter) {
+            let severity = LogSeverity::try_from_primitive(severity).unwrap_or(LogSeverity::Info);
+            callbacks.events.log(severity, message_to_string(message));
+        }
+    }
+}
+
+unsafe fn message_to_string(message: *const c_char) -> String {
+    if message.is_null() {
+        String::new()
+    } else {
+        CStr::from_ptr(message).to_string_lossy().into_owned()
+    }
+}
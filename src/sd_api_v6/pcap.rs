@@ -0,0 +1,588 @@
+//! Export of the BLE traffic seen by the driver as a pcap file for Wireshark.
+//!
+//! Packets are written with the link type `LINKTYPE_BLUETOOTH_LE_LL_WITH_PHDR`,
+//! a 10-byte pseudo header followed by the link layer packet as sent over
+//! the air. The SoftDevice does not hand out raw packets, so they are
+//! re-synthesised from what it reports:
+//!
+//! - advertising reports become the advertising PDU they were received in,
+//!   with the RSSI, channel and PHY of the report;
+//! - GATT events and commands become the ATT PDU exchanged with the peer,
+//!   carried in a data channel PDU on the L2CAP attribute channel.
+//!
+//! The access address and CRC init of data channel PDUs are made up, and the
+//! GATT client is assumed to be the central. The base of vendor specific
+//! UUIDs is not known to the driver, so they are written as 128-bit UUIDs
+//! with an all-zero base.
+
+use crate::{codec::{BleUuid, Command, Event, GattcService, Writer}, gap::{GapAdvertisementReport, GapAddressType, GapPhy}, Error, Result};
+use std::fs::File;
+use std::io::Write;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub const LINKTYPE_BLUETOOTH_LE_LL_WITH_PHDR: u32 = 256;
+
+const PCAP_MAGIC: u32 = 0xa1b2_c3d4;
+const SNAPLEN: u32 = 0xffff;
+
+const ADVERTISING_ACCESS_ADDRESS: u32 = 0x8e89_bed6;
+const ADVERTISING_CRC_INIT: u32 = 0x55_5555;
+/// Base of the access addresses made up for the connection handles.
+const DATA_ACCESS_ADDRESS_BASE: u32 = 0x5065_0000;
+
+const FLAG_DEWHITENED: u16 = 0x0001;
+const FLAG_SIGNAL_POWER_VALID: u16 = 0x0002;
+const FLAG_REFERENCE_ACCESS_ADDRESS_VALID: u16 = 0x0010;
+
+const PHDR_PDU_ADVERTISING: u16 = 0;
+const PHDR_PDU_AUX_ADVERTISING: u16 = 1;
+const PHDR_PDU_MASTER_TO_SLAVE: u16 = 2;
+const PHDR_PDU_SLAVE_TO_MASTER: u16 = 3;
+
+const ADV_IND: u8 = 0x0;
+const ADV_DIRECT_IND: u8 = 0x1;
+const ADV_NONCONN_IND: u8 = 0x2;
+const SCAN_RSP: u8 = 0x4;
+const ADV_SCAN_IND: u8 = 0x6;
+const ADV_EXT_IND: u8 = 0x7;
+
+const LLID_START: u8 = 0x2;
+const L2CAP_CID_ATT: u16 = 0x0004;
+/// Largest ATT PDU that fits a data channel PDU, longer ones are truncated.
+const MAX_ATT_LENGTH: usize = 251 - 4;
+
+const BLE_GATT_OP_WRITE_REQ: u8 = 1;
+const BLE_GATT_OP_WRITE_CMD: u8 = 2;
+const BLE_GATT_OP_SIGN_WRITE_CMD: u8 = 3;
+const BLE_GATT_OP_PREP_WRITE_REQ: u8 = 4;
+const BLE_GATT_OP_EXEC_WRITE_REQ: u8 = 5;
+const BLE_GATTS_OP_EXEC_WRITE_REQ_CANCEL: u8 = 5;
+const BLE_GATTS_OP_EXEC_WRITE_REQ_NOW: u8 = 6;
+const BLE_GATT_HVX_NOTIFICATION: u8 = 1;
+const BLE_GATT_HVX_INDICATION: u8 = 2;
+const BLE_GATT_EXEC_WRITE_FLAG_PREPARED_WRITE: u8 = 1;
+
+const GATT_PRIMARY_SERVICE_UUID: u16 = 0x2800;
+const BLE_UUID_TYPE_BLE: u8 = 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Direction {
+    ClientToServer,
+    ServerToClient,
+}
+
+#[derive(Debug)]
+struct AttPdu {
+    connection_handle: u16,
+    direction: Direction,
+    pdu: Vec<u8>,
+}
+
+/// Appends synthesised link layer packets to a pcap file.
+#[derive(Debug)]
+pub struct PcapWriter {
+    file: File,
+}
+
+impl PcapWriter {
+    pub fn create<P: AsRef<Path>>(path: P) -> Result<PcapWriter> {
+        let mut file = File::create(path).map_err(Error::Io)?;
+        let mut header = Writer::new();
+        header
+            .u32(PCAP_MAGIC)
+            .u16(2)
+            .u16(4)
+            .u32(0)
+            .u32(0)
+            .u32(SNAPLEN)
+            .u32(LINKTYPE_BLUETOOTH_LE_LL_WITH_PHDR);
+        file.write_all(&header.finish()).map_err(Error::Io)?;
+        Ok(PcapWriter { file })
+    }
+
+    /// Writes the advertising PDU `report` was received in.
+    pub fn advertising_report(&mut self, report: &GapAdvertisementReport) -> Result<()> {
+        let (pdu_type, pdu) = advertising_pdu(report);
+        let phy = match (report.report_type.extended_pdu, report.secondary_phy) {
+            (true, GapPhy::OneMbps | GapPhy::TwoMbps | GapPhy::Coded) => report.secondary_phy,
+            _ => report.primary_phy,
+        };
+        let flags = FLAG_SIGNAL_POWER_VALID | pdu_type << 7 | phy_flags(phy);
+        self.packet(
+            report.channel_index,
+            report.rssi,
+            flags,
+            ADVERTISING_ACCESS_ADDRESS,
+            &pdu,
+        )
+    }
+
+    /// Writes the ATT PDUs a GATT client or server event was caused by.
+    /// Other events are ignored.
+    pub fn event(&mut self, event: &Event) -> Result<()> {
+        for att in att_from_event(event) {
+            self.att(att)?;
+        }
+        Ok(())
+    }
+
+    /// Writes the ATT PDU a GATT client or server command sends.
+    /// Other commands are ignored.
+    pub fn command(&mut self, command: &Command) -> Result<()> {
+        match att_from_command(command) {
+            Some(att) => self.att(att),
+            None => Ok(()),
+        }
+    }
+
+    fn att(&mut self, att: AttPdu) -> Result<()> {
+        let length = att.pdu.len().min(MAX_ATT_LENGTH);
+        let mut pdu = Writer::new();
+        pdu.u8(LLID_START)
+            .u8(length as u8 + 4)
+            .u16(length as u16)
+            .u16(L2CAP_CID_ATT)
+            .bytes(&att.pdu[..length]);
+
+        let pdu_type = match att.direction {
+            Direction::ClientToServer => PHDR_PDU_MASTER_TO_SLAVE,
+            Direction::ServerToClient => PHDR_PDU_SLAVE_TO_MASTER,
+        };
+        // The connection's channel is not reported, use a fixed data channel.
+        self.packet(
+            0,
+            0,
+            pdu_type << 7,
+            DATA_ACCESS_ADDRESS_BASE | att.connection_handle as u32,
+            &pdu.finish(),
+        )
+    }
+
+    fn packet(
+        &mut self,
+        channel_index: u8,
+        rssi: i8,
+        flags: u16,
+        access_address: u32,
+        pdu: &[u8],
+    ) -> Result<()> {
+        let crc = crc24(ADVERTISING_CRC_INIT, pdu);
+        let mut packet = Writer::new();
+        packet
+            .u8(rf_channel(channel_index))
+            .u8(rssi as u8)
+            .u8(0)
+            .u8(0)
+            .u32(access_address)
+            .u16(FLAG_DEWHITENED | FLAG_REFERENCE_ACCESS_ADDRESS_VALID | flags)
+            .u32(access_address)
+            .bytes(pdu)
+            .bytes(&crc.to_le_bytes()[..3]);
+        let packet = packet.finish();
+
+        let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+        let mut record = Writer::new();
+        record
+            .u32(timestamp.as_secs() as u32)
+            .u32(timestamp.subsec_micros())
+            .u32(packet.len() as u32)
+            .u32(packet.len() as u32)
+            .bytes(&packet);
+        self.file.write_all(&record.finish()).map_err(Error::Io)
+    }
+}
+
+/// Maps a channel index onto the RF channel the pseudo header expects.
+fn rf_channel(channel_index: u8) -> u8 {
+    match channel_index {
+        37 => 0,
+        38 => 12,
+        39 => 39,
+        index if index < 11 => index + 1,
+        index => index + 2,
+    }
+}
+
+fn phy_flags(phy: GapPhy) -> u16 {
+    match phy {
+        GapPhy::TwoMbps => 1 << 14,
+        GapPhy::Coded => 2 << 14,
+        _ => 0,
+    }
+}
+
+/// The BLE CRC, computed over the PDU in air order. The result is written
+/// little-endian after the PDU.
+fn crc24(crc_init: u32, pdu: &[u8]) -> u32 {
+    // The register shifts towards its least significant bit here, so it
+    // starts with the initial value bit-reversed and uses the reversed
+    // polynomial.
+    let mut state = (crc_init.reverse_bits() >> 8) & 0xff_ffff;
+    for &byte in pdu {
+        let mut byte = byte;
+        for _ in 0..8 {
+            let feedback = (state ^ byte as u32) & 1;
+            byte >>= 1;
+            state >>= 1;
+            if feedback != 0 {
+                state |= 1 << 23;
+                state ^= 0x5a_6000;
+            }
+        }
+    }
+    state
+}
+
+fn is_random(address_type: GapAddressType) -> bool {
+    !matches!(address_type, GapAddressType::Public)
+}
+
+/// PHDR PDU type and the advertising PDU, including its header.
+fn advertising_pdu(report: &GapAdvertisementReport) -> (u16, Vec<u8>) {
+    let report_type = &report.report_type;
+    let tx_add = is_random(report.peer_address.address_type) as u8;
+    // RxAdd is only defined for PDUs carrying the target address.
+    let rx_add = (report_type.directed && is_random(report.direct_address.address_type)) as u8;
+
+    let mut payload = Writer::new();
+    let pdu_type;
+    let mut phdr_type = PHDR_PDU_ADVERTISING;
+    if report_type.extended_pdu {
+        pdu_type = ADV_EXT_IND;
+        if report.channel_index < 37 {
+            phdr_type = PHDR_PDU_AUX_ADVERTISING;
+        }
+        let adv_mode = report_type.connectable as u8 | (report_type.scannable as u8) << 1;
+        let (header_flags, header_length) = if report_type.directed {
+            (0x03, 1 + 6 + 6)
+        } else {
+            (0x01, 1 + 6)
+        };
+        payload
+            .u8(adv_mode << 6 | header_length)
+            .u8(header_flags)
+            .bytes(&report.peer_address.address);
+        if report_type.directed {
+            payload.bytes(&report.direct_address.address);
+        }
+        payload.bytes(&report.data);
+    } else {
+        pdu_type = match report_type {
+            t if t.scan_response => SCAN_RSP,
+            t if t.directed => ADV_DIRECT_IND,
+            t if t.connectable => ADV_IND,
+            t if t.scannable => ADV_SCAN_IND,
+            _ => ADV_NONCONN_IND,
+        };
+        payload.bytes(&report.peer_address.address);
+        if pdu_type == ADV_DIRECT_IND {
+            payload.bytes(&report.direct_address.address);
+        } else {
+            payload.bytes(&report.data);
+        }
+    }
+
+    let payload = payload.finish();
+    let length = payload.len().min(255);
+    let mut pdu = Writer::new();
+    pdu.u8(pdu_type | tx_add << 6 | rx_add << 7)
+        .u8(length as u8)
+        .bytes(&payload[..length]);
+    (phdr_type, pdu.finish())
+}
+
+fn att_from_event(event: &Event) -> Vec<AttPdu> {
+    let mut pdu = Writer::new();
+    let (connection_handle, direction) = match event {
+        Event::GattcPrimaryServicesDiscovered { connection_handle, services, .. } => {
+            return read_by_group_type_responses(*connection_handle, services);
+        }
+        Event::GattcReadResponse { connection_handle, offset, data, .. } => {
+            pdu.u8(if *offset == 0 { 0x0b } else { 0x0d }).bytes(data);
+            (*connection_handle, Direction::ServerToClient)
+        }
+        Event::GattcWriteResponse { connection_handle, handle, write_op, offset, data, .. } => {
+            match *write_op {
+                BLE_GATT_OP_WRITE_REQ => pdu.u8(0x13),
+                BLE_GATT_OP_PREP_WRITE_REQ => pdu.u8(0x17).u16(*handle).u16(*offset).bytes(data),
+                BLE_GATT_OP_EXEC_WRITE_REQ => pdu.u8(0x19),
+                _ => return Vec::new(),
+            };
+            (*connection_handle, Direction::ServerToClient)
+        }
+        Event::GattcHvx { connection_handle, handle, hvx_type, data } => {
+            let Some(opcode) = hvx_opcode(*hvx_type) else {
+                return Vec::new();
+            };
+            pdu.u8(opcode).u16(*handle).bytes(data);
+            (*connection_handle, Direction::ServerToClient)
+        }
+        Event::GattsWrite { connection_handle, handle, op, offset, data, .. } => {
+            match *op {
+                BLE_GATT_OP_WRITE_REQ => pdu.u8(0x12).u16(*handle).bytes(data),
+                BLE_GATT_OP_WRITE_CMD => pdu.u8(0x52).u16(*handle).bytes(data),
+                BLE_GATT_OP_SIGN_WRITE_CMD => pdu.u8(0xd2).u16(*handle).bytes(data),
+                BLE_GATT_OP_PREP_WRITE_REQ => pdu.u8(0x16).u16(*handle).u16(*offset).bytes(data),
+                BLE_GATTS_OP_EXEC_WRITE_REQ_CANCEL => pdu.u8(0x18).u8(0),
+                BLE_GATTS_OP_EXEC_WRITE_REQ_NOW => pdu.u8(0x18).u8(1),
+                _ => return Vec::new(),
+            };
+            (*connection_handle, Direction::ClientToServer)
+        }
+        _ => return Vec::new(),
+    };
+
+    vec![AttPdu {
+        connection_handle,
+        direction,
+        pdu: pdu.finish(),
+    }]
+}
+
+/// The Read By Group Type responses a server sends for `services`. All
+/// entries of a response have the same length, so every change of UUID
+/// size starts a new response.
+fn read_by_group_type_responses(connection_handle: u16, services: &[GattcService]) -> Vec<AttPdu> {
+    let entries: Vec<Vec<u8>> = services
+        .iter()
+        .map(|service| {
+            Writer::new()
+                .u16(service.start_handle)
+                .u16(service.end_handle)
+                .bytes(&uuid_bytes(service.uuid))
+                .finish()
+        })
+        .collect();
+
+    entries
+        .chunk_by(|a, b| a.len() == b.len())
+        .map(|run| {
+            let mut pdu = Writer::new();
+            pdu.u8(0x11).u8(run[0].len() as u8);
+            for entry in run {
+                pdu.bytes(entry);
+            }
+            AttPdu {
+                connection_handle,
+                direction: Direction::ServerToClient,
+                pdu: pdu.finish(),
+            }
+        })
+        .collect()
+}
+
+fn att_from_command(command: &Command) -> Option<AttPdu> {
+    let mut pdu = Writer::new();
+    let (connection_handle, direction) = match command {
+        Command::GattcPrimaryServicesDiscover { connection_handle, start_handle, uuid } => {
+            match uuid {
+                Some(uuid) => pdu
+                    .u8(0x06)
+                    .u16(*start_handle)
+                    .u16(0xffff)
+                    .u16(GATT_PRIMARY_SERVICE_UUID)
+                    .bytes(&uuid_bytes(*uuid)),
+                None => pdu
+                    .u8(0x10)
+                    .u16(*start_handle)
+                    .u16(0xffff)
+                    .u16(GATT_PRIMARY_SERVICE_UUID),
+            };
+            (*connection_handle, Direction::ClientToServer)
+        }
+        Command::GattcRead { connection_handle, handle, offset } => {
+            match *offset {
+                0 => pdu.u8(0x0a).u16(*handle),
+                offset => pdu.u8(0x0c).u16(*handle).u16(offset),
+            };
+            (*connection_handle, Direction::ClientToServer)
+        }
+        Command::GattcWrite { connection_handle, parameters } => {
+            let (handle, value) = (parameters.handle, &parameters.value);
+            match parameters.write_op {
+                BLE_GATT_OP_WRITE_REQ => pdu.u8(0x12).u16(handle).bytes(value),
+                BLE_GATT_OP_WRITE_CMD => pdu.u8(0x52).u16(handle).bytes(value),
+                BLE_GATT_OP_SIGN_WRITE_CMD => pdu.u8(0xd2).u16(handle).bytes(value),
+                BLE_GATT_OP_PREP_WRITE_REQ => {
+                    pdu.u8(0x16).u16(handle).u16(parameters.offset).bytes(value)
+                }
+                BLE_GATT_OP_EXEC_WRITE_REQ => pdu
+                    .u8(0x18)
+                    .u8(parameters.flags & BLE_GATT_EXEC_WRITE_FLAG_PREPARED_WRITE),
+                _ => return None,
+            };
+            (*connection_handle, Direction::ClientToServer)
+        }
+        Command::GattsHvx { connection_handle, handle, hvx_type, data, .. } => {
+            pdu.u8(hvx_opcode(*hvx_type)?).u16(*handle).bytes(data);
+            (*connection_handle, Direction::ServerToClient)
+        }
+        _ => return None,
+    };
+
+    Some(AttPdu {
+        connection_handle,
+        direction,
+        pdu: pdu.finish(),
+    })
+}
+
+/// A UUID as sent over the air, 16-bit if it is Bluetooth SIG assigned.
+fn uuid_bytes(uuid: BleUuid) -> Vec<u8> {
+    let mut bytes = Writer::new();
+    if uuid.uuid_type == BLE_UUID_TYPE_BLE {
+        bytes.u16(uuid.uuid);
+    } else {
+        bytes.bytes(&[0; 12]).u16(uuid.uuid).bytes(&[0; 2]);
+    }
+    bytes.finish()
+}
+
+fn hvx_opcode(hvx_type: u8) -> Option<u8> {
+    match hvx_type {
+        BLE_GATT_HVX_NOTIFICATION => Some(0x1b),
+        BLE_GATT_HVX_INDICATION => Some(0x1d),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codec::GattcWriteParameters;
+    use crate::simulated::SimulatedBackend;
+    use crate::test_support::{advertising_report, simulated_builder};
+    use bytes::Bytes;
+    use std::fs;
+
+    #[test]
+    fn advertising_report_and_att_records() {
+        let report = GapAdvertisementReport {
+            channel_index: 38,
+            ..advertising_report(b"\x02\x01\x06")
+        };
+        let path = std::env::temp_dir().join(format!("nrf-sd-api-{}.pcap", std::process::id()));
+
+        let mut pcap = PcapWriter::create(&path).unwrap();
+        pcap.advertising_report(&report).unwrap();
+        pcap.command(&Command::GattcWrite {
+            connection_handle: 0,
+            parameters: GattcWriteParameters {
+                write_op: BLE_GATT_OP_WRITE_REQ,
+                flags: 0,
+                handle: 0x0010,
+                offset: 0,
+                value: Bytes::from_static(&[0x01, 0x00]),
+            },
+        })
+        .unwrap();
+        pcap.command(&Command::GapScanStop).unwrap();
+        drop(pcap);
+        let contents = fs::read(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(contents[..4], [0xd4, 0xc3, 0xb2, 0xa1]);
+        assert_eq!(contents[20..24], 256u32.to_le_bytes());
+
+        let adv = &contents[24..];
+        let length = u32::from_le_bytes(adv[8..12].try_into().unwrap()) as usize;
+        // Pseudo header, access address, ADV_IND header, AdvA, AdvData and CRC.
+        assert_eq!(length, 10 + 4 + 2 + 6 + 3 + 3);
+        assert_eq!(adv[16], 12);
+        assert_eq!(adv[17], -60i8 as u8);
+        assert_eq!(adv[30..32], [ADV_IND | 0x40, 9]);
+
+        let att = &adv[16 + length..];
+        assert_eq!(att[24..26], (PHDR_PDU_MASTER_TO_SLAVE << 7 | 0x11).to_le_bytes());
+        assert_eq!(
+            att[30..att.len() - 3],
+            [LLID_START, 9, 5, 0, 4, 0, 0x12, 0x10, 0x00, 0x01, 0x00]
+        );
+        // Commands without an ATT PDU are not written.
+        assert_eq!(att.len(), 16 + 10 + 4 + 11 + 3);
+    }
+
+    #[test]
+    fn primary_services_with_128_bit_uuids() {
+        let service = |start_handle, uuid_type| GattcService {
+            uuid: BleUuid { uuid: 0x1523, uuid_type },
+            start_handle,
+            end_handle: start_handle + 4,
+        };
+        let event = |services| Event::GattcPrimaryServicesDiscovered {
+            connection_handle: 0,
+            gatt_status: 0,
+            services,
+        };
+
+        let att = att_from_event(&event(vec![service(0x10, BLE_UUID_TYPE_BLE)]));
+        assert_eq!(att.len(), 1);
+        assert_eq!(att[0].pdu, [0x11, 6, 0x10, 0x00, 0x14, 0x00, 0x23, 0x15]);
+
+        let att = att_from_event(&event(vec![service(0x10, 2), service(0x20, 2)]));
+        assert_eq!(att.len(), 1);
+        assert_eq!(att[0].pdu[..2], [0x11, 20]);
+        assert_eq!(att[0].pdu.len(), 2 + 2 * 20);
+        assert_eq!(att[0].pdu[22..26], [0x20, 0x00, 0x24, 0x00]);
+        assert_eq!(att[0].pdu[38..40], [0x23, 0x15]);
+
+        // Mixed UUID sizes are split into one response per size.
+        let att = att_from_event(&event(vec![
+            service(0x01, BLE_UUID_TYPE_BLE),
+            service(0x06, BLE_UUID_TYPE_BLE),
+            service(0x10, 2),
+        ]));
+        let pdus: Vec<_> = att.iter().map(|att| (att.pdu[1], att.pdu.len())).collect();
+        assert_eq!(pdus, [(6, 2 + 2 * 6), (20, 2 + 20)]);
+        assert_eq!(att[1].pdu[2..4], [0x10, 0x00]);
+    }
+
+    #[test]
+    fn driver_writes_serialized_commands() {
+        let path =
+            std::env::temp_dir().join(format!("nrf-sd-api-{}-driver.pcap", std::process::id()));
+        let (_backend, builder) = simulated_builder(SimulatedBackend::new());
+        let driver = builder.pcap(&path).build().unwrap();
+        let read = |handle| Command::GattcRead {
+            connection_handle: 0,
+            handle,
+            offset: 0,
+        };
+
+        let core = &driver.adapter.core;
+        core.serialized(&read(0x0010), || Ok(())).unwrap();
+        // Failed calls never reached the peer.
+        assert!(core.serialized(&read(0x0020), || Err::<(), _>(Error::DriverClosed)).is_err());
+        drop(driver);
+        let contents = fs::read(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        let att = &contents[24..];
+        assert_eq!(att.len(), 16 + 10 + 4 + 9 + 3);
+        assert_eq!(att[30..att.len() - 3], [LLID_START, 7, 3, 0, 4, 0, 0x0a, 0x10, 0x00]);
+    }
+
+    #[test]
+    fn crc_matches_air_order_reference() {
+        // Reference implementation shifting towards the most significant
+        // bit, with the bits of every byte fed least significant first.
+        fn reference(pdu: &[u8]) -> u32 {
+            let mut state = ADVERTISING_CRC_INIT;
+            for byte in pdu {
+                for bit in 0..8 {
+                    let feedback = ((state >> 23) ^ (*byte as u32 >> bit)) & 1;
+                    state = (state << 1) & 0xff_ffff;
+                    if feedback != 0 {
+                        state ^= 0x00_065b;
+                    }
+                }
+            }
+            // The most significant bit goes on air first.
+            state.reverse_bits() >> 8
+        }
+
+        let pdu = [0x40, 0x09, 0x01, 0x02, 0x03, 0x04, 0x05, 0xc6, 0x02, 0x01, 0x06];
+        assert_eq!(crc24(ADVERTISING_CRC_INIT, &pdu), reference(&pdu));
+    }
+}
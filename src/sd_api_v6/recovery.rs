@@ -0,0 +1,262 @@
+use crate::{codec::{Command, Config}, gap::GapScanParameters, rpc::{ResetMode, RpcStatus}, sd_api_v6::*, Error, Result};
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+/// Delay between attempts to reopen the transport after a link loss.
+const RECOVERY_RETRY_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Attempts at restoring the adapter before recovery gives up.
+const MAX_RECOVERY_ATTEMPTS: u32 = 5;
+
+/// Time the connectivity firmware is given to restart after a reset.
+const RESET_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Everything needed to bring the SoftDevice back to the state the
+/// application left it in.
+#[derive(Debug, Default)]
+pub(crate) struct RecoveryState {
+    /// Successful `sd_ble_cfg_set` calls, replayed after the connectivity
+    /// firmware has been reset.
+    configs: Vec<Config>,
+    enabled: bool,
+    scan_parameters: Option<GapScanParameters>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl DriverCore {
+    pub(crate) fn cfg_set(&self, config: Config) -> Result<()> {
+        self.backend_cfg_set(&config)?;
+        self.recovery.lock().unwrap().configs.push(config);
+        Ok(())
+    }
+
+    pub(crate) fn ble_enable(&self) -> Result<()> {
+        let command = Command::Enable { app_ram_base: Some(0) };
+        self.serialized(&command, || self.backend.ble_enable())?;
+        self.recovery.lock().unwrap().enabled = true;
+        Ok(())
+    }
+
+    fn backend_cfg_set(&self, config: &Config) -> Result<()> {
+        self.serialized(&Command::CfgSet(config.clone()), || self.backend.cfg_set(config))
+    }
+
+    pub(crate) fn record_scan_parameters(&self, scan_parameters: Option<&GapScanParameters>) {
+        self.recovery.lock().unwrap().scan_parameters = scan_parameters.cloned();
+    }
+
+    /// Starts reopening the transport in the background if `status` reports
+    /// a reset or a lost link and recovery is enabled.
+    pub(crate) fn check_link_loss(self: &Arc<Self>, status: RpcStatus) {
+        let link_lost = matches!(
+            status,
+            RpcStatus::ResetPerformed
+                | RpcStatus::IoResourcesUnavailable
+                | RpcStatus::PacketSendMaxRetriesReached
+        );
+        if !link_lost
+            || !self.auto_recover.load(Ordering::Relaxed)
+            || self.shutdown.load(Ordering::Acquire)
+        {
+            return;
+        }
+        if self.recovering.swap(true, Ordering::AcqRel) {
+            return;
+        }
+
+        // The backend must not be closed from its own callback thread.
+        let core = Arc::clone(self);
+        let handle = thread::spawn(move || {
+            let mut attempts = 0;
+            while !core.shutdown.load(Ordering::Acquire) {
+                attempts += 1;
+                match core.recover() {
+                    Ok(()) => {
+                        core.callback_event.send(EventType::Reconnected);
+                        break;
+                    }
+                    Err(error) if attempts >= MAX_RECOVERY_ATTEMPTS => {
+                        core.callback_event.send(EventType::RecoveryFailed(error.to_string()));
+                        break;
+                    }
+                    Err(_) => thread::sleep(RECOVERY_RETRY_INTERVAL),
+                }
+            }
+            core.recovering.store(false, Ordering::Release);
+        });
+
+        let previous = self.recovery.lock().unwrap().thread.replace(handle);
+        if let Some(previous) = previous {
+            let _result = previous.join();
+        }
+    }
+
+    pub(crate) fn reset(&self, mode: ResetMode) -> Result<()> {
+        let result = self
+            .reset_firmware(mode)
+            .and_then(|()| self.identify_firmware());
+        // Whatever the outcome, the SoftDevice may have lost its state.
+        let mut state = self.recovery.lock().unwrap();
+        state.configs.clear();
+        state.enabled = false;
+        state.scan_parameters = None;
+        result
+    }
+
+    /// Resets the connectivity chip and waits for the firmware to restart.
+    fn reset_firmware(&self, mode: ResetMode) -> Result<()> {
+        let resets_performed = *self.resets_performed.lock().unwrap();
+        self.resetting.store(true, Ordering::Release);
+
+        let result = self
+            .command("sd_rpc_conn_reset", None, &mode, || self.backend.conn_reset(mode))
+            .and_then(|()| {
+                let resets = self.resets_performed.lock().unwrap();
+                let (_resets, timeout) = self
+                    .reset_performed
+                    .wait_timeout_while(resets, RESET_TIMEOUT, |resets| {
+                        *resets == resets_performed
+                    })
+                    .unwrap();
+                if timeout.timed_out() {
+                    Err(Error::LinkTimeout)
+                } else {
+                    Ok(())
+                }
+            });
+
+        self.resetting.store(false, Ordering::Release);
+        self.is_scanning.store(false, Ordering::Release);
+        result
+    }
+
+    /// Stops any recovery in progress and waits for it to finish.
+    pub(crate) fn stop_recovery(&self) {
+        self.shutdown.store(true, Ordering::Release);
+        let handle = self.recovery.lock().unwrap().thread.take();
+        if let Some(handle) = handle {
+            let _result = handle.join();
+        }
+    }
+
+    fn recover(&self) -> Result<()> {
+        let _result = self.rpc_close();
+        self.is_scanning.store(false, Ordering::Release);
+        self.rpc_open()?;
+        // A lost link does not reset the SoftDevice, which may still be
+        // enabled and reject the configuration. Replay on a clean slate.
+        self.reset_firmware(ResetMode::SoftReset)?;
+        self.identify_firmware()?;
+
+        let (configs, enabled, scan_parameters) = {
+            let state = self.recovery.lock().unwrap();
+            (state.configs.clone(), state.enabled, state.scan_parameters.clone())
+        };
+
+        for config in &configs {
+            self.backend_cfg_set(config)?;
+        }
+
+        if enabled {
+            self.ble_enable()?;
+            if let Some(scan_parameters) = scan_parameters {
+                self.gap_scan_start(&scan_parameters)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blocking::BlockingBleDriver;
+    use crate::lifecycle::Configuring;
+    use crate::simulated::SimulatedBackend;
+    use crate::test_support::{simulated_builder, simulated_driver};
+
+    fn configuring_driver() -> (Arc<SimulatedBackend>, BleDriver<Configuring>) {
+        let (backend, driver) = simulated_driver(SimulatedBackend::new());
+        (backend, driver.open_adapter().unwrap())
+    }
+
+    fn assert_forgotten(driver: &BleDriver<Configuring>) {
+        let recovery = driver.adapter.core.recovery.lock().unwrap();
+        assert!(recovery.configs.is_empty());
+        assert!(!recovery.enabled);
+        assert!(recovery.scan_parameters.is_none());
+        assert!(!driver.adapter.core.is_scanning.load(Ordering::Acquire));
+    }
+
+    #[test]
+    fn reset_while_configuring() {
+        let (backend, mut driver) = configuring_driver();
+        driver.gap_set_connection_config(1, 1, 6).unwrap();
+        assert_eq!(backend.configs().len(), 1);
+
+        let mut driver = driver.reset(ResetMode::SoftReset).unwrap();
+        assert!(backend.configs().is_empty());
+        assert_forgotten(&driver);
+
+        driver.gap_set_connection_config(1, 1, 6).unwrap();
+        driver.ble_enable().unwrap();
+        assert!(backend.is_enabled());
+    }
+
+    #[test]
+    fn reset_while_enabled() {
+        let (backend, mut driver) = configuring_driver();
+        driver.gap_set_connection_config(1, 1, 6).unwrap();
+        let mut driver = driver.ble_enable().unwrap();
+        driver.gap_scan_start(&GapScanParameters::default()).unwrap();
+
+        let mut driver = driver.reset(ResetMode::SoftReset).unwrap();
+        assert!(!backend.is_enabled());
+        assert!(!backend.is_scanning());
+        assert_forgotten(&driver);
+
+        // The SoftDevice accepts a new configuration, and scanning starts
+        // afresh instead of resuming.
+        driver.gap_set_connection_config(1, 1, 6).unwrap();
+        let mut driver = driver.ble_enable().unwrap();
+        driver.gap_scan_start(&GapScanParameters::default()).unwrap();
+        assert!(backend.is_scanning());
+        assert_eq!(driver.stats().commands["sd_rpc_conn_reset"].calls, 1);
+    }
+
+    #[test]
+    fn recovery_replays_configuration_and_resumes_scanning() {
+        let (backend, builder) = simulated_builder(SimulatedBackend::new());
+        let driver = builder.auto_recover(true).build().unwrap();
+
+        let mut adapter = BlockingBleDriver::open(driver).unwrap();
+        adapter.gap_set_connection_config(1, 1, 6).unwrap();
+        adapter.ble_enable().unwrap();
+        let scan_parameters = GapScanParameters {
+            active: 1,
+            ..GapScanParameters::default()
+        };
+        adapter.gap_scan_start(&scan_parameters).unwrap();
+
+        backend.emit_status(RpcStatus::IoResourcesUnavailable, "Serial port lost");
+        loop {
+            match adapter.recv_event_timeout(Duration::from_secs(2)).unwrap() {
+                Some(EventType::Reconnected) => break,
+                Some(EventType::RecoveryFailed(error)) => panic!("recovery failed: {}", error),
+                Some(_) => {}
+                None => panic!("the adapter was not recovered"),
+            }
+        }
+
+        // The firmware was reset before the configuration was replayed.
+        let stats = adapter.handle().stats();
+        assert_eq!(stats.commands["sd_rpc_conn_reset"].calls, 1);
+        assert_eq!(backend.configs().len(), 1);
+        assert!(backend.is_enabled());
+        assert!(backend.is_scanning());
+        assert_eq!(stats.commands["sd_ble_gap_scan_start"].calls, 2);
+    }
+}
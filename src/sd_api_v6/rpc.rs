@@ -0,0 +1,113 @@
+#[cfg(feature = "pc-ble-driver")]
+use nrf_ble_driver_sys::ffi;
+use num_enum::{FromPrimitive, TryFromPrimitive};
+
+/// Status codes reported by the pc-ble-driver transport through the
+/// status callback.
+#[derive(Debug, FromPrimitive, Clone, Copy, PartialEq, Eq)]
+#[repr(u32)]
+pub enum RpcStatus {
+    /// A packet was retransmitted the maximum number of times without an acknowledgement.
+    PacketSendMaxRetriesReached = 0,
+    /// A packet was received that did not match any outstanding request.
+    PacketUnexpected = 1,
+    PacketEncodeError = 2,
+    PacketDecodeError = 3,
+    PacketSendError = 4,
+    /// The serial port could not be opened or was lost.
+    IoResourcesUnavailable = 5,
+    /// The connectivity firmware has been reset.
+    ResetPerformed = 6,
+    /// The link to the connectivity firmware is established.
+    ConnectionActive = 7,
+    /// A status code this version of the crate does not know.
+    #[num_enum(catch_all)]
+    Unknown(u32),
+}
+
+impl RpcStatus {
+    /// Returns true if the status reports a failure rather than a state change.
+    pub fn is_error(&self) -> bool {
+        !matches!(self, RpcStatus::ResetPerformed | RpcStatus::ConnectionActive)
+    }
+}
+
+/// Severity of a log message emitted by pc-ble-driver.
+#[derive(Debug, TryFromPrimitive, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[repr(u32)]
+pub enum LogSeverity {
+    Trace = 0,
+    Debug = 1,
+    Info = 2,
+    Warning = 3,
+    Error = 4,
+    #[num_enum(default)]
+    Fatal = 5,
+}
+
+impl LogSeverity {
+    #[cfg(feature = "pc-ble-driver")]
+    pub(crate) fn to_ffi(self) -> ffi::sd_rpc_log_severity_t {
+        self as ffi::sd_rpc_log_severity_t
+    }
+
+    /// Forwards a driver log message to the `log` and/or `tracing` crates,
+    /// depending on which cargo features are enabled.
+    #[allow(unused_variables)]
+    pub(crate) fn forward(self, message: &str) {
+        #[cfg(feature = "log")]
+        {
+            let level = match self {
+                LogSeverity::Trace => log::Level::Trace,
+                LogSeverity::Debug => log::Level::Debug,
+                LogSeverity::Info => log::Level::Info,
+                LogSeverity::Warning => log::Level::Warn,
+                LogSeverity::Error | LogSeverity::Fatal => log::Level::Error,
+            };
+            log::log!(target: "nrf_sd_api::rpc", level, "{}", message);
+        }
+
+        #[cfg(feature = "tracing")]
+        match self {
+            LogSeverity::Trace => tracing::trace!(target: "nrf_sd_api::rpc", "{}", message),
+            LogSeverity::Debug => tracing::debug!(target: "nrf_sd_api::rpc", "{}", message),
+            LogSeverity::Info => tracing::info!(target: "nrf_sd_api::rpc", "{}", message),
+            LogSeverity::Warning => tracing::warn!(target: "nrf_sd_api::rpc", "{}", message),
+            LogSeverity::Error | LogSeverity::Fatal => {
+                tracing::error!(target: "nrf_sd_api::rpc", "{}", message)
+            }
+        }
+    }
+}
+
+/// How `BleDriver::reset` resets the connectivity chip.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u32)]
+pub enum ResetMode {
+    /// Resets the whole chip, as after a power cycle.
+    SystemReset = 0,
+    /// Restarts the connectivity application without resetting the chip.
+    SoftReset = 1,
+}
+
+#[cfg(feature = "pc-ble-driver")]
+impl ResetMode {
+    pub(crate) fn to_ffi(self) -> ffi::sd_rpc_reset_t {
+        self as ffi::sd_rpc_reset_t
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unknown_status_keeps_its_code() {
+        assert_eq!(
+            RpcStatus::from(6),
+            RpcStatus::ResetPerformed
+        );
+        assert_eq!(RpcStatus::from(0x1234), RpcStatus::Unknown(0x1234));
+        assert!(RpcStatus::Unknown(0x1234).is_error());
+    }
+}
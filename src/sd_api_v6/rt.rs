@@ -0,0 +1,324 @@
+//! Async primitives that work with any executor.
+//!
+//! Replies from the driver thread travel over the `oneshot` channel below,
+//! which only needs `std`. Timers are the one thing an executor has to
+//! provide: with `rt-tokio` or `rt-async-std` they use that runtime's
+//! timer, otherwise (`rt-none`) all pending timeouts are served by one
+//! shared timer thread.
+
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Condvar, Mutex};
+use std::task::{Context, Poll, Waker};
+use std::time::Duration;
+
+#[derive(Debug)]
+struct Slot<T> {
+    value: Option<T>,
+    /// Set once the sender has sent its value or has been dropped.
+    done: bool,
+    waker: Option<Waker>,
+}
+
+#[derive(Debug)]
+struct Shared<T> {
+    slot: Mutex<Slot<T>>,
+    ready: Condvar,
+}
+
+/// Sends a single value to a `Receiver`.
+#[derive(Debug)]
+pub(crate) struct Sender<T> {
+    shared: Arc<Shared<T>>,
+}
+
+/// Receives the value of a `Sender`, either awaited or blocking. Yields
+/// `None` if the sender was dropped without sending.
+#[derive(Debug)]
+pub(crate) struct Receiver<T> {
+    shared: Arc<Shared<T>>,
+}
+
+pub(crate) fn oneshot<T>() -> (Sender<T>, Receiver<T>) {
+    let shared = Arc::new(Shared {
+        slot: Mutex::new(Slot {
+            value: None,
+            done: false,
+            waker: None,
+        }),
+        ready: Condvar::new(),
+    });
+
+    (Sender { shared: Arc::clone(&shared) }, Receiver { shared })
+}
+
+impl<T> Sender<T> {
+    pub(crate) fn send(self, value: T) {
+        self.shared.slot.lock().unwrap().value = Some(value);
+        // Dropping `self` wakes the receiver.
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        let waker = {
+            let mut slot = self.shared.slot.lock().unwrap();
+            slot.done = true;
+            slot.waker.take()
+        };
+        self.shared.ready.notify_all();
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    }
+}
+
+impl<T> Receiver<T> {
+    /// Blocks the calling thread until the value has been sent.
+    pub(crate) fn blocking_recv(self) -> Option<T> {
+        let slot = self.shared.slot.lock().unwrap();
+        let mut slot = self.shared.ready.wait_while(slot, |slot| !slot.done).unwrap();
+        slot.value.take()
+    }
+}
+
+impl<T> Future for Receiver<T> {
+    type Output = Option<T>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<T>> {
+        let mut slot = self.shared.slot.lock().unwrap();
+        if slot.done {
+            Poll::Ready(slot.value.take())
+        } else {
+            slot.waker = Some(cx.waker().clone());
+            Poll::Pending
+        }
+    }
+}
+
+/// Runs `future` for at most `duration`. Returns `None` if it timed out.
+#[cfg(feature = "rt-tokio")]
+pub(crate) async fn timeout<F: Future>(duration: Duration, future: F) -> Option<F::Output> {
+    tokio::time::timeout(duration, future).await.ok()
+}
+
+/// Runs `future` for at most `duration`. Returns `None` if it timed out.
+#[cfg(all(feature = "rt-async-std", not(feature = "rt-tokio")))]
+pub(crate) async fn timeout<F: Future>(duration: Duration, future: F) -> Option<F::Output> {
+    async_std::future::timeout(duration, future).await.ok()
+}
+
+/// Runs `future` for at most `duration`. Returns `None` if it timed out.
+#[cfg(not(any(feature = "rt-tokio", feature = "rt-async-std")))]
+pub(crate) async fn timeout<F: Future>(duration: Duration, future: F) -> Option<F::Output> {
+    let mut delay = timer::Delay::new(duration);
+    let mut future = std::pin::pin!(future);
+    std::future::poll_fn(|cx| {
+        if let Poll::Ready(output) = future.as_mut().poll(cx) {
+            return Poll::Ready(Some(output));
+        }
+        match Pin::new(&mut delay).poll(cx) {
+            Poll::Ready(()) => Poll::Ready(None),
+            Poll::Pending => Poll::Pending,
+        }
+    })
+    .await
+}
+
+/// Timer thread shared by every pending `timeout` without a runtime.
+#[cfg(not(any(feature = "rt-tokio", feature = "rt-async-std")))]
+mod timer {
+    use std::cmp::Reverse;
+    use std::collections::{BinaryHeap, HashMap};
+    use std::future::Future;
+    use std::pin::Pin;
+    use std::sync::{Condvar, Mutex, OnceLock};
+    use std::task::{Context, Poll, Waker};
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    #[derive(Debug, Default)]
+    struct Timers {
+        /// Deadlines of the registered delays, earliest first. Entries of
+        /// dropped delays stay until they are due.
+        deadlines: BinaryHeap<Reverse<(Instant, u64)>>,
+        /// Wakers of the delays that are still pending.
+        wakers: HashMap<u64, Waker>,
+        next_id: u64,
+    }
+
+    #[derive(Debug, Default)]
+    pub(super) struct Timer {
+        timers: Mutex<Timers>,
+        changed: Condvar,
+    }
+
+    impl Timer {
+        /// The timer, starting its thread on first use.
+        pub(super) fn get() -> &'static Timer {
+            static TIMER: OnceLock<Timer> = OnceLock::new();
+            TIMER.get_or_init(|| {
+                thread::spawn(|| Timer::get().run());
+                Timer::default()
+            })
+        }
+
+        fn run(&self) {
+            let mut timers = self.timers.lock().unwrap();
+            loop {
+                let now = Instant::now();
+                while let Some(&Reverse((deadline, id))) = timers.deadlines.peek() {
+                    if deadline > now {
+                        break;
+                    }
+                    timers.deadlines.pop();
+                    if let Some(waker) = timers.wakers.remove(&id) {
+                        waker.wake();
+                    }
+                }
+
+                timers = match timers.deadlines.peek() {
+                    Some(&Reverse((deadline, _))) => {
+                        let wait = deadline.saturating_duration_since(now);
+                        self.changed.wait_timeout(timers, wait).unwrap().0
+                    }
+                    None => self.changed.wait(timers).unwrap(),
+                };
+            }
+        }
+
+        fn register(&self, deadline: Instant, waker: &Waker) -> u64 {
+            let mut timers = self.timers.lock().unwrap();
+            let id = timers.next_id;
+            timers.next_id += 1;
+            let earliest = timers
+                .deadlines
+                .peek()
+                .is_none_or(|Reverse((earliest, _))| deadline < *earliest);
+            timers.deadlines.push(Reverse((deadline, id)));
+            timers.wakers.insert(id, waker.clone());
+            if earliest {
+                self.changed.notify_one();
+            }
+            id
+        }
+
+        fn update(&self, id: u64, waker: &Waker) {
+            if let Some(registered) = self.timers.lock().unwrap().wakers.get_mut(&id) {
+                registered.clone_from(waker);
+            }
+        }
+
+        fn cancel(&self, id: u64) {
+            self.timers.lock().unwrap().wakers.remove(&id);
+        }
+
+        #[cfg(test)]
+        pub(super) fn is_pending(&self, id: u64) -> bool {
+            self.timers.lock().unwrap().wakers.contains_key(&id)
+        }
+    }
+
+    /// Completes once `duration` has passed. Dropping it cancels the wakeup.
+    #[derive(Debug)]
+    pub(super) struct Delay {
+        deadline: Instant,
+        id: Option<u64>,
+    }
+
+    impl Delay {
+        pub(super) fn new(duration: Duration) -> Delay {
+            Delay {
+                deadline: Instant::now() + duration,
+                id: None,
+            }
+        }
+
+        #[cfg(test)]
+        pub(super) fn id(&self) -> Option<u64> {
+            self.id
+        }
+    }
+
+    impl Future for Delay {
+        type Output = ();
+
+        fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+            if Instant::now() >= self.deadline {
+                if let Some(id) = self.id.take() {
+                    Timer::get().cancel(id);
+                }
+                return Poll::Ready(());
+            }
+            match self.id {
+                Some(id) => Timer::get().update(id, cx.waker()),
+                None => self.id = Some(Timer::get().register(self.deadline, cx.waker())),
+            }
+            Poll::Pending
+        }
+    }
+
+    impl Drop for Delay {
+        fn drop(&mut self) {
+            if let Some(id) = self.id {
+                Timer::get().cancel(id);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::future;
+    use std::thread;
+
+    #[test]
+    fn oneshot_delivers_or_reports_dropped_sender() {
+        let (sender, receiver) = oneshot();
+        thread::spawn(move || sender.send(7));
+        assert_eq!(receiver.blocking_recv(), Some(7));
+
+        let (sender, receiver) = oneshot::<u32>();
+        drop(sender);
+        assert_eq!(receiver.blocking_recv(), None);
+    }
+
+    #[tokio::test]
+    async fn timeout_expires() {
+        assert_eq!(timeout(Duration::from_millis(10), future::pending::<()>()).await, None);
+        assert_eq!(timeout(Duration::from_secs(1), async { 7 }).await, Some(7));
+    }
+
+    #[cfg(not(any(feature = "rt-tokio", feature = "rt-async-std")))]
+    #[test]
+    fn delays_share_the_timer_thread() {
+        use std::time::Instant;
+
+        // A later registered but earlier deadline wakes the timer thread.
+        let waiters: Vec<_> = [60, 20, 40]
+            .into_iter()
+            .map(|millis| {
+                thread::spawn(move || {
+                    let runtime = tokio::runtime::Builder::new_current_thread().build().unwrap();
+                    let started = Instant::now();
+                    let duration = Duration::from_millis(millis);
+                    assert_eq!(runtime.block_on(timeout(duration, future::pending::<()>())), None);
+                    started.elapsed() - duration
+                })
+            })
+            .collect();
+        for waiter in waiters {
+            assert!(waiter.join().unwrap() < Duration::from_millis(500));
+        }
+
+        // Dropping a pending delay cancels its wakeup.
+        let mut delay = timer::Delay::new(Duration::from_secs(60));
+        let mut cx = Context::from_waker(std::task::Waker::noop());
+        assert!(Pin::new(&mut delay).poll(&mut cx).is_pending());
+        let id = delay.id().unwrap();
+        assert!(timer::Timer::get().is_pending(id));
+        drop(delay);
+        assert!(!timer::Timer::get().is_pending(id));
+    }
+}
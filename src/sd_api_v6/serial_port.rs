@@ -0,0 +1,171 @@
+//! Serial port enumeration. Listing ports uses pc-ble-driver and needs the
+//! `pc-ble-driver` feature.
+
+#[cfg(feature = "pc-ble-driver")]
+use crate::{handle::{BleDriverHandle, EventStream}, sd_api_v6::BleDriver, Error, Result};
+#[cfg(feature = "pc-ble-driver")]
+use nrf_ble_driver_sys::ffi;
+#[cfg(feature = "pc-ble-driver")]
+use std::ffi::CStr;
+#[cfg(feature = "pc-ble-driver")]
+use std::mem;
+#[cfg(feature = "pc-ble-driver")]
+use std::os::raw::c_char;
+
+/// USB vendor ID of Segger J-Link debug probes, including the on-board
+/// probes of the nRF development kits.
+pub const SEGGER_VENDOR_ID: u16 = 0x1366;
+/// USB vendor ID of Nordic Semiconductor.
+pub const NORDIC_VENDOR_ID: u16 = 0x1915;
+/// USB product ID of an nRF52840 dongle running connectivity firmware.
+pub const NRF52840_DONGLE_PRODUCT_ID: u16 = 0xc00a;
+
+/// Maximum number of serial ports returned by a single enumeration.
+#[cfg(feature = "pc-ble-driver")]
+const MAX_SERIAL_PORTS: usize = 32;
+
+/// Description of a serial port found by `BleDriver::list_ports`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SerialPortInfo {
+    /// Name of the port to pass to `BleDriver::new`, e.g. `/dev/ttyACM0` or `COM3`.
+    pub port: String,
+    pub manufacturer: String,
+    pub serial_number: String,
+    pub pnp_id: String,
+    pub location_id: String,
+    pub vendor_id: Option<u16>,
+    pub product_id: Option<u16>,
+}
+
+impl SerialPortInfo {
+    /// Returns true if the port belongs to a Segger J-Link or an nRF52840 dongle.
+    pub fn is_nordic_device(&self) -> bool {
+        matches!(
+            (self.vendor_id, self.product_id),
+            (Some(SEGGER_VENDOR_ID), _) | (Some(NORDIC_VENDOR_ID), Some(NRF52840_DONGLE_PRODUCT_ID))
+        )
+    }
+
+    #[cfg(feature = "pc-ble-driver")]
+    fn from_ffi(desc: &ffi::sd_rpc_serial_port_desc_t) -> SerialPortInfo {
+        SerialPortInfo {
+            port: c_array_to_string(&desc.port),
+            manufacturer: c_array_to_string(&desc.manufacturer),
+            serial_number: c_array_to_string(&desc.serialNumber),
+            pnp_id: c_array_to_string(&desc.pnpId),
+            location_id: c_array_to_string(&desc.locationId),
+            vendor_id: parse_usb_id(&c_array_to_string(&desc.vendorId)),
+            product_id: parse_usb_id(&c_array_to_string(&desc.productId)),
+        }
+    }
+}
+
+#[cfg(feature = "pc-ble-driver")]
+impl BleDriver {
+    /// Lists the serial ports present on the system.
+    pub fn list_ports() -> Result<Vec<SerialPortInfo>> {
+        let mut descs: Vec<ffi::sd_rpc_serial_port_desc_t> =
+            vec![unsafe { mem::zeroed() }; MAX_SERIAL_PORTS];
+        let mut size = MAX_SERIAL_PORTS as u32;
+
+        unsafe {
+            let error_code = ffi::sd_rpc_serial_port_enum(descs.as_mut_ptr(), &mut size);
+            if error_code != ffi::NRF_SUCCESS {
+                return Err(Error::nrf("sd_rpc_serial_port_enum", error_code));
+            }
+        }
+        descs.truncate(size as usize);
+
+        Ok(descs.iter().map(SerialPortInfo::from_ffi).collect())
+    }
+
+    /// Creates and opens a driver on the first Segger J-Link or nRF52840
+    /// dongle found by `list_ports`.
+    pub fn open_first_nordic() -> Result<(BleDriverHandle, EventStream)> {
+        let port = BleDriver::list_ports()?
+            .into_iter()
+            .find(SerialPortInfo::is_nordic_device)
+            .ok_or(Error::NoAdapterFound)?;
+
+        BleDriver::new(&port.port)?.open()
+    }
+}
+
+#[cfg(feature = "pc-ble-driver")]
+fn c_array_to_string(chars: &[c_char]) -> String {
+    if !chars.contains(&0) {
+        return String::new();
+    }
+    unsafe {
+        CStr::from_ptr(chars.as_ptr())
+            .to_string_lossy()
+            .into_owned()
+    }
+}
+
+/// Parses a vendor or product ID as reported by the enumeration, four hex
+/// digits with an optional `0x` prefix.
+#[cfg(feature = "pc-ble-driver")]
+fn parse_usb_id(id: &str) -> Option<u16> {
+    let id = id.trim();
+    let id = id.strip_prefix("0x").or_else(|| id.strip_prefix("0X")).unwrap_or(id);
+    // `from_str_radix` would accept a sign as well.
+    if !id.bytes().all(|byte| byte.is_ascii_hexdigit()) {
+        return None;
+    }
+    u16::from_str_radix(id, 16).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(feature = "pc-ble-driver")]
+    #[test]
+    fn parse_usb_ids() {
+        let cases = [
+            ("1366", Some(0x1366)),
+            ("0x1915", Some(0x1915)),
+            ("0XC00A", Some(0xc00a)),
+            ("c00a", Some(0xc00a)),
+            ("0xc00a", Some(0xc00a)),
+            (" 1915\n", Some(0x1915)),
+            ("", None),
+            ("0x", None),
+            ("+1366", None),
+            ("-1", None),
+            ("13 66", None),
+            ("c00g", None),
+            ("10000", None),
+        ];
+        for (id, expected) in cases {
+            assert_eq!(parse_usb_id(id), expected, "{:?}", id);
+        }
+    }
+
+    #[test]
+    fn nordic_devices() {
+        let port = |vendor_id, product_id| SerialPortInfo {
+            port: String::from("/dev/ttyACM0"),
+            manufacturer: String::new(),
+            serial_number: String::new(),
+            pnp_id: String::new(),
+            location_id: String::new(),
+            vendor_id,
+            product_id,
+        };
+        let cases = [
+            (Some(SEGGER_VENDOR_ID), Some(0x1015), true),
+            (Some(SEGGER_VENDOR_ID), None, true),
+            (Some(NORDIC_VENDOR_ID), Some(NRF52840_DONGLE_PRODUCT_ID), true),
+            // The dongle's bootloader and other Nordic products.
+            (Some(NORDIC_VENDOR_ID), Some(0x521f), false),
+            (Some(NORDIC_VENDOR_ID), None, false),
+            (Some(0x0403), Some(NRF52840_DONGLE_PRODUCT_ID), false),
+            (None, None, false),
+        ];
+        for (vendor_id, product_id, expected) in cases {
+            assert_eq!(port(vendor_id, product_id).is_nordic_device(), expected, "{:?}:{:?}", vendor_id, product_id);
+        }
+    }
+}
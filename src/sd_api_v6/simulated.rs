@@ -0,0 +1,361 @@
+//! In-memory SoftDevice for testing applications without a dongle.
+//!
+//! ```
+//! use bytes::Bytes;
+//! use nrf_sd_api::{blocking::BlockingBleDriver, builder::BleDriverBuilder, simulated::SimulatedBackend};
+//! use nrf_sd_api::gap::{GapAddress, GapAddressType, GapAdvertisementReport, GapAdvertisementReportType, GapPhy, GapScanParameters, GapSetId, TxPowerLevel};
+//! use std::sync::Arc;
+//!
+//! # fn main() -> nrf_sd_api::Result<()> {
+//! let address = GapAddress {
+//!     address_id_peer: false,
+//!     address_type: GapAddressType::RandomStatic,
+//!     address: [0x01, 0x02, 0x03, 0x04, 0x05, 0xc6],
+//! };
+//! let report = GapAdvertisementReport {
+//!     report_type: GapAdvertisementReportType { connectable: true, ..GapAdvertisementReportType::default() },
+//!     peer_address: address,
+//!     direct_address: address,
+//!     primary_phy: GapPhy::OneMbps,
+//!     secondary_phy: GapPhy::NotConfigured,
+//!     tx_power: TxPowerLevel::Invalid,
+//!     rssi: -60,
+//!     channel_index: 37,
+//!     set_id: GapSetId::NotAvailable,
+//!     // A complete local name.
+//!     data: Bytes::from_static(b"\x05\x09nRF5"),
+//! };
+//!
+//! let backend = Arc::new(SimulatedBackend::new().advertising_report(report));
+//! let driver = BleDriverBuilder::new("simulated").backend(backend).build()?;
+//! let mut driver = BlockingBleDriver::open(driver)?;
+//! driver.ble_enable()?;
+//! driver.gap_scan_start(&GapScanParameters::default())?;
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::{backend::{BleBackend, EventSink}, ble::NORDIC_COMPANY_ID, codec::{Config, Event}, gap::{GapAdvertisementReport, GapScanParameters}, rpc::{LogSeverity, ResetMode, RpcStatus}, Error, Result};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex, Weak};
+use std::thread;
+use std::time::Duration;
+
+const NRF_ERROR_INVALID_STATE: u32 = 8;
+const BLE_ERROR_NOT_ENABLED: u32 = 0x3001;
+const BLE_CONN_HANDLE_INVALID: u16 = 0xffff;
+const BLE_GAP_TIMEOUT_SRC_SCAN: u8 = 1;
+/// Time the simulated firmware takes to restart after a reset.
+const RESET_DURATION: Duration = Duration::from_millis(10);
+
+#[derive(Debug)]
+enum Delivery {
+    Event(Event),
+    AdvertisingReport(GapAdvertisementReport),
+    Status(RpcStatus, String),
+}
+
+#[derive(Debug)]
+struct SimulatedState {
+    version: (u8, u16, u16),
+    advertising_reports: Vec<GapAdvertisementReport>,
+    advertising_interval: Duration,
+    script: Vec<(Duration, Event)>,
+    deliveries: Option<Sender<(Duration, u64, Delivery)>>,
+    /// Incremented on every open so that nothing queued before a close is
+    /// delivered afterwards.
+    generation: u64,
+    enabled: bool,
+    scanning: bool,
+    scan_paused: bool,
+    next_report: usize,
+    configs: Vec<Config>,
+}
+
+/// A `BleBackend` that simulates a connectivity dongle running SoftDevice
+/// API v6.
+///
+/// While scanning it reports the configured advertising reports in turn.
+/// Like the real SoftDevice, it pauses after every report until scanning
+/// is resumed. Connection
+/// events and GATT responses are scripted with `event_after` or injected
+/// at any time with `emit`. Events are delivered in order on a thread of
+/// the backend's own.
+#[derive(Debug)]
+pub struct SimulatedBackend {
+    state: Arc<Mutex<SimulatedState>>,
+}
+
+impl SimulatedBackend {
+    pub fn new() -> SimulatedBackend {
+        SimulatedBackend {
+            state: Arc::new(Mutex::new(SimulatedState {
+                version: (9, NORDIC_COMPANY_ID, 0x00b6),
+                advertising_reports: Vec::new(),
+                advertising_interval: Duration::from_millis(10),
+                script: Vec::new(),
+                deliveries: None,
+                generation: 0,
+                enabled: false,
+                scanning: false,
+                scan_paused: false,
+                next_report: 0,
+                configs: Vec::new(),
+            })),
+        }
+    }
+
+    /// Version reported by `sd_ble_version_get`, S140 v6.1 by default.
+    pub fn version(self, version_number: u8, company_id: u16, subversion_number: u16) -> Self {
+        self.state.lock().unwrap().version = (version_number, company_id, subversion_number);
+        self
+    }
+
+    /// Reports another version from now on, as if new firmware had been
+    /// flashed.
+    pub fn set_version(&self, version_number: u8, company_id: u16, subversion_number: u16) {
+        self.state.lock().unwrap().version = (version_number, company_id, subversion_number);
+    }
+
+    /// Adds a report to the advertisers seen while scanning.
+    pub fn advertising_report(self, report: GapAdvertisementReport) -> Self {
+        self.state.lock().unwrap().advertising_reports.push(report);
+        self
+    }
+
+    /// Delay between resuming scanning and the next advertising report.
+    pub fn advertising_interval(self, interval: Duration) -> Self {
+        self.state.lock().unwrap().advertising_interval = interval;
+        self
+    }
+
+    /// Emits `event` `delay` after the previous scripted event, starting
+    /// when the backend is opened.
+    pub fn event_after(self, delay: Duration, event: Event) -> Self {
+        self.state.lock().unwrap().script.push((delay, event));
+        self
+    }
+
+    /// Emits `event` now. Ignored while the backend is closed.
+    pub fn emit(&self, event: Event) {
+        self.state.lock().unwrap().deliver(Duration::ZERO, Delivery::Event(event));
+    }
+
+    /// Reports a transport status change, such as a lost link.
+    pub fn emit_status(&self, status: RpcStatus, message: &str) {
+        let delivery = Delivery::Status(status, String::from(message));
+        self.state.lock().unwrap().deliver(Duration::ZERO, delivery);
+    }
+
+    /// Configurations set since the backend was last opened.
+    pub fn configs(&self) -> Vec<Config> {
+        self.state.lock().unwrap().configs.clone()
+    }
+
+    pub fn is_open(&self) -> bool {
+        self.state.lock().unwrap().deliveries.is_some()
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.state.lock().unwrap().enabled
+    }
+
+    pub fn is_scanning(&self) -> bool {
+        self.state.lock().unwrap().scanning
+    }
+}
+
+impl Default for SimulatedBackend {
+    fn default() -> Self {
+        SimulatedBackend::new()
+    }
+}
+
+impl SimulatedState {
+    fn deliver(&self, delay: Duration, delivery: Delivery) {
+        if let Some(deliveries) = &self.deliveries {
+            let _result = deliveries.send((delay, self.generation, delivery));
+        }
+    }
+
+    fn schedule_advertising_report(&mut self) {
+        if self.advertising_reports.is_empty() {
+            return;
+        }
+        let report = self.advertising_reports[self.next_report % self.advertising_reports.len()].clone();
+        self.next_report += 1;
+        self.deliver(self.advertising_interval, Delivery::AdvertisingReport(report));
+    }
+
+    fn check_open(&self, operation: &'static str) -> Result<()> {
+        if self.deliveries.is_some() {
+            Ok(())
+        } else {
+            Err(Error::nrf(operation, NRF_ERROR_INVALID_STATE))
+        }
+    }
+}
+
+impl BleBackend for SimulatedBackend {
+    fn open(&self, events: EventSink) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        if state.deliveries.is_some() {
+            return Err(Error::nrf("sd_rpc_open", NRF_ERROR_INVALID_STATE));
+        }
+
+        // Opening resets the connectivity firmware.
+        let (sender, receiver) = mpsc::channel();
+        state.deliveries = Some(sender);
+        state.generation += 1;
+        state.enabled = false;
+        state.scanning = false;
+        state.configs.clear();
+        for (delay, event) in state.script.clone() {
+            state.deliver(delay, Delivery::Event(event));
+        }
+
+        // Only a weak reference, so the thread ends with the backend.
+        let deliver_state = Arc::downgrade(&self.state);
+        thread::spawn(move || deliver(deliver_state, receiver, events));
+        Ok(())
+    }
+
+    fn close(&self) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        state.check_open("sd_rpc_close")?;
+        state.deliveries = None;
+        state.scanning = false;
+        Ok(())
+    }
+
+    fn set_log_severity_filter(&self, _severity: LogSeverity) -> Result<()> {
+        Ok(())
+    }
+
+    fn conn_reset(&self, _mode: ResetMode) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        state.check_open("sd_rpc_conn_reset")?;
+
+        // Nothing queued before the reset is delivered afterwards.
+        state.generation += 1;
+        state.enabled = false;
+        state.scanning = false;
+        state.configs.clear();
+        let delivery = Delivery::Status(RpcStatus::ResetPerformed, String::from("Reset performed"));
+        state.deliver(RESET_DURATION, delivery);
+        Ok(())
+    }
+
+    fn version_get(&self) -> Result<(u8, u16, u16)> {
+        let state = self.state.lock().unwrap();
+        state.check_open("sd_ble_version_get")?;
+        Ok(state.version)
+    }
+
+    fn cfg_set(&self, config: &Config) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        state.check_open("sd_ble_cfg_set")?;
+        if state.enabled {
+            return Err(Error::nrf("sd_ble_cfg_set", NRF_ERROR_INVALID_STATE));
+        }
+        state.configs.push(config.clone());
+        Ok(())
+    }
+
+    fn ble_enable(&self) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        state.check_open("sd_ble_enable")?;
+        if state.enabled {
+            return Err(Error::nrf("sd_ble_enable", NRF_ERROR_INVALID_STATE));
+        }
+        state.enabled = true;
+        Ok(())
+    }
+
+    fn gap_scan_start(&self, parameters: Option<&GapScanParameters>) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        state.check_open("sd_ble_gap_scan_start")?;
+        if !state.enabled {
+            return Err(Error::nrf("sd_ble_gap_scan_start", BLE_ERROR_NOT_ENABLED));
+        }
+
+        match parameters {
+            Some(_) if state.scanning => {
+                return Err(Error::nrf("sd_ble_gap_scan_start", NRF_ERROR_INVALID_STATE))
+            }
+            None if !state.scanning || !state.scan_paused => {
+                return Err(Error::nrf("sd_ble_gap_scan_start", NRF_ERROR_INVALID_STATE))
+            }
+            _ => {}
+        }
+
+        state.scanning = true;
+        state.scan_paused = false;
+        state.schedule_advertising_report();
+        Ok(())
+    }
+
+    fn gap_scan_stop(&self) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        state.check_open("sd_ble_gap_scan_stop")?;
+        if !state.scanning {
+            return Err(Error::nrf("sd_ble_gap_scan_stop", NRF_ERROR_INVALID_STATE));
+        }
+        state.scanning = false;
+        Ok(())
+    }
+
+    fn user_mem_reply(&self, _connection_handle: u16, _length: Option<u16>) -> Result<()> {
+        let state = self.state.lock().unwrap();
+        state.check_open("sd_ble_user_mem_reply")?;
+        if !state.enabled {
+            return Err(Error::nrf("sd_ble_user_mem_reply", BLE_ERROR_NOT_ENABLED));
+        }
+        Ok(())
+    }
+}
+
+fn deliver(
+    state: Weak<Mutex<SimulatedState>>,
+    deliveries: Receiver<(Duration, u64, Delivery)>,
+    events: EventSink,
+) {
+    for (delay, generation, delivery) in deliveries {
+        thread::sleep(delay);
+
+        {
+            let state = match state.upgrade() {
+                Some(state) => state,
+                None => break,
+            };
+            let mut state = state.lock().unwrap();
+            if state.generation != generation || state.deliveries.is_none() {
+                continue;
+            }
+            if let Delivery::Event(Event::GapTimeout { source: BLE_GAP_TIMEOUT_SRC_SCAN, .. }) = delivery {
+                state.scanning = false;
+            }
+            if let Delivery::AdvertisingReport(_) = delivery {
+                if !state.scanning {
+                    continue;
+                }
+                state.scan_paused = true;
+            }
+        }
+
+        match delivery {
+            Delivery::Event(event) => {
+                events.traffic(&event);
+                events.event(event.into());
+            }
+            Delivery::AdvertisingReport(report) => events.event(
+                Event::GapAdvertisingReport {
+                    connection_handle: BLE_CONN_HANDLE_INVALID,
+                    report,
+                }
+                .into(),
+            ),
+            Delivery::Status(status, message) => events.status(status, message),
+        }
+    }
+}
@@ -0,0 +1,190 @@
+use crate::{event_bus::{EventBus, EventCategory}, event_queue::EventQueueStats, gap::GapEvent, rpc::RpcStatus, sd_api_v6::EventType, Result};
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Calls of one SoftDevice function.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct CommandStats {
+    pub calls: u64,
+    /// Calls that returned an error.
+    pub failures: u64,
+}
+
+/// Health counters of an adapter, counted since the driver was created or
+/// the counters were last reset.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct DriverStats {
+    /// Events received from the adapter per category.
+    pub events: HashMap<EventCategory, u64>,
+    pub advertising_reports: u64,
+    /// Advertising reports received per second, averaged over the time
+    /// since the counters were reset.
+    pub advertising_reports_per_second: f64,
+    /// Calls and failures per SoftDevice function, e.g. `sd_ble_gap_scan_start`.
+    pub commands: HashMap<&'static str, CommandStats>,
+    /// Drop counters and high-water mark of the event queues.
+    pub event_queue: EventQueueStats,
+    /// RPC status reports indicating a transport failure.
+    pub rpc_status_errors: u64,
+    /// Time since the last event was received, `None` if there was none.
+    pub since_last_event: Option<Duration>,
+}
+
+#[derive(Debug)]
+struct Counters {
+    since: Instant,
+    events: HashMap<EventCategory, u64>,
+    advertising_reports: u64,
+    commands: HashMap<&'static str, CommandStats>,
+    rpc_status_errors: u64,
+    last_event: Option<Instant>,
+}
+
+impl Counters {
+    fn new() -> Counters {
+        Counters {
+            since: Instant::now(),
+            events: HashMap::new(),
+            advertising_reports: 0,
+            commands: HashMap::new(),
+            rpc_status_errors: 0,
+            last_event: None,
+        }
+    }
+}
+
+/// Counters updated by the driver core, shared with `BleDriverHandle`.
+#[derive(Debug)]
+pub(crate) struct StatsCollector {
+    counters: Mutex<Counters>,
+}
+
+impl StatsCollector {
+    pub(crate) fn new() -> StatsCollector {
+        StatsCollector {
+            counters: Mutex::new(Counters::new()),
+        }
+    }
+
+    pub(crate) fn record_event(&self, event: &EventType) {
+        let mut counters = self.counters.lock().unwrap();
+        *counters.events.entry(event.category()).or_default() += 1;
+        if let EventType::BleGap(_, GapEvent::AdvertisingReport(_)) = event {
+            counters.advertising_reports += 1;
+        }
+        counters.last_event = Some(Instant::now());
+    }
+
+    pub(crate) fn record_status(&self, status: RpcStatus) {
+        if status.is_error() {
+            self.counters.lock().unwrap().rpc_status_errors += 1;
+        }
+    }
+
+    pub(crate) fn record_command<T>(&self, name: &'static str, result: &Result<T>) {
+        let mut counters = self.counters.lock().unwrap();
+        let command = counters.commands.entry(name).or_default();
+        command.calls += 1;
+        if result.is_err() {
+            command.failures += 1;
+        }
+    }
+
+    pub(crate) fn snapshot(&self, events: &EventBus) -> DriverStats {
+        let counters = self.counters.lock().unwrap();
+        let elapsed = counters.since.elapsed().as_secs_f64();
+        let advertising_reports_per_second = if elapsed > 0.0 {
+            counters.advertising_reports as f64 / elapsed
+        } else {
+            0.0
+        };
+
+        DriverStats {
+            events: counters.events.clone(),
+            advertising_reports: counters.advertising_reports,
+            advertising_reports_per_second,
+            commands: counters.commands.clone(),
+            event_queue: events.stats(),
+            rpc_status_errors: counters.rpc_status_errors,
+            since_last_event: counters.last_event.map(|last_event| last_event.elapsed()),
+        }
+    }
+
+    pub(crate) fn reset(&self, events: &EventBus) {
+        *self.counters.lock().unwrap() = Counters::new();
+        events.reset_stats();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gap::GapScanParameters;
+    use crate::simulated::SimulatedBackend;
+    use crate::test_support::simulated_driver;
+    use crate::Error;
+
+    #[test]
+    fn counts_commands_and_failures() {
+        let (_sender, events) = EventBus::new();
+        let stats = StatsCollector::new();
+        stats.record_command("sd_ble_enable", &Ok(()));
+        stats.record_command("sd_ble_gap_scan_start", &Ok(()));
+        stats.record_command::<()>("sd_ble_gap_scan_start", &Err(Error::DriverClosed));
+
+        let snapshot = stats.snapshot(&events);
+        assert_eq!(snapshot.commands["sd_ble_enable"], CommandStats { calls: 1, failures: 0 });
+        assert_eq!(snapshot.commands["sd_ble_gap_scan_start"], CommandStats { calls: 2, failures: 1 });
+        assert_eq!(snapshot.commands.len(), 2);
+    }
+
+    #[test]
+    fn counts_events_per_category() {
+        let (_sender, events) = EventBus::new();
+        let stats = StatsCollector::new();
+        assert_eq!(stats.snapshot(&events).since_last_event, None);
+
+        stats.record_event(&EventType::BleGap(0, GapEvent::Connect));
+        stats.record_event(&EventType::BleGattClient(0, 0x30));
+        stats.record_event(&EventType::BleGattClient(0, 0x31));
+        stats.record_event(&EventType::Reconnected);
+        stats.record_status(RpcStatus::ResetPerformed);
+        stats.record_status(RpcStatus::IoResourcesUnavailable);
+
+        let snapshot = stats.snapshot(&events);
+        assert_eq!(snapshot.events[&EventCategory::Gap], 1);
+        assert_eq!(snapshot.events[&EventCategory::GattClient], 2);
+        assert_eq!(snapshot.events[&EventCategory::Driver], 1);
+        assert!(!snapshot.events.contains_key(&EventCategory::GattServer));
+        assert_eq!(snapshot.advertising_reports, 0);
+        assert_eq!(snapshot.rpc_status_errors, 1);
+        assert!(snapshot.since_last_event.is_some());
+
+        stats.reset(&events);
+        let snapshot = stats.snapshot(&events);
+        assert!(snapshot.events.is_empty());
+        assert_eq!(snapshot.rpc_status_errors, 0);
+        assert_eq!(snapshot.since_last_event, None);
+    }
+
+    #[test]
+    fn driver_counts_failed_calls() {
+        let (_backend, driver) = simulated_driver(SimulatedBackend::new());
+        let driver = driver.open_adapter().unwrap();
+        let mut driver = driver.ble_enable().unwrap();
+
+        // The SoftDevice refuses to stop scanning before it has started.
+        assert!(driver.gap_scan_stop().is_err());
+        driver.gap_scan_start(&GapScanParameters::default()).unwrap();
+        driver.gap_scan_stop().unwrap();
+
+        let stats = driver.stats();
+        assert_eq!(stats.commands["sd_ble_enable"], CommandStats { calls: 1, failures: 0 });
+        assert_eq!(stats.commands["sd_ble_gap_scan_stop"], CommandStats { calls: 2, failures: 1 });
+        assert_eq!(stats.commands["sd_ble_gap_scan_start"].failures, 0);
+
+        driver.reset_stats();
+        assert!(driver.stats().commands.is_empty());
+    }
+}
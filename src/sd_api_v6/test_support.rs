@@ -0,0 +1,74 @@
+//! Fixtures shared by the tests of the driver modules.
+
+use crate::{builder::BleDriverBuilder, gap::{GapAddress, GapAddressType, GapAdvertisementReport, GapAdvertisementReportType, GapPhy, GapSetId, TxPowerLevel}, sd_api_v6::BleDriver, simulated::SimulatedBackend};
+use bytes::Bytes;
+#[cfg(target_os = "linux")]
+use std::fs::File;
+#[cfg(target_os = "linux")]
+use std::os::unix::io::FromRawFd;
+use std::sync::Arc;
+
+/// A connectable, scannable legacy advertisement on channel 37 from a
+/// random static address, carrying the advertising data `data`.
+pub(crate) fn advertising_report(data: &'static [u8]) -> GapAdvertisementReport {
+    let address = GapAddress {
+        address_id_peer: false,
+        address_type: GapAddressType::RandomStatic,
+        address: [0x01, 0x02, 0x03, 0x04, 0x05, 0xc6],
+    };
+
+    GapAdvertisementReport {
+        report_type: GapAdvertisementReportType {
+            connectable: true,
+            scannable: true,
+            ..GapAdvertisementReportType::default()
+        },
+        peer_address: address,
+        direct_address: address,
+        primary_phy: GapPhy::OneMbps,
+        secondary_phy: GapPhy::NotConfigured,
+        tx_power: TxPowerLevel::Invalid,
+        rssi: -60,
+        channel_index: 37,
+        set_id: GapSetId::NotAvailable,
+        data: Bytes::from_static(data),
+    }
+}
+
+/// A builder driving `backend`, which is handed back for the test to
+/// inspect and script the simulated firmware.
+pub(crate) fn simulated_builder(backend: SimulatedBackend) -> (Arc<SimulatedBackend>, BleDriverBuilder) {
+    let backend = Arc::new(backend);
+    let builder = BleDriverBuilder::new("simulated").backend(backend.clone());
+    (backend, builder)
+}
+
+/// A closed driver on `backend` with the default settings.
+pub(crate) fn simulated_driver(backend: SimulatedBackend) -> (Arc<SimulatedBackend>, BleDriver) {
+    let (backend, builder) = simulated_builder(backend);
+    (backend, builder.build().unwrap())
+}
+
+/// Opens a pseudo-terminal pair in raw mode and returns both ends.
+#[cfg(target_os = "linux")]
+pub(crate) fn pty_pair() -> (File, File) {
+    unsafe {
+        let mut master = 0;
+        let mut slave = 0;
+        let result = libc::openpty(
+            &mut master,
+            &mut slave,
+            std::ptr::null_mut(),
+            std::ptr::null(),
+            std::ptr::null(),
+        );
+        assert_eq!(result, 0);
+
+        let mut termios = std::mem::zeroed();
+        libc::tcgetattr(slave, &mut termios);
+        libc::cfmakeraw(&mut termios);
+        libc::tcsetattr(slave, libc::TCSANOW, &termios);
+
+        (File::from_raw_fd(master), File::from_raw_fd(slave))
+    }
+}
@@ -0,0 +1,97 @@
+//! `tracing` instrumentation of SoftDevice commands and events, compiled
+//! to plain calls unless the `tracing` feature is enabled.
+//!
+//! Every command runs in a `command` span carrying its SoftDevice function
+//! name, connection handle, parameters and the resulting NRF error code,
+//! so subscribers can correlate a failure with the RPC log and events
+//! around it. Events are logged with their decoded type under the
+//! `nrf_sd_api::event` target.
+
+use crate::{rpc::RpcStatus, sd_api_v6::EventType, Result};
+use std::fmt::Debug;
+
+#[cfg(feature = "tracing")]
+use crate::{gap::GapEvent, Error};
+#[cfg(feature = "tracing")]
+use crate::codec::response::NRF_SUCCESS;
+#[cfg(feature = "tracing")]
+use tracing::field;
+
+/// Runs `command` inside a span describing the SoftDevice call `name`.
+#[cfg(feature = "tracing")]
+pub(crate) fn command<T>(
+    name: &'static str,
+    connection_handle: Option<u16>,
+    parameters: &dyn Debug,
+    command: impl FnOnce() -> Result<T>,
+) -> Result<T> {
+    let span = tracing::debug_span!(
+        target: "nrf_sd_api::command",
+        "command",
+        command = name,
+        connection_handle = field::Empty,
+        parameters = ?parameters,
+        nrf_error = field::Empty,
+    );
+    if let Some(connection_handle) = connection_handle {
+        span.record("connection_handle", connection_handle);
+    }
+    let _entered = span.enter();
+
+    let result = command();
+    match &result {
+        Ok(_) => {
+            span.record("nrf_error", NRF_SUCCESS);
+            tracing::trace!(target: "nrf_sd_api::command", "{} succeeded", name);
+        }
+        Err(Error::Nrf { error, .. }) => {
+            span.record("nrf_error", error.code());
+            tracing::warn!(target: "nrf_sd_api::command", "{} failed with {}", name, error);
+        }
+        Err(error) => {
+            tracing::warn!(target: "nrf_sd_api::command", "{} failed: {}", name, error);
+        }
+    }
+    result
+}
+
+#[cfg(not(feature = "tracing"))]
+#[allow(unused_variables)]
+pub(crate) fn command<T>(
+    name: &'static str,
+    connection_handle: Option<u16>,
+    parameters: &dyn Debug,
+    command: impl FnOnce() -> Result<T>,
+) -> Result<T> {
+    command()
+}
+
+/// Logs an event received from the adapter. Advertising reports arrive
+/// many times a second and are only logged at trace level.
+#[allow(unused_variables)]
+pub(crate) fn event(event: &EventType) {
+    #[cfg(feature = "tracing")]
+    match event {
+        EventType::BleGap(_, GapEvent::AdvertisingReport(_)) => tracing::trace!(
+            target: "nrf_sd_api::event",
+            category = ?event.category(),
+            connection_handle = ?event.connection_handle(),
+            "{:?}",
+            event
+        ),
+        _ => tracing::debug!(
+            target: "nrf_sd_api::event",
+            category = ?event.category(),
+            connection_handle = ?event.connection_handle(),
+            "{:?}",
+            event
+        ),
+    }
+}
+
+/// Logs a transport status change reported by the backend.
+#[allow(unused_variables)]
+pub(crate) fn status(status: RpcStatus, message: &str) {
+    #[cfg(feature = "tracing")]
+    tracing::info!(target: "nrf_sd_api::event", status = ?status, "{}", message);
+}
@@ -0,0 +1,52 @@
+//! SoftDevice API versions.
+//!
+//! `BleDriver` detects the API version from the firmware ID reported when
+//! the adapter is opened. The driver is built on the API v6 bindings of
+//! nrf-ble-driver-sys, whose structures differ from those of older
+//! SoftDevices, so firmware of any other version is refused with
+//! `Error::IncompatibleFirmware`.
+
+use std::fmt;
+
+/// A SoftDevice API version, shared by the SoftDevice releases
+/// implementing it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum SdApiVersion {
+    /// S132 and S140 v6.x
+    V6,
+}
+
+impl SdApiVersion {
+    /// The API version of the SoftDevice with firmware ID `firmware_id`,
+    /// the Link Layer subversion number reported by `sd_ble_version_get`.
+    /// `None` for SoftDevices implementing another version.
+    pub fn from_firmware_id(firmware_id: u16) -> Option<SdApiVersion> {
+        match firmware_id {
+            0x00a8 | 0x00a9 | 0x00ae | 0x00af | 0x00b6 | 0x00b7 => Some(SdApiVersion::V6),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for SdApiVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            SdApiVersion::V6 => "v6",
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn firmware_ids() {
+        assert_eq!(SdApiVersion::from_firmware_id(0x00a8), Some(SdApiVersion::V6));
+        assert_eq!(SdApiVersion::from_firmware_id(0x00b6), Some(SdApiVersion::V6));
+        // S132 v3.1.0 and v5.0.0
+        assert_eq!(SdApiVersion::from_firmware_id(0x0091), None);
+        assert_eq!(SdApiVersion::from_firmware_id(0x009d), None);
+        assert_eq!(SdApiVersion::from_firmware_id(0x0098), None);
+    }
+}